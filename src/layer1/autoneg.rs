@@ -0,0 +1,252 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Speed {
+    Ten,
+    Hundred,
+    Thousand,
+    TenThousand,
+}
+
+impl Speed {
+    pub fn bits_per_second(&self) -> u64 {
+        match self {
+            Speed::Ten => 10_000_000,
+            Speed::Hundred => 100_000_000,
+            Speed::Thousand => 1_000_000_000,
+            Speed::TenThousand => 10_000_000_000,
+        }
+    }
+
+    // Value of the "speed" command and of the Speed column of "show interfaces status"
+    pub fn megabits(&self) -> u32 {
+        (self.bits_per_second() / 1_000_000) as u32
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::Ten => write!(f, "10Mb/s"),
+            Speed::Hundred => write!(f, "100Mb/s"),
+            Speed::Thousand => write!(f, "1000Mb/s"),
+            Speed::TenThousand => write!(f, "10Gb/s"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Duplex {
+    Half,
+    Full,
+}
+
+impl fmt::Display for Duplex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Duplex::Half => write!(f, "Half-duplex"),
+            Duplex::Full => write!(f, "Full-duplex"),
+        }
+    }
+}
+
+// "speed auto" or "speed 10|100|1000"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedSetting {
+    Auto,
+    Forced(Speed),
+}
+
+// "duplex auto" or "duplex half|full"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplexSetting {
+    Auto,
+    Forced(Duplex),
+}
+
+/// Speed and duplex an interface actually runs at once its link is up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkMode {
+    pub speed: Speed,
+    pub duplex: Duplex,
+    // Whether the mode was autonegotiated rather than configured
+    pub negotiated: bool,
+}
+
+impl fmt::Display for LinkMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {}", self.duplex, self.speed)
+    }
+}
+
+/// What one end of a link brings to the negotiation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortAbility {
+    // Speeds the hardware supports
+    pub speeds: Vec<Speed>,
+    pub speed: SpeedSetting,
+    pub duplex: DuplexSetting,
+}
+
+impl PortAbility {
+    // Forcing the speed turns autonegotiation off, as on Cisco switches
+    pub fn autonegotiates(&self) -> bool {
+        self.speed == SpeedSetting::Auto
+    }
+
+    // Mode of a port attached to a hub, which does not negotiate: the configured speed or
+    // else the fastest one, always at half duplex since the medium is shared
+    pub fn repeater_mode(&self) -> Option<LinkMode> {
+        let speed = match self.speed {
+            SpeedSetting::Auto => self.speeds.iter().max().copied()?,
+            SpeedSetting::Forced(speed) => speed,
+        };
+        Some(LinkMode {
+            speed,
+            duplex: Duplex::Half,
+            negotiated: false,
+        })
+    }
+
+    fn duplexes(&self) -> Vec<Duplex> {
+        match self.duplex {
+            DuplexSetting::Auto => vec![Duplex::Full, Duplex::Half],
+            DuplexSetting::Forced(duplex) => vec![duplex],
+        }
+    }
+
+    // Mode of a port that does not negotiate. Without autonegotiation it cannot learn
+    // anything about the other end, and an automatic duplex falls back to half.
+    fn forced_mode(&self, speed: Speed) -> LinkMode {
+        let duplex = match self.duplex {
+            DuplexSetting::Auto => Duplex::Half,
+            DuplexSetting::Forced(duplex) => duplex,
+        };
+        LinkMode {
+            speed,
+            duplex,
+            negotiated: false,
+        }
+    }
+
+    // Mode picked by a negotiating port facing a port that does not negotiate: parallel
+    // detection recognizes the speed from the line signal but not the duplex, so it
+    // assumes half duplex unless configured otherwise
+    fn parallel_detected_mode(&self, speed: Speed) -> Result<LinkMode, NegotiationError> {
+        if !self.speeds.contains(&speed) {
+            return Err(NegotiationError::NoCommonSpeed);
+        }
+        let duplex = match self.duplex {
+            DuplexSetting::Auto => Duplex::Half,
+            DuplexSetting::Forced(duplex) => duplex,
+        };
+        Ok(LinkMode {
+            speed,
+            duplex,
+            negotiated: true,
+        })
+    }
+}
+
+/// Why a link cannot come up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegotiationError {
+    NoCommonSpeed,
+    // Both ends negotiate but are forced to different duplex settings
+    NoCommonDuplex,
+}
+
+impl fmt::Display for NegotiationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NegotiationError::NoCommonSpeed => write!(f, "no common speed"),
+            NegotiationError::NoCommonDuplex => write!(f, "no common duplex"),
+        }
+    }
+}
+
+impl std::error::Error for NegotiationError {}
+
+/// Runs the autonegotiation exchange between the two ends of a link (IEEE 802.3 clause 28).
+/// Two negotiating ports settle on the best mode both advertise. Returns the mode of each
+/// end, or why the link cannot come up. Nothing stops the ends from ending up with
+/// different duplex settings when only one of them negotiates.
+pub fn negotiate(
+    a: &PortAbility,
+    b: &PortAbility,
+) -> Result<(LinkMode, LinkMode), NegotiationError> {
+    match (a.speed, b.speed) {
+        (SpeedSetting::Auto, SpeedSetting::Auto) => {
+            let speed = a
+                .speeds
+                .iter()
+                .filter(|speed| b.speeds.contains(speed))
+                .max()
+                .copied()
+                .ok_or(NegotiationError::NoCommonSpeed)?;
+            let duplex = a
+                .duplexes()
+                .into_iter()
+                .find(|duplex| b.duplexes().contains(duplex))
+                .ok_or(NegotiationError::NoCommonDuplex)?;
+            let mode = LinkMode {
+                speed,
+                duplex,
+                negotiated: true,
+            };
+            Ok((mode, mode))
+        }
+        (SpeedSetting::Auto, SpeedSetting::Forced(speed)) => {
+            Ok((a.parallel_detected_mode(speed)?, b.forced_mode(speed)))
+        }
+        (SpeedSetting::Forced(speed), SpeedSetting::Auto) => {
+            Ok((a.forced_mode(speed), b.parallel_detected_mode(speed)?))
+        }
+        (SpeedSetting::Forced(speed_a), SpeedSetting::Forced(speed_b)) => {
+            // Different forced speeds never establish a link
+            let supported = a.speeds.contains(&speed_a) && b.speeds.contains(&speed_b);
+            if speed_a != speed_b || !supported {
+                return Err(NegotiationError::NoCommonSpeed);
+            }
+            Ok((a.forced_mode(speed_a), b.forced_mode(speed_b)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ability(speed: SpeedSetting, duplex: DuplexSetting) -> PortAbility {
+        PortAbility {
+            speeds: vec![Speed::Ten, Speed::Hundred, Speed::Thousand],
+            speed,
+            duplex,
+        }
+    }
+
+    #[test]
+    fn negotiates_best_common_mode() {
+        let a = ability(SpeedSetting::Auto, DuplexSetting::Auto);
+        let mut b = ability(SpeedSetting::Auto, DuplexSetting::Auto);
+        b.speeds = vec![Speed::Ten, Speed::Hundred];
+        let (mode_a, mode_b) = negotiate(&a, &b).unwrap();
+        assert_eq!(mode_a, mode_b);
+        assert_eq!(mode_a.speed, Speed::Hundred);
+        assert_eq!(mode_a.duplex, Duplex::Full);
+    }
+
+    #[test]
+    fn reports_no_common_speed() {
+        let a = ability(SpeedSetting::Forced(Speed::Hundred), DuplexSetting::Auto);
+        let b = ability(SpeedSetting::Forced(Speed::Ten), DuplexSetting::Auto);
+        assert_eq!(negotiate(&a, &b), Err(NegotiationError::NoCommonSpeed));
+    }
+
+    #[test]
+    fn reports_no_common_duplex() {
+        let a = ability(SpeedSetting::Auto, DuplexSetting::Forced(Duplex::Full));
+        let b = ability(SpeedSetting::Auto, DuplexSetting::Forced(Duplex::Half));
+        assert_eq!(negotiate(&a, &b), Err(NegotiationError::NoCommonDuplex));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::layer2::{interface::Direction, pdu::EthernetFrame};
+use bevy::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+// LINKTYPE_ETHERNET / DLT_EN10MB
+const LINKTYPE_ETHERNET: u32 = 1;
+// Every captured frame carries a 4 byte FCS
+const FCS_LENGTH: u8 = 4;
+const SNAPLEN: u32 = 65535;
+
+/// Records every frame crossing the entity it is attached to.
+/// Attach it to an `Interface` entity to capture what it sends and receives,
+/// or to a `Link` or `Hub` entity to capture everything transmitted on that medium.
+#[derive(Component, Debug, Default)]
+pub struct Capture {
+    pub frames: Vec<CapturedFrame>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    // Simulation time at which the frame was seen
+    pub timestamp: Duration,
+    // Only known for interface captures
+    pub direction: Option<Direction>,
+    // Frame bytes as they appear on the wire, FCS included
+    pub data: Vec<u8>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, timestamp: Duration, direction: Option<Direction>, frame: &EthernetFrame) {
+        self.frames.push(CapturedFrame {
+            timestamp,
+            direction,
+            data: frame.to_wire(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Writes the capture in the classic libpcap format with nanosecond timestamps
+    pub fn write_pcap<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        // Global header. The link type also announces the FCS length (in 16-bit words)
+        // so that readers do not mistake the FCS for payload.
+        writer.write_all(&0xA1B2_3C4Du32.to_le_bytes())?; // Nanosecond resolution magic
+        writer.write_all(&2u16.to_le_bytes())?; // Major version
+        writer.write_all(&4u16.to_le_bytes())?; // Minor version
+        writer.write_all(&0i32.to_le_bytes())?; // GMT offset
+        writer.write_all(&0u32.to_le_bytes())?; // Timestamp accuracy
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        let fcs_words = u32::from(FCS_LENGTH / 2);
+        writer.write_all(&(LINKTYPE_ETHERNET | (fcs_words << 28) | 0x0400_0000).to_le_bytes())?;
+
+        for frame in &self.frames {
+            let length = frame.data.len() as u32;
+            writer.write_all(&(frame.timestamp.as_secs() as u32).to_le_bytes())?;
+            writer.write_all(&frame.timestamp.subsec_nanos().to_le_bytes())?;
+            writer.write_all(&length.to_le_bytes())?; // Captured length
+            writer.write_all(&length.to_le_bytes())?; // Original length
+            writer.write_all(&frame.data)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the capture in the pcapng format with a single Ethernet interface
+    pub fn write_pcapng<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        // Section Header Block
+        let mut body = Vec::new();
+        body.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes()); // Byte-order magic
+        body.extend_from_slice(&1u16.to_le_bytes()); // Major version
+        body.extend_from_slice(&0u16.to_le_bytes()); // Minor version
+        body.extend_from_slice(&(-1i64).to_le_bytes()); // Section length not specified
+        write_block(&mut writer, 0x0A0D_0D0A, &body)?;
+
+        // Interface Description Block
+        let mut body = Vec::new();
+        body.extend_from_slice(&(LINKTYPE_ETHERNET as u16).to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // Reserved
+        body.extend_from_slice(&SNAPLEN.to_le_bytes());
+        push_option(&mut body, 9, &[9]); // if_tsresol: 10^-9 seconds
+        push_option(&mut body, 13, &[FCS_LENGTH]); // if_fcslen
+        push_option(&mut body, 0, &[]); // opt_endofopt
+        write_block(&mut writer, 0x0000_0001, &body)?;
+
+        // One Enhanced Packet Block per frame
+        for frame in &self.frames {
+            let timestamp = frame.timestamp.as_nanos() as u64;
+            let length = frame.data.len() as u32;
+            let mut body = Vec::new();
+            body.extend_from_slice(&0u32.to_le_bytes()); // Interface ID
+            body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+            body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+            body.extend_from_slice(&length.to_le_bytes()); // Captured length
+            body.extend_from_slice(&length.to_le_bytes()); // Original length
+            body.extend_from_slice(&frame.data);
+            pad_to_32_bits(&mut body);
+            if let Some(direction) = &frame.direction {
+                // epb_flags: the lowest two bits hold the direction (1 inbound, 2 outbound)
+                let flags: u32 = match direction {
+                    Direction::In => 1,
+                    Direction::Out => 2,
+                };
+                push_option(&mut body, 2, &flags.to_le_bytes());
+                push_option(&mut body, 0, &[]);
+            }
+            write_block(&mut writer, 0x0000_0006, &body)?;
+        }
+        writer.flush()
+    }
+
+    pub fn save_pcap<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_pcap(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn save_pcapng<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_pcapng(BufWriter::new(File::create(path)?))
+    }
+}
+
+// Writes a pcapng block: type, total length, body and the total length again
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_length = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total_length.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad_to_32_bits(body);
+}
+
+fn pad_to_32_bits(bytes: &mut Vec<u8>) {
+    bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer2::address::MacAddress;
+    use crate::layer3::address::Ipv4Addr;
+
+    #[test]
+    fn captured_frames_parse_back() {
+        let frame = EthernetFrame::arp_request(
+            MacAddress::random(),
+            Ipv4Addr::new("192.168.1.10").unwrap(),
+            Ipv4Addr::new("192.168.1.1").unwrap(),
+        );
+        let mut capture = Capture::new();
+        capture.record(Duration::ZERO, Some(Direction::Out), &frame);
+        let data = &capture.frames[0].data;
+        assert_eq!(*data, frame.to_wire());
+        assert!(EthernetFrame::from_wire(data).unwrap().has_valid_fcs());
+    }
+}
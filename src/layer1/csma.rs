@@ -0,0 +1,97 @@
+use super::transmission_time;
+use crate::layer2::pdu::EthernetFrame;
+use rand::Rng;
+use std::time::Duration;
+
+// Collision window: a collision detected after this many bytes is a late collision
+pub const SLOT_TIME_BYTES: usize = 64;
+// Jam signal sent after detecting a collision, so that every station notices it
+pub const JAM_SIZE_BYTES: usize = 4;
+// Transmissions of a frame before giving up on it
+pub const ATTEMPT_LIMIT: u32 = 16;
+// Collisions after which the backoff range stops doubling
+pub const BACKOFF_LIMIT: u32 = 10;
+
+/// What becomes of a frame after a collision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionOutcome {
+    // Sent again once the backoff ends
+    Retry { backoff_until: Duration },
+    // Dropped: the collision came too late for the MAC to retry
+    LateCollision,
+    // Dropped after too many attempts
+    ExcessiveCollisions,
+}
+
+/// Transmit state of an interface on a shared half-duplex medium (IEEE 802.3 CSMA/CD).
+/// The frame being sent stays here until it gets through or is given up.
+#[derive(Debug, Clone, Default)]
+pub struct CsmaCd {
+    pub frame: Option<EthernetFrame>,
+    // Collisions suffered by the current frame
+    pub attempts: u32,
+    // The interface does not sense the medium again before then
+    pub backoff_until: Duration,
+    // When the frame started going out on a half-duplex link, while it is being sent
+    pub sending_since: Option<Duration>,
+}
+
+impl CsmaCd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn slot_time(bandwidth: u64) -> Duration {
+        transmission_time(SLOT_TIME_BYTES, bandwidth)
+    }
+
+    pub fn jam_time(bandwidth: u64) -> Duration {
+        transmission_time(JAM_SIZE_BYTES, bandwidth)
+    }
+
+    // Whether a collision detected `elapsed` into the transmission is a late one
+    pub fn is_late(elapsed: Duration, bandwidth: u64) -> bool {
+        elapsed > Self::slot_time(bandwidth)
+    }
+
+    pub fn is_backing_off(&self, now: Duration) -> bool {
+        now < self.backoff_until
+    }
+
+    /// Records a collision of `frame`, sent from `started` and jammed until `jam_end`.
+    /// Within the collision window the frame is kept and retried after a truncated binary
+    /// exponential backoff: a random number of slot times in [0, 2^min(n, 10)) after the
+    /// n-th collision.
+    pub fn collision<R: Rng>(
+        &mut self,
+        frame: EthernetFrame,
+        started: Duration,
+        detected: Duration,
+        jam_end: Duration,
+        bandwidth: u64,
+        rng: &mut R,
+    ) -> CollisionOutcome {
+        self.sending_since = None;
+        if Self::is_late(detected.saturating_sub(started), bandwidth) {
+            self.attempts = 0;
+            return CollisionOutcome::LateCollision;
+        }
+        self.attempts += 1;
+        if self.attempts >= ATTEMPT_LIMIT {
+            self.attempts = 0;
+            return CollisionOutcome::ExcessiveCollisions;
+        }
+        let slots = rng.gen_range(0..1u32 << self.attempts.min(BACKOFF_LIMIT));
+        self.backoff_until = jam_end + Self::slot_time(bandwidth) * slots;
+        self.frame = Some(frame);
+        CollisionOutcome::Retry {
+            backoff_until: self.backoff_until,
+        }
+    }
+
+    // The frame got through
+    pub fn transmitted(&mut self) {
+        self.attempts = 0;
+        self.sending_since = None;
+    }
+}
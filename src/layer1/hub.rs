@@ -1,41 +1,227 @@
-use super::super::layer2::interface::{Direction, Interface};
+use super::capture::Capture;
+use super::csma::CsmaCd;
+use super::super::layer2::{
+    interface::{Direction, Interface},
+    pdu::EthernetFrame,
+};
+use super::transmission_time;
+use crate::simulation::entity::EventScheduler;
 use bevy::prelude::*;
+use std::time::Duration;
 
+// A frame on the shared medium of a hub
+#[derive(Debug, Clone)]
+struct Transmission {
+    port: Entity,
+    frame: EthernetFrame,
+    bandwidth: u64,
+    start: Duration,
+    // When the sender stops sending: after the last bit, or after the jam signal
+    end: Duration,
+    collided: bool,
+}
+
+/// A repeater hub: every port shares a single half-duplex collision domain (CSMA/CD).
+/// Ports only start transmitting when they hear no carrier; ports starting while another
+/// signal has not reached them yet collide, jam, and back off before retrying.
 #[derive(Component)]
 pub struct Hub {
     pub interfaces: Vec<Entity>,
+    // Time for a signal to travel between two ports, cables included
+    pub propagation_delay: Duration,
+    transmissions: Vec<Transmission>,
 }
 
 impl Hub {
     pub fn new(interfaces: Vec<Entity>) -> Self {
-        Hub { interfaces }
+        Hub {
+            interfaces,
+            propagation_delay: Duration::ZERO,
+            transmissions: Vec::new(),
+        }
     }
 
-    pub fn transmit_frame(&self, interfaces: &mut Query<&mut Interface>) {
+    // When the carrier heard by `port` ends, if the medium is busy at `now`
+    fn carrier_until(&self, port: Entity, now: Duration) -> Option<Duration> {
+        self.transmissions
+            .iter()
+            .filter(|t| t.port != port && t.start + self.propagation_delay <= now)
+            .map(|t| t.end + self.propagation_delay)
+            .filter(|idle| now < *idle)
+            .max()
+    }
+
+    pub fn transmit_frame(
+        &mut self,
+        hub: Entity,
+        now: Duration,
+        scheduler: &mut EventScheduler,
+        interfaces: &mut Query<&mut Interface>,
+        captures: &mut Query<&mut Capture>,
+    ) {
+        self.deliver_frames(hub, now, scheduler, interfaces, captures);
+
+        // Ports with a frame to send that hear an idle medium
+        let mut contenders: Vec<(Entity, EthernetFrame, u64)> = Vec::new();
         for interface in self.interfaces.iter() {
-            match interfaces.get_mut(*interface) {
-                Ok(mut eth_interface) => {
-                    if let Interface::Ethernet(eth) = &mut *eth_interface {
-                        if let Some(frame) = eth.dequeue_frame(Direction::Out) {
-                            for dest_interface in self.interfaces.iter() {
-                                if dest_interface != interface {
-                                    match interfaces.get_mut(*dest_interface) {
-                                        Ok(mut dest_eth_interface) => {
-                                            if let Interface::Ethernet(dest_eth) =
-                                                &mut *dest_eth_interface
-                                            {
-                                                dest_eth.enqueue_frame(frame.clone(), Direction::In);
-                                            }
-                                        }
-                                        Err(_) => println!("Destination interface not found."),
-                                    }
-                                }
-                            }
-                        }
-                    }
+            let Ok(mut eth_interface) = interfaces.get_mut(*interface) else {
+                println!("Source interface not found.");
+                continue;
+            };
+            let Interface::Ethernet(eth) = &mut *eth_interface else {
+                continue;
+            };
+            if !eth.is_up() {
+                continue;
+            }
+            let Some(frame) = eth.next_half_duplex_frame(now) else {
+                continue;
+            };
+            if let Some(idle) = self.carrier_until(*interface, now) {
+                // Carrier sense: wait for the medium to go quiet
+                eth.defer_frame(frame);
+                scheduler.schedule_wakeup(idle);
+                continue;
+            }
+            contenders.push((*interface, frame, eth.line_rate()));
+        }
+        if contenders.is_empty() {
+            return;
+        }
+
+        // Transmissions whose signal has not reached the other ports yet
+        let in_flight: Vec<usize> = self
+            .transmissions
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| !t.collided && now < t.end && now < t.start + self.propagation_delay)
+            .map(|(i, _)| i)
+            .collect();
+        if contenders.len() == 1 && in_flight.is_empty() {
+            let (port, frame, bandwidth) = contenders.remove(0);
+            let end = now + transmission_time(frame.wire_size(), bandwidth);
+            if let Ok(mut interface) = interfaces.get_mut(port) {
+                if let Interface::Ethernet(eth) = &mut *interface {
+                    eth.tx_busy_until = end;
                 }
-                Err(_) => println!("Source interface not found."),
             }
+            self.transmissions.push(Transmission {
+                port,
+                frame,
+                bandwidth,
+                start: now,
+                end,
+                collided: false,
+            });
+            scheduler.schedule_wakeup(end + self.propagation_delay);
+            return;
+        }
+
+        // Collision. The new senders notice it when the earliest other signal reaches them,
+        // the ones already sending when the new signals reach them.
+        let detected = in_flight
+            .iter()
+            .map(|i| self.transmissions[*i].start)
+            .fold(now, Duration::min)
+            + self.propagation_delay;
+        println!("\nCollision on hub {:?}", hub);
+        for (port, frame, bandwidth) in contenders {
+            let jam_end = detected + CsmaCd::jam_time(bandwidth);
+            Self::collide(
+                port,
+                frame.clone(),
+                now,
+                detected,
+                bandwidth,
+                scheduler,
+                interfaces,
+            );
+            self.transmissions.push(Transmission {
+                port,
+                frame,
+                bandwidth,
+                start: now,
+                end: jam_end,
+                collided: true,
+            });
+            scheduler.schedule_wakeup(jam_end + self.propagation_delay);
+        }
+        for i in in_flight {
+            let t = self.transmissions[i].clone();
+            let detected = now + self.propagation_delay;
+            let jam_end = detected + CsmaCd::jam_time(t.bandwidth);
+            Self::collide(
+                t.port,
+                t.frame,
+                t.start,
+                detected,
+                t.bandwidth,
+                scheduler,
+                interfaces,
+            );
+            self.transmissions[i].end = jam_end;
+            self.transmissions[i].collided = true;
+            scheduler.schedule_wakeup(jam_end + self.propagation_delay);
         }
     }
-}
\ No newline at end of file
+
+    // Lets the interface on `port` jam and back off, and wakes it up when it may retry
+    fn collide(
+        port: Entity,
+        frame: EthernetFrame,
+        started: Duration,
+        detected: Duration,
+        bandwidth: u64,
+        scheduler: &mut EventScheduler,
+        interfaces: &mut Query<&mut Interface>,
+    ) {
+        let Ok(mut interface) = interfaces.get_mut(port) else {
+            return;
+        };
+        if let Interface::Ethernet(eth) = &mut *interface {
+            let retry = eth.collision(frame, started, detected, bandwidth);
+            scheduler.schedule_wakeup(retry);
+        }
+    }
+
+    // Repeats to the other ports the frames whose last bit has crossed the hub, and clears
+    // the medium of the collided ones
+    fn deliver_frames(
+        &mut self,
+        hub: Entity,
+        now: Duration,
+        scheduler: &mut EventScheduler,
+        interfaces: &mut Query<&mut Interface>,
+        captures: &mut Query<&mut Capture>,
+    ) {
+        let propagation_delay = self.propagation_delay;
+        let (finished, ongoing): (Vec<_>, Vec<_>) = self
+            .transmissions
+            .drain(..)
+            .partition(|t| t.end + propagation_delay <= now);
+        self.transmissions = ongoing;
+        for t in finished.into_iter().filter(|t| !t.collided) {
+            if let Ok(mut interface) = interfaces.get_mut(t.port) {
+                if let Interface::Ethernet(eth) = &mut *interface {
+                    eth.stats.tx_frames += 1;
+                    eth.csma_cd.transmitted();
+                }
+            }
+            if let Ok(mut capture) = captures.get_mut(t.port) {
+                capture.record(t.start, Some(Direction::Out), &t.frame);
+            }
+            if let Ok(mut capture) = captures.get_mut(hub) {
+                capture.record(t.start, None, &t.frame);
+            }
+            for dest_interface in self.interfaces.iter() {
+                if *dest_interface != t.port {
+                    scheduler.schedule_frame_arrival(
+                        t.end + propagation_delay,
+                        *dest_interface,
+                        t.frame.clone(),
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,37 +1,401 @@
-use super::super::layer2::interface::{Direction, Interface};
+use super::capture::Capture;
+use super::super::layer2::{
+    interface::{Direction, EthernetInterface, Interface, InterfaceStatus},
+    pdu::EthernetFrame,
+};
+use super::transmission_time;
+use crate::simulation::entity::EventScheduler;
 use bevy::prelude::*;
+use rand::Rng;
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Component)]
 pub struct Link(pub Entity, pub Entity);
 
+/// An interface went up or down: it was shut down or enabled, its link was negotiated, or the
+/// link at the other end went away
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct LinkStateChange {
+    pub interface: Entity,
+    pub status: InterfaceStatus,
+    pub time: Duration,
+}
+
 impl Link {
     pub fn new(source: Entity, destination: Entity) -> Self {
         Link(source, destination)
     }
 
+    /// Starts sending the next frame queued on `source` if its transmitter is idle.
+    /// The frame arrives at `destination` once it has been serialized and has propagated
+    /// across the link, unless the link characteristics cause it to be lost or corrupted.
+    /// Nothing is sent until autonegotiation has brought the link up. A half-duplex end
+    /// defers to the other end and detects collisions, which the other end causes when it
+    /// runs at full duplex (duplex mismatch).
+    #[allow(clippy::too_many_arguments)]
     pub fn transmit_frame(
+        link: Entity,
         source: Entity,
         destination: Entity,
+        now: Duration,
+        characteristics: Option<&mut LinkCharacteristics>,
+        scheduler: &mut EventScheduler,
         interfaces: &mut Query<&mut Interface>,
+        captures: &mut Query<&mut Capture>,
     ) {
-        match interfaces.get_mut(source) {
-            Ok(mut src_interface) => {
-                if let Interface::Ethernet(src_eth_interface) = &mut *src_interface {
-                    if let Some(frame) = src_eth_interface.dequeue_frame(Direction::Out) {
-                        match interfaces.get_mut(destination) {
-                            Ok(mut dest_interface) => {
-                                if let Interface::Ethernet(dest_eth_interface) =
-                                    &mut *dest_interface
-                                {
-                                    dest_eth_interface.enqueue_frame(frame, Direction::In);
-                                }
-                            }
-                            Err(_) => println!("Destination interface not found."),
+        let (line_rate, half_duplex, carrier, dest_half_duplex, dest_sending) =
+            match (interfaces.get(source), interfaces.get(destination)) {
+                (Ok(Interface::Ethernet(src)), Ok(Interface::Ethernet(dest))) => {
+                    if src.link_mode.is_none() || dest.link_mode.is_none() {
+                        return;
+                    }
+                    let sending = dest
+                        .csma_cd
+                        .sending_since
+                        .filter(|_| now < dest.tx_busy_until);
+                    (
+                        src.line_rate().min(dest.line_rate()),
+                        src.is_half_duplex(),
+                        (dest.tx_started_at, dest.tx_busy_until),
+                        dest.is_half_duplex(),
+                        dest.is_half_duplex().then_some(sending).flatten(),
+                    )
+                }
+                (Err(_), _) => {
+                    println!("Source interface not found.");
+                    return;
+                }
+                (_, Err(_)) => {
+                    println!("Destination interface not found.");
+                    return;
+                }
+                _ => return,
+            };
+        let bandwidth = characteristics
+            .as_ref()
+            .map_or(line_rate, |c| c.effective_bandwidth(line_rate));
+
+        let Ok(mut src_interface) = interfaces.get_mut(source) else {
+            return;
+        };
+        let Interface::Ethernet(src_eth_interface) = &mut *src_interface else {
+            return;
+        };
+        let sent = if half_duplex {
+            Self::transmit_half_duplex(src_eth_interface, now, bandwidth, carrier, scheduler)
+        } else {
+            src_eth_interface
+                .start_transmission(now, bandwidth)
+                .map(|(frame, tx_end)| (frame, now, tx_end))
+        };
+        let Some((frame, started, tx_end)) = sent else {
+            return;
+        };
+        // Signal starting now while the other end is sending at half duplex: it sees a
+        // collision. A full-duplex end does not listen before talking, so with a duplex
+        // mismatch this happens at any point of the frame, leading to late collisions.
+        if let (true, Some(dest_started)) = (started == now, dest_sending) {
+            if let Ok(mut dest_interface) = interfaces.get_mut(destination) {
+                if let Interface::Ethernet(dest) = &mut *dest_interface {
+                    if let Some(frame) = dest.csma_cd.frame.take() {
+                        let retry = dest.collision(frame.clone(), dest_started, now, bandwidth);
+                        scheduler.schedule_wakeup(retry);
+                        // What it sent so far arrives as a fragment failing the FCS check,
+                        // unless this end also knows about the collision
+                        if !half_duplex {
+                            scheduler.schedule_frame_arrival(now, source, Self::fragment(frame));
                         }
                     }
                 }
             }
-            Err(_) => println!("Source interface not found."),
         }
+        let is_fragment = half_duplex && started == tx_end;
+        if is_fragment && dest_half_duplex {
+            // Half-duplex receivers discard collision fragments
+            return;
+        }
+        Self::deliver(
+            link,
+            source,
+            destination,
+            frame,
+            started,
+            tx_end,
+            characteristics,
+            scheduler,
+            captures,
+        );
+    }
+
+    /// Half-duplex transmission: a frame is only handed to the link once the whole of it
+    /// has been sent without collision. Until then it waits in the CSMA/CD state of the
+    /// interface, and a new one only starts when the other end is silent. `carrier` is the
+    /// start and end of the current transmission of the other end.
+    /// Returns the frame that got through, or the fragment left by a collision, with when
+    /// it started and ended.
+    fn transmit_half_duplex(
+        src: &mut EthernetInterface,
+        now: Duration,
+        bandwidth: u64,
+        (carrier_start, carrier_end): (Duration, Duration),
+        scheduler: &mut EventScheduler,
+    ) -> Option<(EthernetFrame, Duration, Duration)> {
+        if let Some(started) = src.csma_cd.sending_since {
+            if now < src.tx_busy_until {
+                return None;
+            }
+            let frame = src.csma_cd.frame.take()?;
+            src.csma_cd.transmitted();
+            src.stats.tx_frames += 1;
+            return Some((frame, started, src.tx_busy_until));
+        }
+        let frame = src.next_half_duplex_frame(now)?;
+        if carrier_start < now && now < carrier_end {
+            // Carrier sense: the other end is talking
+            src.defer_frame(frame);
+            scheduler.schedule_wakeup(carrier_end);
+            return None;
+        }
+        if now < carrier_end {
+            // The other end started at this very instant, too late to be heard
+            let retry = src.collision(frame.clone(), now, now, bandwidth);
+            scheduler.schedule_wakeup(retry);
+            return Some((Self::fragment(frame), now, now));
+        }
+        src.tx_started_at = now;
+        src.tx_busy_until = now + transmission_time(frame.wire_size(), bandwidth);
+        src.csma_cd.sending_since = Some(now);
+        src.csma_cd.frame = Some(frame);
+        scheduler.schedule_wakeup(src.tx_busy_until);
+        None
+    }
+
+    // What the other end receives of a frame cut short by a collision: it fails the FCS check
+    fn fragment(mut frame: EthernetFrame) -> EthernetFrame {
+        frame.fcs = frame.fcs.map(|byte| !byte);
+        frame
+    }
+
+    // Sends `frame`, serialized from `started` to `tx_end`, across the link
+    #[allow(clippy::too_many_arguments)]
+    fn deliver(
+        link: Entity,
+        source: Entity,
+        destination: Entity,
+        mut frame: EthernetFrame,
+        started: Duration,
+        tx_end: Duration,
+        characteristics: Option<&mut LinkCharacteristics>,
+        scheduler: &mut EventScheduler,
+        captures: &mut Query<&mut Capture>,
+    ) {
+        // Wake up when the transmitter is free to send the next queued frame
+        scheduler.schedule_wakeup(tx_end);
+
+        if let Ok(mut capture) = captures.get_mut(source) {
+            capture.record(started, Some(Direction::Out), &frame);
+        }
+        if let Ok(mut capture) = captures.get_mut(link) {
+            capture.record(started, None, &frame);
+        }
+
+        let arrival = match characteristics {
+            Some(characteristics) => {
+                let mut rng = rand::thread_rng();
+                characteristics.stats.frames_sent += 1;
+                if characteristics.is_lost(&mut rng) {
+                    characteristics.stats.frames_lost += 1;
+                    return;
+                }
+                // Corrupted frames are still delivered; the receiver drops them on the FCS check
+                if characteristics.apply_bit_errors(&mut frame, &mut rng) {
+                    characteristics.stats.frames_corrupted += 1;
+                }
+                tx_end + characteristics.delivery_delay(&mut rng)
+            }
+            None => tx_end,
+        };
+        scheduler.schedule_frame_arrival(arrival, destination, frame);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkError {
+    // Probabilities must be numbers; they are clamped to [0, 1]
+    InvalidProbability(f64),
+    ZeroBandwidth,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::InvalidProbability(value) => write!(f, "Invalid probability {}", value),
+            LinkError::ZeroBandwidth => write!(f, "Bandwidth must be greater than zero"),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Physical characteristics of a link, attached to the same entity as the `Link`.
+/// Links without it behave like an ideal cable running at the line rate of the slower
+/// interface, with no propagation delay.
+#[derive(Component, Debug, Clone, Default)]
+pub struct LinkCharacteristics {
+    // Caps the bandwidth in bits per second; by default the interface types decide
+    pub bandwidth: Option<u64>,
+    pub propagation_delay: Duration,
+    // Maximum random delay added on top of the propagation delay. Frames may be reordered.
+    pub jitter: Duration,
+    // Probability in [0, 1] that a frame is lost on the link
+    pub loss_probability: f64,
+    // Probability in [0, 1] that any single bit is flipped in transit
+    pub bit_error_rate: f64,
+    pub stats: LinkStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LinkStats {
+    pub frames_sent: u64,
+    pub frames_lost: u64,
+    pub frames_corrupted: u64,
+}
+
+impl LinkCharacteristics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_bandwidth(&mut self, bandwidth: u64) -> Result<(), LinkError> {
+        if bandwidth == 0 {
+            return Err(LinkError::ZeroBandwidth);
+        }
+        self.bandwidth = Some(bandwidth);
+        Ok(())
+    }
+
+    pub fn set_loss_probability(&mut self, probability: f64) -> Result<(), LinkError> {
+        self.loss_probability = Self::validate_probability(probability)?;
+        Ok(())
+    }
+
+    pub fn set_bit_error_rate(&mut self, rate: f64) -> Result<(), LinkError> {
+        self.bit_error_rate = Self::validate_probability(rate)?;
+        Ok(())
+    }
+
+    fn validate_probability(probability: f64) -> Result<f64, LinkError> {
+        if probability.is_nan() {
+            return Err(LinkError::InvalidProbability(probability));
+        }
+        Ok(probability.clamp(0.0, 1.0))
+    }
+
+    // The fields are public, so values that were not validated are made harmless where
+    // they are used: NaN counts as zero and anything else is clamped to [0, 1]
+    fn probability(value: f64) -> f64 {
+        Self::validate_probability(value).unwrap_or(0.0)
+    }
+
+    // Bandwidth actually available given the line rate of the slower interface.
+    // A zero cap is ignored.
+    pub fn effective_bandwidth(&self, line_rate: u64) -> u64 {
+        self.bandwidth
+            .filter(|bandwidth| *bandwidth > 0)
+            .map_or(line_rate, |bandwidth| bandwidth.min(line_rate))
+    }
+
+    // Propagation delay plus a uniformly distributed amount of jitter
+    pub fn delivery_delay<R: Rng>(&self, rng: &mut R) -> Duration {
+        if self.jitter.is_zero() {
+            return self.propagation_delay;
+        }
+        let jitter = rng.gen_range(0..=self.jitter.as_nanos() as u64);
+        self.propagation_delay + Duration::from_nanos(jitter)
+    }
+
+    pub fn is_lost<R: Rng>(&self, rng: &mut R) -> bool {
+        let probability = Self::probability(self.loss_probability);
+        probability > 0.0 && rng.gen_bool(probability)
+    }
+
+    /// Flips each bit of the frame on the wire, FCS included, with probability `bit_error_rate`.
+    /// Returns true if any bit was flipped.
+    pub fn apply_bit_errors<R: Rng>(&self, frame: &mut EthernetFrame, rng: &mut R) -> bool {
+        let bit_error_rate = Self::probability(self.bit_error_rate);
+        if bit_error_rate <= 0.0 {
+            return false;
+        }
+        let mut bytes = frame.to_wire();
+        let bits = bytes.len() * 8;
+        let mut flipped = false;
+        let mut position = 0;
+        // Jump straight from one errored bit to the next using geometrically distributed
+        // gaps instead of drawing a random number for every bit
+        loop {
+            let gap = if bit_error_rate >= 1.0 {
+                0.0
+            } else {
+                (rng.gen::<f64>().ln() / (1.0 - bit_error_rate).ln()).floor()
+            };
+            if gap >= (bits - position) as f64 {
+                break;
+            }
+            position += gap as usize;
+            bytes[position / 8] ^= 0x80 >> (position % 8);
+            flipped = true;
+            position += 1;
+        }
+        if !flipped {
+            return false;
+        }
+
+        match EthernetFrame::from_wire(&bytes) {
+            Ok(corrupted) => *frame = corrupted,
+            // The corrupted header no longer parses; make sure the receiver still sees a bad FCS
+            Err(_) => frame.fcs = frame.fcs.map(|byte| !byte),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer2::address::MacAddress;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let mut characteristics = LinkCharacteristics::new();
+        assert_eq!(
+            characteristics.set_bandwidth(0),
+            Err(LinkError::ZeroBandwidth)
+        );
+        assert!(characteristics.set_bit_error_rate(f64::NAN).is_err());
+        characteristics.set_loss_probability(1.5).unwrap();
+        assert_eq!(characteristics.loss_probability, 1.0);
+        characteristics.set_bit_error_rate(-0.5).unwrap();
+        assert_eq!(characteristics.bit_error_rate, 0.0);
+    }
+
+    #[test]
+    fn ignores_unvalidated_values() {
+        let characteristics = LinkCharacteristics {
+            bandwidth: Some(0),
+            loss_probability: f64::NAN,
+            bit_error_rate: f64::NAN,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut frame = EthernetFrame::new(MacAddress::random(), MacAddress::broadcast());
+        assert!(!characteristics.apply_bit_errors(&mut frame, &mut rng));
+        assert!(!characteristics.is_lost(&mut rng));
+        assert_eq!(
+            characteristics.effective_bandwidth(100_000_000),
+            100_000_000
+        );
+        assert_eq!(transmission_time(64, 0), Duration::ZERO);
     }
 }
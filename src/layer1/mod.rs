@@ -1,8 +1,14 @@
-use crate::layer2::systems::peek_queues;
+use crate::layer2::systems::{peek_queues, process_frames};
+use crate::simulation::system::dispatch_events;
 use bevy::prelude::*;
-use systems::transmit_frames;
+use link::LinkStateChange;
+use std::time::Duration;
+use systems::{negotiate_links, report_link_changes, transmit_frames};
 
+pub mod autoneg;
+pub mod capture;
 pub mod crc;
+pub mod csma;
 pub mod link;
 pub mod systems;
 pub mod hub;
@@ -11,6 +17,26 @@ pub struct Layer1Plugin;
 
 impl Plugin for Layer1Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(FixedUpdate, transmit_frames.before(peek_queues));
+        // Link states are settled before the upper layers look at them. Transmit after
+        // processing so replies generated this instant go out right away.
+        app.add_event::<LinkStateChange>().add_systems(
+            FixedUpdate,
+            (
+                (negotiate_links, report_link_changes)
+                    .chain()
+                    .after(dispatch_events)
+                    .before(peek_queues),
+                transmit_frames.after(process_frames),
+            ),
+        );
     }
 }
+
+// Time it takes to serialize `bytes` onto a medium running at `bandwidth` bits per second.
+// A zero bandwidth is treated as unlimited.
+pub fn transmission_time(bytes: usize, bandwidth: u64) -> Duration {
+    if bandwidth == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(bytes as u64 * 8 * 1_000_000_000 / bandwidth)
+}
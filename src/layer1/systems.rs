@@ -1,17 +1,163 @@
 use bevy::prelude::*;
-use super::{link::Link, hub::Hub};
-use crate::layer2::interface::Interface;
+use super::{
+    autoneg::negotiate,
+    capture::Capture,
+    hub::Hub,
+    link::{Link, LinkCharacteristics, LinkStateChange},
+};
+use crate::layer2::interface::{Interface, InterfaceStatus};
+use crate::simulation::entity::{EventScheduler, SimClock};
+use std::collections::HashSet;
 
-pub fn transmit_frames(links: Query<&Link>, hubs: Query<&Hub>, mut interfaces: Query<&mut Interface>) {
+/// Brings links up and down. Autonegotiation runs on the links just connected and on those
+/// whose ends changed their speed, duplex or shutdown settings; a link with an end shut down
+/// stays down at both ends. Ports attached to a hub come up at half duplex. Interfaces no
+/// longer attached to anything, e.g. because their `Link` was removed, go down.
+pub fn negotiate_links(
+    links: Query<Ref<Link>>,
+    hubs: Query<Ref<Hub>>,
+    mut interfaces: Query<(Entity, &mut Interface)>,
+) {
+    let mut attached: HashSet<Entity> = HashSet::new();
     for link in links.iter() {
+        let (Ok((_, Interface::Ethernet(a))), Ok((_, Interface::Ethernet(b)))) =
+            (interfaces.get(link.0), interfaces.get(link.1))
+        else {
+            continue;
+        };
+        attached.extend([link.0, link.1]);
+        if !link.is_added() && !a.needs_autonegotiation() && !b.needs_autonegotiation() {
+            continue;
+        }
+        let modes = if a.is_shutdown() || b.is_shutdown() {
+            None
+        } else {
+            match negotiate(&a.ability(), &b.ability()) {
+                Ok((mode_a, mode_b)) => {
+                    if mode_a.duplex != mode_b.duplex {
+                        println!("\nLink up, duplex mismatch: {} and {}", mode_a, mode_b);
+                    } else {
+                        println!("\nLink up: {}", mode_a);
+                    }
+                    Some((mode_a, mode_b))
+                }
+                Err(error) => {
+                    println!("\nLink down: {}", error);
+                    None
+                }
+            }
+        };
+        for (entity, mode) in [(link.0, modes.map(|m| m.0)), (link.1, modes.map(|m| m.1))] {
+            if let Ok((_, mut interface)) = interfaces.get_mut(entity) {
+                if let Interface::Ethernet(eth) = &mut *interface {
+                    eth.set_link_mode(mode);
+                }
+            }
+        }
+    }
+
+    for hub in hubs.iter() {
+        for port in hub.interfaces.iter() {
+            let Ok((_, mut interface)) = interfaces.get_mut(*port) else {
+                continue;
+            };
+            let Interface::Ethernet(eth) = &mut *interface else {
+                continue;
+            };
+            attached.insert(*port);
+            if hub.is_added() || eth.needs_autonegotiation() {
+                let mode = eth.ability().repeater_mode().filter(|_| !eth.is_shutdown());
+                eth.set_link_mode(mode);
+            }
+        }
+    }
+
+    for (entity, mut interface) in interfaces.iter_mut() {
+        if let Interface::Ethernet(eth) = &mut *interface {
+            if eth.link_mode.is_some() && !attached.contains(&entity) {
+                eth.set_link_mode(None);
+            }
+        }
+    }
+}
+
+// Reports the interfaces that went up or down as `LinkStateChange` events, logging them
+// like the IOS console does
+pub fn report_link_changes(
+    clock: Res<SimClock>,
+    mut interfaces: Query<(Entity, &mut Interface, Option<&Name>)>,
+    mut changes: EventWriter<LinkStateChange>,
+) {
+    for (entity, mut interface, name) in interfaces.iter_mut() {
+        let Interface::Ethernet(eth) = &mut *interface else {
+            continue;
+        };
+        let Some((previous, status)) = eth.take_status_change() else {
+            continue;
+        };
+        let name = name.map_or_else(|| format!("{:?}", entity), |name| name.to_string());
+        match status {
+            InterfaceStatus::AdministrativelyDown => println!(
+                "\n%LINK-5-CHANGED: Interface {}, changed state to {}",
+                name, status
+            ),
+            _ => println!(
+                "\n%LINK-3-UPDOWN: Interface {}, changed state to {}",
+                name, status
+            ),
+        }
+        // The line protocol only changes when the interface comes up or leaves the up state
+        if previous == InterfaceStatus::Up || status == InterfaceStatus::Up {
+            println!(
+                "%LINEPROTO-5-UPDOWN: Line protocol on Interface {}, changed state to {}",
+                name,
+                eth.line_protocol()
+            );
+        }
+        changes.send(LinkStateChange {
+            interface: entity,
+            status,
+            time: clock.now(),
+        });
+    }
+}
+
+pub fn transmit_frames(
+    mut links: Query<(Entity, &Link, Option<&mut LinkCharacteristics>)>,
+    mut hubs: Query<(Entity, &mut Hub)>,
+    clock: Res<SimClock>,
+    mut scheduler: ResMut<EventScheduler>,
+    mut interfaces: Query<&mut Interface>,
+    mut captures: Query<&mut Capture>,
+) {
+    let now = clock.now();
+    for (entity, link, mut characteristics) in links.iter_mut() {
         // Transmit frame from link.0 to link.1
-        Link::transmit_frame(link.0, link.1, &mut interfaces);
+        Link::transmit_frame(
+            entity,
+            link.0,
+            link.1,
+            now,
+            characteristics.as_deref_mut(),
+            &mut scheduler,
+            &mut interfaces,
+            &mut captures,
+        );
 
         // Transmit frame from link.1 to link.0
-        Link::transmit_frame(link.1, link.0, &mut interfaces);
+        Link::transmit_frame(
+            entity,
+            link.1,
+            link.0,
+            now,
+            characteristics.as_deref_mut(),
+            &mut scheduler,
+            &mut interfaces,
+            &mut captures,
+        );
     }
 
-    for hub in hubs.iter() {
-        hub.transmit_frame(&mut interfaces);
+    for (entity, mut hub) in hubs.iter_mut() {
+        hub.transmit_frame(entity, now, &mut scheduler, &mut interfaces, &mut captures);
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,9 @@
+use crate::layer3::address::{AddressError, Ipv6Addr};
 use once_cell::sync::Lazy;
 use rand::Rng;
 use regex::Regex;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct MacAddress {
@@ -9,50 +11,66 @@ pub struct MacAddress {
 }
 
 impl MacAddress {
-    pub fn new(address: String) -> Result<Self, String> {
-        static MAC_ADDRESS_REGEX: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r"^([0-9A-Fa-f]{2}[:-]){5}([0-9A-Fa-f]{2})$")
-                .expect("Failed to compile regex")
-        });
-
-        if MAC_ADDRESS_REGEX.is_match(&address) {
-            let bytes: Result<Vec<u8>, _> = address
-                .split(|c| c == ':' || c == '-')
-                .map(|byte| u8::from_str_radix(byte, 16))
-                .collect();
-            match bytes {
-                Ok(bytes) => Ok(Self {
-                    bytes: [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]],
-                }),
-                Err(_) => Err("Invalid MAC address format".to_string()),
-            }
-        } else {
-            Err("Invalid MAC address format".to_string())
-        }
+    pub fn new(address: String) -> Result<Self, AddressError> {
+        address.parse()
     }
 
     pub fn random() -> Self {
-        let fixed_oid = "00:11:22";
         let mut rng = rand::thread_rng();
-        let random_bytes: Vec<String> =
-            (0..3).map(|_| format!("{:02X}", rng.gen::<u8>())).collect();
-        let mac_address = format!("{}:{}", fixed_oid, random_bytes.join(":"));
-        Self::new(mac_address).unwrap()
+        // Fixed OUI 00:11:22
+        Self {
+            bytes: [0x00, 0x11, 0x22, rng.gen(), rng.gen(), rng.gen()],
+        }
     }
 
     pub fn is_broadcast(&self) -> bool {
         self.bytes.iter().all(|&byte| byte == 0xFF)
     }
 
+    // Group addresses have the least significant bit of the first octet set
+    pub fn is_multicast(&self) -> bool {
+        self.bytes[0] & 0x01 == 0x01
+    }
+
     pub fn broadcast() -> Self {
         Self {
             bytes: [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
         }
     }
 
+    // Destination of spanning tree BPDUs, never forwarded by bridges
+    pub fn bridge_group() -> Self {
+        Self {
+            bytes: [0x01, 0x80, 0xC2, 0x00, 0x00, 0x00],
+        }
+    }
+
+    // Destination of packets sent to an IPv6 multicast group, 33:33 followed by the last
+    // 32 bits of the group address (RFC 2464)
+    pub fn ipv6_multicast(group: &Ipv6Addr) -> Self {
+        let [_, _, _, _, _, _, high, low] = group.segments;
+        let [a, b] = high.to_be_bytes();
+        let [c, d] = low.to_be_bytes();
+        Self {
+            bytes: [0x33, 0x33, a, b, c, d],
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 6]) -> Self {
+        Self { bytes }
+    }
+
     pub fn to_bytes(&self) -> [u8; 6] {
         self.bytes
     }
+
+    // Cisco style notation, e.g. 0011.2233.4455
+    pub fn to_dotted_string(&self) -> String {
+        format!(
+            "{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}",
+            self.bytes[0], self.bytes[1], self.bytes[2], self.bytes[3], self.bytes[4], self.bytes[5]
+        )
+    }
 }
 
 impl fmt::Display for MacAddress {
@@ -69,3 +87,61 @@ impl fmt::Display for MacAddress {
         )
     }
 }
+
+impl FromStr for MacAddress {
+    type Err = AddressError;
+
+    // Accepts colon or hyphen separated octets, e.g. "00:11:22:33:44:55"
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        static MAC_ADDRESS_REGEX: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"^([0-9A-Fa-f]{2}[:-]){5}([0-9A-Fa-f]{2})$")
+                .expect("Failed to compile regex")
+        });
+
+        if !MAC_ADDRESS_REGEX.is_match(address) {
+            return Err(AddressError::InvalidMac(address.to_string()));
+        }
+        let mut bytes = [0; 6];
+        for (byte, part) in bytes.iter_mut().zip(address.split([':', '-'])) {
+            *byte = u8::from_str_radix(part, 16)
+                .map_err(|_| AddressError::InvalidMac(address.to_string()))?;
+        }
+        Ok(Self { bytes })
+    }
+}
+
+impl TryFrom<&str> for MacAddress {
+    type Error = AddressError;
+
+    fn try_from(address: &str) -> Result<Self, Self::Error> {
+        address.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mac_addresses() {
+        let mac: MacAddress = "00:11:22:aa:BB:cc".parse().unwrap();
+        assert_eq!(mac.to_bytes(), [0x00, 0x11, 0x22, 0xAA, 0xBB, 0xCC]);
+        assert_eq!("00-11-22-AA-BB-CC".parse::<MacAddress>().unwrap(), mac);
+        assert_eq!(mac.to_dotted_string(), "0011.22aa.bbcc");
+    }
+
+    #[test]
+    fn rejects_invalid_mac_addresses() {
+        for value in [
+            "00:11:22:33:44:GG",
+            "00:11:22:33:44",
+            "00:11:22:33:44:555",
+            "",
+        ] {
+            assert_eq!(
+                value.parse::<MacAddress>().unwrap_err(),
+                AddressError::InvalidMac(value.to_string())
+            );
+        }
+    }
+}
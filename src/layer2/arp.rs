@@ -1,7 +1,13 @@
-use super::{address::MacAddress, pdu::Ethertype};
+use super::{
+    address::MacAddress,
+    pdu::{Ethertype, FrameError},
+};
 use crate::layer3::address::Ipv4Addr;
-use std::collections::HashMap;
+use crate::layer3::pdu::Ipv4Packet;
+use bevy::prelude::*;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub enum ArpOperation {
@@ -15,6 +21,17 @@ impl ArpOperation {
             ArpOperation::Reply => [0x00, 0x02],
         }
     }
+
+    pub fn from_value(value: [u8; 2]) -> Result<Self, FrameError> {
+        match value {
+            [0x00, 0x01] => Ok(ArpOperation::Request),
+            [0x00, 0x02] => Ok(ArpOperation::Reply),
+            _ => Err(FrameError::InvalidArp(format!(
+                "unknown operation 0x{:04X}",
+                u16::from_be_bytes(value)
+            ))),
+        }
+    }
 }
 
 impl fmt::Display for ArpOperation {
@@ -37,6 +54,16 @@ impl ArpHardwareType {
             ArpHardwareType::Ethernet => [0x00, 0x01],
         }
     }
+
+    pub fn from_value(value: [u8; 2]) -> Result<Self, FrameError> {
+        match value {
+            [0x00, 0x01] => Ok(ArpHardwareType::Ethernet),
+            _ => Err(FrameError::InvalidArp(format!(
+                "unsupported hardware type 0x{:04X}",
+                u16::from_be_bytes(value)
+            ))),
+        }
+    }
 }
 
 impl fmt::Display for ArpHardwareType {
@@ -61,6 +88,9 @@ pub struct ArpPacket {
 }
 
 impl ArpPacket {
+    // Length of an ARP packet for Ethernet and IPv4
+    const LENGTH: usize = 28;
+
     pub fn new(
         operation: ArpOperation,
         sender_mac: MacAddress,
@@ -75,7 +105,7 @@ impl ArpPacket {
             operation,
             sender_mac,
             sender_ip,
-            target_mac: MacAddress::new("00:00:00:00:00:00".to_string()).unwrap(), // We don't know the target MAC address yet
+            target_mac: MacAddress::from_bytes([0; 6]), // We don't know the target MAC address yet
             target_ip,
         }
     }
@@ -94,6 +124,55 @@ impl ArpPacket {
         bytes
     }
 
+    /// Parses an Ethernet/IPv4 ARP packet. Trailing bytes such as frame padding are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() < Self::LENGTH {
+            return Err(FrameError::Truncated {
+                expected: Self::LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        let hardware_type = ArpHardwareType::from_value([bytes[0], bytes[1]])?;
+        let protocol_type = match Ethertype::from_value([bytes[2], bytes[3]]) {
+            Ok(Ethertype::IPv4) => Ethertype::IPv4,
+            _ => {
+                return Err(FrameError::InvalidArp(format!(
+                    "unsupported protocol type 0x{:04X}",
+                    u16::from_be_bytes([bytes[2], bytes[3]])
+                )))
+            }
+        };
+        let hardware_size = bytes[4];
+        let protocol_size = bytes[5];
+        if hardware_size != 6 || protocol_size != 4 {
+            return Err(FrameError::InvalidArp(format!(
+                "unexpected address sizes {}/{}",
+                hardware_size, protocol_size
+            )));
+        }
+        let operation = ArpOperation::from_value([bytes[6], bytes[7]])?;
+
+        Ok(Self {
+            hardware_type,
+            protocol_type,
+            hardware_size,
+            protocol_size,
+            operation,
+            sender_mac: MacAddress::from_bytes([
+                bytes[8], bytes[9], bytes[10], bytes[11], bytes[12], bytes[13],
+            ]),
+            sender_ip: Ipv4Addr {
+                octets: [bytes[14], bytes[15], bytes[16], bytes[17]],
+            },
+            target_mac: MacAddress::from_bytes([
+                bytes[18], bytes[19], bytes[20], bytes[21], bytes[22], bytes[23],
+            ]),
+            target_ip: Ipv4Addr {
+                octets: [bytes[24], bytes[25], bytes[26], bytes[27]],
+            },
+        })
+    }
+
     pub fn create_reply(&self, sender_mac: MacAddress) -> Self {
         Self {
             hardware_type: self.hardware_type,
@@ -101,10 +180,10 @@ impl ArpPacket {
             hardware_size: self.hardware_size,
             protocol_size: self.protocol_size,
             operation: ArpOperation::Reply,
-            sender_mac: sender_mac,
-            sender_ip: self.target_ip.clone(),
+            sender_mac,
+            sender_ip: self.target_ip,
             target_mac: self.sender_mac.clone(),
-            target_ip: self.sender_ip.clone(),
+            target_ip: self.sender_ip,
         }
     }
 }
@@ -136,24 +215,296 @@ impl fmt::Display for ArpPacket {
     }
 }
 
+/// Another station answered or announced an address assigned to one of our interfaces
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct AddressConflict {
+    pub interface: Entity,
+    pub address: Ipv4Addr,
+    // MAC address of the station claiming the address
+    pub mac: MacAddress,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpEntryType {
+    Dynamic,
+    Static,
+}
+
+impl fmt::Display for ArpEntryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArpEntryType::Dynamic => f.pad("Dynamic"),
+            ArpEntryType::Static => f.pad("Static"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArpEntry {
+    // None while the entry is incomplete: a request is outstanding and nothing answered yet
+    pub mac: Option<MacAddress>,
+    pub entry_type: ArpEntryType,
+    // Simulation time at which the address was learned, or the last request was sent
+    // while incomplete
+    pub updated: Duration,
+    // Requests sent without an answer so far
+    pub requests: u8,
+    // Packets waiting for the address to be resolved
+    pending: VecDeque<Ipv4Packet>,
+}
+
+impl ArpEntry {
+    pub fn is_incomplete(&self) -> bool {
+        self.mac.is_none()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// What became of incomplete entries after `ArpTable::update`
+#[derive(Debug, Default)]
+pub struct ArpUpdate {
+    // Addresses to send another request for
+    pub retries: Vec<Ipv4Addr>,
+    // Packets dropped because their next hop never answered
+    pub dropped: Vec<Ipv4Packet>,
+}
+
+/// IPv4 to MAC address cache. Dynamic entries expire after the timeout, static ones never do.
+/// While a request is outstanding the entry is incomplete and holds the packets waiting on it;
+/// they are released when the reply arrives, or dropped once the requests run out.
 #[derive(Debug)]
 pub struct ArpTable {
-    entries: HashMap<Ipv4Addr, MacAddress>,
+    entries: HashMap<Ipv4Addr, ArpEntry>,
+    timeout: Duration,
+    retry_interval: Duration,
+    max_requests: u8,
+    scheduled_wakeup: Option<Duration>,
 }
 
 impl ArpTable {
+    // Cisco IOS keeps entries for 4 hours
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(4 * 60 * 60);
+    pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+    pub const DEFAULT_MAX_REQUESTS: u8 = 3;
+    // Packets held per incomplete entry; more are dropped
+    pub const MAX_PENDING: usize = 3;
+
     pub fn new() -> Self {
         Self {
             entries: HashMap::new(),
+            timeout: Self::DEFAULT_TIMEOUT,
+            retry_interval: Self::DEFAULT_RETRY_INTERVAL,
+            max_requests: Self::DEFAULT_MAX_REQUESTS,
+            scheduled_wakeup: None,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_retries(&mut self, max_requests: u8, retry_interval: Duration) {
+        self.max_requests = max_requests.max(1);
+        self.retry_interval = retry_interval;
+    }
+
+    /// Records that `ip` is at `mac`. Static entries are never overwritten.
+    /// Returns the packets that were waiting for the address, ready to be sent.
+    pub fn learn(&mut self, ip: Ipv4Addr, mac: MacAddress, now: Duration) -> Vec<Ipv4Packet> {
+        let entry = self.entries.entry(ip).or_insert_with(|| ArpEntry {
+            mac: None,
+            entry_type: ArpEntryType::Dynamic,
+            updated: now,
+            requests: 0,
+            pending: VecDeque::new(),
+        });
+        if entry.entry_type == ArpEntryType::Static {
+            return Vec::new();
+        }
+        entry.mac = Some(mac);
+        entry.updated = now;
+        entry.requests = 0;
+        entry.pending.drain(..).collect()
+    }
+
+    // Whether `ip` already has an entry, complete or not, that has not expired
+    pub fn contains(&self, ip: &Ipv4Addr, now: Duration) -> bool {
+        self.entries
+            .get(ip)
+            .is_some_and(|entry| !self.is_expired(entry, now))
+    }
+
+    pub fn add_static_entry(&mut self, ip: Ipv4Addr, mac: MacAddress) {
+        self.entries.insert(
+            ip,
+            ArpEntry {
+                mac: Some(mac),
+                entry_type: ArpEntryType::Static,
+                updated: Duration::ZERO,
+                requests: 0,
+                pending: VecDeque::new(),
+            },
+        );
+    }
+
+    pub fn remove_entry(&mut self, ip: &Ipv4Addr) {
+        self.entries.remove(ip);
+    }
+
+    // Returns the MAC address of `ip`, ignoring incomplete entries and those that have aged out
+    pub fn get_mac_address(&self, ip: &Ipv4Addr, now: Duration) -> Option<MacAddress> {
+        self.entries
+            .get(ip)
+            .filter(|entry| !self.is_expired(entry, now))
+            .and_then(|entry| entry.mac.clone())
+    }
+
+    /// Holds `packet` until `ip` is resolved. Returns true if the address was not being
+    /// resolved yet, in which case the caller must send the first request.
+    /// Packets beyond `MAX_PENDING` are dropped.
+    pub fn queue_packet(&mut self, ip: Ipv4Addr, packet: Ipv4Packet, now: Duration) -> bool {
+        let expired = self
+            .entries
+            .get(&ip)
+            .is_some_and(|entry| self.is_expired(entry, now));
+        if expired {
+            self.entries.remove(&ip);
+        }
+        match self.entries.get_mut(&ip) {
+            Some(entry) => {
+                if entry.pending.len() < Self::MAX_PENDING {
+                    entry.pending.push_back(packet);
+                }
+                false
+            }
+            None => {
+                self.entries.insert(
+                    ip,
+                    ArpEntry {
+                        mac: None,
+                        entry_type: ArpEntryType::Dynamic,
+                        updated: now,
+                        requests: 1,
+                        pending: VecDeque::from([packet]),
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Removes the dynamic entries that have aged out and retries the incomplete ones whose
+    /// request went unanswered, giving up after the maximum number of requests
+    pub fn update(&mut self, now: Duration) -> ArpUpdate {
+        let mut update = ArpUpdate::default();
+        let (timeout, retry_interval, max_requests) =
+            (self.timeout, self.retry_interval, self.max_requests);
+        self.entries.retain(|ip, entry| {
+            if entry.entry_type == ArpEntryType::Static {
+                return true;
+            }
+            if !entry.is_incomplete() {
+                return now.saturating_sub(entry.updated) <= timeout;
+            }
+            if now < entry.updated + retry_interval {
+                return true;
+            }
+            if entry.requests < max_requests {
+                entry.requests += 1;
+                entry.updated = now;
+                update.retries.push(*ip);
+                return true;
+            }
+            update.dropped.extend(entry.pending.drain(..));
+            false
+        });
+        update
+    }
+
+    // Next time an incomplete entry needs a retry, if not already scheduled
+    pub fn next_wakeup(&mut self) -> Option<Duration> {
+        let next = self
+            .entries
+            .values()
+            .filter(|entry| entry.is_incomplete())
+            .map(|entry| entry.updated + self.retry_interval)
+            .min()?;
+        if self.scheduled_wakeup == Some(next) {
+            return None;
         }
+        self.scheduled_wakeup = Some(next);
+        Some(next)
+    }
+
+    // "clear arp": removes every dynamic and incomplete entry, dropping the waiting packets
+    pub fn clear(&mut self) {
+        self.entries
+            .retain(|_, entry| entry.entry_type == ArpEntryType::Static);
+    }
+
+    pub fn entry(&self, ip: &Ipv4Addr) -> Option<&ArpEntry> {
+        self.entries.get(ip)
     }
 
-    pub fn add_entry(&mut self, ip: Ipv4Addr, mac: MacAddress) {
-        self.entries.insert(ip, mac);
+    pub fn len(&self) -> usize {
+        self.entries.len()
     }
 
-    pub fn get_mac_address(&self, ip: &Ipv4Addr) -> Option<MacAddress> {
-        self.entries.get(ip).cloned()
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn is_expired(&self, entry: &ArpEntry, now: Duration) -> bool {
+        entry.entry_type == ArpEntryType::Dynamic
+            && !entry.is_incomplete()
+            && now.saturating_sub(entry.updated) > self.timeout
+    }
+
+    /// Formats the table like "show arp", with the age of the entries in minutes
+    pub fn show(&self, now: Duration, interface_name: &str) -> String {
+        let mut entries: Vec<(&Ipv4Addr, &ArpEntry)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !self.is_expired(entry, now))
+            .collect();
+        entries.sort_by_key(|(ip, _)| ip.to_u32());
+
+        let mut output = String::new();
+        output.push_str("Protocol  Address          Age (min)  Hardware Addr   Type   Interface\n");
+        for (ip, entry) in entries {
+            let age = match entry.entry_type {
+                ArpEntryType::Static => "-".to_string(),
+                ArpEntryType::Dynamic => {
+                    (now.saturating_sub(entry.updated).as_secs() / 60).to_string()
+                }
+            };
+            let mac = match &entry.mac {
+                Some(mac) => mac.to_dotted_string(),
+                None => "Incomplete".to_string(),
+            };
+            output.push_str(&format!(
+                "Internet  {:<15}  {:>9}  {:<14}  ARPA   {}\n",
+                ip.to_string(),
+                age,
+                mac,
+                interface_name
+            ));
+        }
+        output
+    }
+}
+
+impl Default for ArpTable {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -165,18 +516,169 @@ impl fmt::Display for ArpTable {
             .keys()
             .map(|ip| ip.to_string().len())
             .max()
-            .unwrap_or(0);
+            .unwrap_or(0)
+            .max("IP Address".len());
 
         // Create a header
-        let header = format!("{:<max_ip_width$} | MAC Address", "IP Address");
+        let header = format!(
+            "{:<max_ip_width$} | {:<17} | Type",
+            "IP Address", "MAC Address"
+        );
         writeln!(f, "{}", header)?;
         writeln!(f, "{}", "-".repeat(header.len()))?;
 
         // Print each entry
-        for (ip, mac) in &self.entries {
-            writeln!(f, "{:<max_ip_width$} | {}", ip, mac)?;
+        for (ip, entry) in &self.entries {
+            let mac = match &entry.mac {
+                Some(mac) => mac.to_string(),
+                None => "Incomplete".to_string(),
+            };
+            writeln!(
+                f,
+                "{:<max_ip_width$} | {:<17} | {}",
+                ip.to_string(),
+                mac,
+                entry.entry_type
+            )?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer3::pdu::IpPayload;
+
+    fn ip(value: &str) -> Ipv4Addr {
+        Ipv4Addr::new(value).unwrap()
+    }
+
+    fn mac(value: &str) -> MacAddress {
+        value.parse().unwrap()
+    }
+
+    // Packet to 10.0.0.2 told apart by its identification
+    fn packet(identification: u16) -> Ipv4Packet {
+        let mut packet =
+            Ipv4Packet::new(ip("10.0.0.1"), ip("10.0.0.2"), IpPayload { data: vec![] });
+        packet.header.identification = identification;
+        packet.update_header();
+        packet
+    }
+
+    fn identifications(packets: &[Ipv4Packet]) -> Vec<u16> {
+        packets
+            .iter()
+            .map(|packet| packet.header.identification)
+            .collect()
+    }
+
+    #[test]
+    fn ages_out_dynamic_entries() {
+        let mut table = ArpTable::new();
+        table.set_timeout(Duration::from_secs(60));
+        table.learn(
+            ip("10.0.0.2"),
+            mac("00:11:22:33:44:55"),
+            Duration::from_secs(10),
+        );
+        table.add_static_entry(ip("10.0.0.3"), mac("00:11:22:33:44:66"));
+
+        let now = Duration::from_secs(70);
+        assert_eq!(
+            table.get_mac_address(&ip("10.0.0.2"), now),
+            Some(mac("00:11:22:33:44:55"))
+        );
+        table.update(now);
+        assert_eq!(table.len(), 2);
+
+        let now = Duration::from_secs(71);
+        assert_eq!(table.get_mac_address(&ip("10.0.0.2"), now), None);
+        assert!(!table.contains(&ip("10.0.0.2"), now));
+        table.update(now);
+        assert!(table.entry(&ip("10.0.0.2")).is_none());
+        assert!(table.contains(&ip("10.0.0.3"), Duration::from_secs(1_000_000)));
+    }
+
+    #[test]
+    fn releases_queued_packets_once_resolved() {
+        let mut table = ArpTable::new();
+        let now = Duration::from_secs(1);
+        assert!(table.queue_packet(ip("10.0.0.2"), packet(1), now));
+        assert!(!table.queue_packet(ip("10.0.0.2"), packet(2), now));
+        let entry = table.entry(&ip("10.0.0.2")).unwrap();
+        assert!(entry.is_incomplete());
+        assert_eq!(entry.pending(), 2);
+        assert_eq!(table.get_mac_address(&ip("10.0.0.2"), now), None);
+
+        let released = table.learn(ip("10.0.0.2"), mac("00:11:22:33:44:55"), now);
+        assert_eq!(identifications(&released), [1, 2]);
+        let entry = table.entry(&ip("10.0.0.2")).unwrap();
+        assert!(!entry.is_incomplete());
+        assert_eq!(entry.entry_type, ArpEntryType::Dynamic);
+        assert_eq!(entry.pending(), 0);
+        assert_eq!(
+            table.get_mac_address(&ip("10.0.0.2"), now),
+            Some(mac("00:11:22:33:44:55"))
+        );
+    }
+
+    #[test]
+    fn drops_packets_beyond_max_pending() {
+        let mut table = ArpTable::new();
+        for identification in 1..=5 {
+            table.queue_packet(ip("10.0.0.2"), packet(identification), Duration::ZERO);
+        }
+        assert_eq!(
+            table.entry(&ip("10.0.0.2")).unwrap().pending(),
+            ArpTable::MAX_PENDING
+        );
+        let released = table.learn(ip("10.0.0.2"), mac("00:11:22:33:44:55"), Duration::ZERO);
+        assert_eq!(identifications(&released), [1, 2, 3]);
+    }
+
+    #[test]
+    fn retries_requests_then_gives_up() {
+        let mut table = ArpTable::new();
+        table.set_retries(3, Duration::from_secs(1));
+        table.queue_packet(ip("10.0.0.2"), packet(1), Duration::ZERO);
+        assert_eq!(table.next_wakeup(), Some(Duration::from_secs(1)));
+        // Already scheduled
+        assert_eq!(table.next_wakeup(), None);
+
+        let update = table.update(Duration::from_millis(500));
+        assert!(update.retries.is_empty() && update.dropped.is_empty());
+
+        for (second, requests) in [(1, 2), (2, 3)] {
+            let update = table.update(Duration::from_secs(second));
+            assert_eq!(update.retries, [ip("10.0.0.2")]);
+            assert!(update.dropped.is_empty());
+            assert_eq!(table.entry(&ip("10.0.0.2")).unwrap().requests, requests);
+        }
+        assert_eq!(table.next_wakeup(), Some(Duration::from_secs(3)));
+
+        let update = table.update(Duration::from_secs(3));
+        assert!(update.retries.is_empty());
+        assert_eq!(identifications(&update.dropped), [1]);
+        assert!(table.is_empty());
+        assert_eq!(table.next_wakeup(), None);
+    }
+
+    #[test]
+    fn keeps_static_entries() {
+        let mut table = ArpTable::new();
+        table.add_static_entry(ip("10.0.0.3"), mac("00:11:22:33:44:66"));
+        table.learn(ip("10.0.0.3"), mac("00:11:22:33:44:77"), Duration::ZERO);
+        table.learn(ip("10.0.0.2"), mac("00:11:22:33:44:55"), Duration::ZERO);
+        table.queue_packet(ip("10.0.0.4"), packet(1), Duration::ZERO);
+        assert_eq!(table.len(), 3);
+
+        table.clear();
+        assert_eq!(table.len(), 1);
+        let entry = table.entry(&ip("10.0.0.3")).unwrap();
+        assert_eq!(entry.entry_type, ArpEntryType::Static);
+        assert_eq!(entry.mac, Some(mac("00:11:22:33:44:66")));
+    }
+}
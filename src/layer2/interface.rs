@@ -1,39 +1,104 @@
 use super::{
     address::MacAddress,
-    arp::{ArpOperation, ArpTable},
+    arp::{ArpOperation, ArpPacket, ArpTable},
     pdu::{EthernetFrame, EthernetPayload},
+    qos::EgressScheduler,
 };
-use crate::layer3::address::{IpAddr, Ipv4Addr, Ipv6Addr};
+use crate::layer1::autoneg::{Duplex, DuplexSetting, LinkMode, PortAbility, Speed, SpeedSetting};
+use crate::layer1::csma::{CollisionOutcome, CsmaCd};
+use crate::layer1::transmission_time;
+use crate::layer3::address::{IpAddr, Ipv4Addr, Ipv4Interface, Ipv6Addr, Ipv6Interface};
+use crate::layer3::icmp;
+use crate::layer3::icmpv6::{Icmpv6Message, PrefixInformation};
+use crate::layer3::ndp::{
+    eui64_address, link_local_address, NeighborCache, RouterAdvertisements, TentativeAddress,
+};
+use crate::layer3::pdu::{Ipv4Packet, Ipv6Packet};
 use bevy::prelude::*;
 use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Component)]
 pub struct SourceInterface;
 #[derive(Component)]
 pub struct DestinationInterface;
 
+/// How much a queue holds before it starts dropping: a number of bytes, counting frames
+/// by their encoded size, or a number of frames regardless of their size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueCapacity {
+    Bytes(usize),
+    Frames(usize),
+}
+
+// Anything that takes up room in a queue
+pub trait QueueItem {
+    // Size in bytes counted against a byte capacity
+    fn size(&self) -> usize;
+}
+
+impl QueueItem for EthernetFrame {
+    fn size(&self) -> usize {
+        self.to_bytes().len()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QueueStats {
+    pub enqueued: u64,
+    // Items refused because the queue was full
+    pub dropped: u64,
+    // Most bytes and frames the queue has held at once
+    pub high_watermark_bytes: usize,
+    pub high_watermark_frames: usize,
+}
+
+#[derive(Debug, Clone)]
 pub struct Queue<T> {
     elements: VecDeque<T>,
-    capacity: u32,
+    capacity: QueueCapacity,
+    // Total size of the queued items
+    bytes: usize,
+    pub stats: QueueStats,
 }
 
-impl<T> Queue<T> {
+impl<T: QueueItem> Queue<T> {
     // Creates a new empty queue
-    pub fn new(capacity: u32) -> Self {
+    pub fn new(capacity: QueueCapacity) -> Self {
         Queue {
             elements: VecDeque::new(),
             capacity,
+            bytes: 0,
+            stats: QueueStats::default(),
         }
     }
 
-    // Adds an item to the back of the queue
-    pub fn enqueue(&mut self, item: T) {
+    /// Adds an item to the back of the queue, or drops it if it does not fit (tail drop).
+    /// Returns the dropped item.
+    pub fn enqueue(&mut self, item: T) -> Result<(), T> {
+        let size = item.size();
+        let fits = match self.capacity {
+            QueueCapacity::Bytes(capacity) => self.bytes + size <= capacity,
+            QueueCapacity::Frames(capacity) => self.elements.len() < capacity,
+        };
+        if !fits {
+            self.stats.dropped += 1;
+            return Err(item);
+        }
+        self.bytes += size;
         self.elements.push_back(item);
+        self.stats.enqueued += 1;
+        self.stats.high_watermark_bytes = self.stats.high_watermark_bytes.max(self.bytes);
+        self.stats.high_watermark_frames = self.stats.high_watermark_frames.max(self.len());
+        Ok(())
     }
 
     // Removes an item from the front of the queue
     pub fn dequeue(&mut self) -> Option<T> {
-        self.elements.pop_front()
+        let item = self.elements.pop_front()?;
+        self.bytes -= item.size();
+        Some(item)
     }
 
     // Checks if the queue is empty
@@ -46,6 +111,21 @@ impl<T> Queue<T> {
         self.elements.len()
     }
 
+    // Returns the total size of the items in the queue
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // Returns the configured capacity of the queue
+    pub fn capacity(&self) -> QueueCapacity {
+        self.capacity
+    }
+
+    // Changes the capacity. Items already queued stay even if they no longer fit.
+    pub fn set_capacity(&mut self, capacity: QueueCapacity) {
+        self.capacity = capacity;
+    }
+
     // Peeks at the first item in the queue without removing it
     pub fn peek(&self) -> Option<&T> {
         self.elements.front()
@@ -59,6 +139,28 @@ pub enum InterfaceType {
     TenGigabitEthernet,
 }
 
+impl InterfaceType {
+    // Line rate in bits per second
+    pub fn bandwidth(&self) -> u64 {
+        match self {
+            InterfaceType::FastEthernet => 100_000_000,
+            InterfaceType::GigabitEthernet => 1_000_000_000,
+            InterfaceType::TenGigabitEthernet => 10_000_000_000,
+        }
+    }
+
+    // Speeds the hardware can run at
+    pub fn speeds(&self) -> Vec<Speed> {
+        match self {
+            InterfaceType::FastEthernet => vec![Speed::Ten, Speed::Hundred],
+            InterfaceType::GigabitEthernet => vec![Speed::Ten, Speed::Hundred, Speed::Thousand],
+            InterfaceType::TenGigabitEthernet => vec![Speed::Thousand, Speed::TenThousand],
+        }
+    }
+}
+
+// Each interface is a component of its own entity, so the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Component)]
 pub enum Interface {
     Ethernet(EthernetInterface),
@@ -76,64 +178,543 @@ impl Interface {
     }
 }
 
+/// State of an interface as shown by "show interfaces", also used for its line protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterfaceStatus {
+    Up,
+    Down,
+    // Disabled with "shutdown"
+    AdministrativelyDown,
+}
+
+impl fmt::Display for InterfaceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceStatus::Up => f.pad("up"),
+            InterfaceStatus::Down => f.pad("down"),
+            InterfaceStatus::AdministrativelyDown => f.pad("administratively down"),
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct EthernetInterface {
     pub interface_type: InterfaceType,
+    // Disabled with "shutdown": the link goes down at both ends and nothing is sent or received
+    shutdown: bool,
+    // Configured speed and duplex, negotiated with the other end when automatic
+    pub speed: SpeedSetting,
+    pub duplex: DuplexSetting,
+    // Speed and duplex in use, once the link is up
+    pub link_mode: Option<LinkMode>,
+    // Set when the settings changed and the link must negotiate again
+    autoneg_pending: bool,
+    // Status last reported as a link state change
+    reported_status: InterfaceStatus,
     pub device: Option<Entity>,
     pub mac_address: MacAddress,
-    pub ipv4_address: Option<Ipv4Addr>,
-    pub ipv6_addresses: Vec<Ipv6Addr>,
+    // Primary address, the source of the packets the interface originates
+    pub ipv4_address: Option<Ipv4Interface>,
+    // Additional addresses, e.g. "ip address 10.0.0.1 255.255.255.0 secondary"
+    pub secondary_ipv4_addresses: Vec<Ipv4Interface>,
+    // Where the interface sends packets for destinations outside its own network
+    pub default_gateway: Option<Ipv4Addr>,
+    // Largest IP packet the interface sends without fragmenting
+    pub mtu: u16,
+    // Usable IPv6 addresses, link-local included
+    pub ipv6_addresses: Vec<Ipv6Interface>,
+    // Addresses still undergoing duplicate address detection
+    pub tentative_ipv6_addresses: Vec<TentativeAddress>,
+    // Addresses given up because another node already uses them
+    pub duplicate_ipv6_addresses: Vec<Ipv6Addr>,
+    // Default router learned from router advertisements
+    pub ipv6_default_gateway: Option<Ipv6Addr>,
+    pub neighbor_cache: NeighborCache,
+    // Set on the interfaces of IPv6 routers, which advertise their prefixes
+    pub router_advertisements: Option<RouterAdvertisements>,
+    scheduled_ipv6_wakeup: Option<Duration>,
     pub arp_table: ArpTable,
+    // Answer ARP requests for addresses reachable through another interface of the device
+    pub proxy_arp: bool,
+    // Address conflicts detected since they were last reported, as (address, MAC address of
+    // the other station)
+    pub address_conflicts: Vec<(Ipv4Addr, MacAddress)>,
+    // Simulation time at which the address was last defended against a conflicting station
+    last_defense: Option<Duration>,
     pub in_queue: Queue<EthernetFrame>,
     pub out_queue: Queue<EthernetFrame>,
+    // Queuing policy replacing the out_queue FIFO, e.g. priority queuing for voice
+    pub egress_scheduler: Option<EgressScheduler>,
+    // Simulation time at which the transmitter started and finishes sending the current frame
+    pub tx_started_at: Duration,
+    pub tx_busy_until: Duration,
+    // Collision handling when the interface shares a half-duplex medium
+    pub csma_cd: CsmaCd,
+    pub stats: InterfaceStats,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceStats {
+    pub tx_frames: u64,
+    pub rx_frames: u64,
+    // Frames dropped on receive because the FCS did not match
+    pub rx_crc_errors: u64,
+    pub collisions: u64,
+    // Collisions detected after the collision window, whose frames are lost
+    pub late_collisions: u64,
+    // Frames given up after colliding on every attempt
+    pub excessive_collisions: u64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
     In,
     Out,
 }
 
 impl EthernetInterface {
+    pub const DEFAULT_MTU: u16 = 1500;
+    // Minimum time between two defenses of the address (RFC 5227)
+    pub const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
     pub fn new(interface_type: InterfaceType) -> Self {
         Self {
-            interface_type: interface_type,
+            interface_type,
+            shutdown: false,
+            speed: SpeedSetting::Auto,
+            duplex: DuplexSetting::Auto,
+            link_mode: None,
+            autoneg_pending: true,
+            reported_status: InterfaceStatus::Down,
             device: None,
             mac_address: MacAddress::random(),
             ipv4_address: None,
+            secondary_ipv4_addresses: Vec::new(),
+            default_gateway: None,
+            mtu: Self::DEFAULT_MTU,
             ipv6_addresses: Vec::new(),
+            tentative_ipv6_addresses: Vec::new(),
+            duplicate_ipv6_addresses: Vec::new(),
+            ipv6_default_gateway: None,
+            neighbor_cache: NeighborCache::new(),
+            router_advertisements: None,
+            scheduled_ipv6_wakeup: None,
             arp_table: ArpTable::new(),
-            in_queue: Queue::new(0x2000000),  // 32 MB
-            out_queue: Queue::new(0x2000000), // 32 MB
+            proxy_arp: false,
+            address_conflicts: Vec::new(),
+            last_defense: None,
+            in_queue: Queue::new(QueueCapacity::Bytes(0x2000000)), // 32 MB
+            out_queue: Queue::new(QueueCapacity::Bytes(0x2000000)), // 32 MB
+            egress_scheduler: None,
+            tx_started_at: Duration::ZERO,
+            tx_busy_until: Duration::ZERO,
+            csma_cd: CsmaCd::new(),
+            stats: InterfaceStats::default(),
+        }
+    }
+
+    // "speed". The link goes down and negotiates again.
+    pub fn set_speed(&mut self, speed: SpeedSetting) {
+        self.speed = speed;
+        self.restart_autonegotiation();
+    }
+
+    // "duplex". The link goes down and negotiates again.
+    pub fn set_duplex(&mut self, duplex: DuplexSetting) {
+        self.duplex = duplex;
+        self.restart_autonegotiation();
+    }
+
+    // "shutdown". The link goes down, at the other end too.
+    pub fn shutdown(&mut self) {
+        self.shutdown = true;
+        self.restart_autonegotiation();
+    }
+
+    // "no shutdown". The link comes up once negotiated with the other end.
+    pub fn no_shutdown(&mut self) {
+        self.shutdown = false;
+        self.restart_autonegotiation();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
+    pub fn restart_autonegotiation(&mut self) {
+        if self.link_mode.take().is_some() {
+            self.link_down();
+        }
+        self.autoneg_pending = true;
+    }
+
+    pub fn needs_autonegotiation(&self) -> bool {
+        self.autoneg_pending
+    }
+
+    // What the interface advertises when negotiating
+    pub fn ability(&self) -> PortAbility {
+        PortAbility {
+            speeds: self.interface_type.speeds(),
+            speed: self.speed,
+            duplex: self.duplex,
+        }
+    }
+
+    // Records the outcome of the negotiation; None leaves the link down
+    pub fn set_link_mode(&mut self, link_mode: Option<LinkMode>) {
+        if self.link_mode.is_some() && link_mode.is_none() {
+            self.link_down();
+        }
+        self.link_mode = link_mode;
+        self.autoneg_pending = false;
+    }
+
+    // Frames waiting to go out are lost with the link, and so are the neighbors learned on it
+    fn link_down(&mut self) {
+        while self.dequeue_frame(Direction::Out).is_some() {}
+        self.csma_cd = CsmaCd::new();
+        self.arp_table.clear();
+        self.neighbor_cache.clear();
+    }
+
+    // Whether the interface is enabled and its link is up
+    pub fn is_up(&self) -> bool {
+        !self.shutdown && self.link_mode.is_some()
+    }
+
+    pub fn status(&self) -> InterfaceStatus {
+        match (self.shutdown, self.link_mode) {
+            (true, _) => InterfaceStatus::AdministrativelyDown,
+            (false, Some(_)) => InterfaceStatus::Up,
+            (false, None) => InterfaceStatus::Down,
+        }
+    }
+
+    // Ethernet has no keepalive failures here: the line protocol follows the link
+    pub fn line_protocol(&self) -> InterfaceStatus {
+        if self.is_up() {
+            InterfaceStatus::Up
+        } else {
+            InterfaceStatus::Down
+        }
+    }
+
+    // Returns the previous and the new status if it changed since it was last taken
+    pub fn take_status_change(&mut self) -> Option<(InterfaceStatus, InterfaceStatus)> {
+        let status = self.status();
+        if status == self.reported_status {
+            return None;
+        }
+        Some((std::mem::replace(&mut self.reported_status, status), status))
+    }
+
+    // Bits per second the interface sends at: the negotiated speed, else the configured
+    // one, else the fastest the hardware supports
+    pub fn line_rate(&self) -> u64 {
+        match (self.link_mode, self.speed) {
+            (Some(mode), _) => mode.speed.bits_per_second(),
+            (None, SpeedSetting::Forced(speed)) => speed.bits_per_second(),
+            (None, SpeedSetting::Auto) => self.interface_type.bandwidth(),
         }
     }
 
-    pub fn set_ipv4_address(&mut self, ipv4_address: Ipv4Addr) {
+    pub fn is_half_duplex(&self) -> bool {
+        self.link_mode
+            .is_some_and(|mode| mode.duplex == Duplex::Half)
+    }
+
+    // Assigns the address and announces it with a gratuitous ARP, which also reveals
+    // any other station already using it
+    pub fn set_ipv4_address(&mut self, ipv4_address: Ipv4Interface) {
+        self.secondary_ipv4_addresses
+            .retain(|secondary| secondary.address != ipv4_address.address);
         self.ipv4_address = Some(ipv4_address);
+        self.announce(ipv4_address.address);
+    }
+
+    // Adds a secondary address, announced like the primary one
+    pub fn add_secondary_ipv4_address(&mut self, ipv4_address: Ipv4Interface) {
+        if self.has_ipv4_address(&ipv4_address.address) {
+            return;
+        }
+        self.secondary_ipv4_addresses.push(ipv4_address);
+        self.announce(ipv4_address.address);
+    }
+
+    pub fn remove_secondary_ipv4_address(&mut self, address: &Ipv4Addr) {
+        self.secondary_ipv4_addresses
+            .retain(|secondary| secondary.address != *address);
+    }
+
+    // Primary address first, then the secondary ones
+    pub fn ipv4_addresses(&self) -> impl Iterator<Item = &Ipv4Interface> {
+        self.ipv4_address
+            .iter()
+            .chain(self.secondary_ipv4_addresses.iter())
+    }
+
+    pub fn has_ipv4_address(&self, address: &Ipv4Addr) -> bool {
+        self.ipv4_addresses()
+            .any(|ipv4_address| ipv4_address.address == *address)
+    }
+
+    /// Address to use when talking to `destination`: the one on the same network if any,
+    /// the primary address otherwise
+    pub fn source_address(&self, destination: &Ipv4Addr) -> Option<Ipv4Addr> {
+        self.ipv4_addresses()
+            .find(|ipv4_address| ipv4_address.contains(destination))
+            .or(self.ipv4_address.as_ref())
+            .map(|ipv4_address| ipv4_address.address)
+    }
+
+    // Announces every address of the interface
+    pub fn send_gratuitous_arp(&mut self) {
+        let addresses: Vec<Ipv4Addr> = self.ipv4_addresses().map(|ip| ip.address).collect();
+        for address in addresses {
+            self.announce(address);
+        }
+    }
+
+    fn announce(&mut self, address: Ipv4Addr) {
+        let frame = EthernetFrame::arp_request(self.mac_address.clone(), address, address);
+        self.enqueue_frame(frame, Direction::Out);
+    }
+
+    // Drains the address conflicts detected so far
+    pub fn take_address_conflicts(&mut self) -> Vec<(Ipv4Addr, MacAddress)> {
+        std::mem::take(&mut self.address_conflicts)
+    }
+
+    pub fn set_default_gateway(&mut self, gateway: Ipv4Addr) {
+        self.default_gateway = Some(gateway);
+    }
+
+    // Whether `address` is on one of the networks the interface is attached to
+    pub fn is_on_link(&self, address: &Ipv4Addr) -> bool {
+        self.ipv4_addresses()
+            .any(|ipv4_address| ipv4_address.contains(address))
+    }
+
+    /// Enables IPv6 on the interface ("ipv6 enable"), configuring its link-local address.
+    /// Like every address, it becomes usable once duplicate address detection completes.
+    pub fn enable_ipv6(&mut self) {
+        if !self.ipv6_enabled() {
+            self.add_tentative_address(link_local_address(&self.mac_address));
+        }
+    }
+
+    // Adds an address, enabling IPv6 if needed
+    pub fn add_ipv6_address(&mut self, ipv6_address: Ipv6Interface) {
+        self.enable_ipv6();
+        self.add_tentative_address(ipv6_address);
+    }
+
+    fn add_tentative_address(&mut self, address: Ipv6Interface) {
+        let known = self.has_ipv6_address(&address.address)
+            || self.is_tentative(&address.address)
+            || self.duplicate_ipv6_addresses.contains(&address.address);
+        if !known {
+            self.tentative_ipv6_addresses.push(TentativeAddress {
+                address,
+                completes_at: None,
+            });
+        }
+    }
+
+    // Makes the interface an IPv6 router advertising the prefixes of its addresses
+    pub fn enable_router_advertisements(&mut self) {
+        self.enable_ipv6();
+        self.router_advertisements
+            .get_or_insert_with(RouterAdvertisements::new);
+    }
+
+    pub fn ipv6_enabled(&self) -> bool {
+        !self.ipv6_addresses.is_empty() || !self.tentative_ipv6_addresses.is_empty()
+    }
+
+    pub fn has_ipv6_address(&self, address: &Ipv6Addr) -> bool {
+        self.ipv6_addresses
+            .iter()
+            .any(|ipv6_address| ipv6_address.address == *address)
+    }
+
+    pub fn is_tentative(&self, address: &Ipv6Addr) -> bool {
+        self.tentative_ipv6_addresses
+            .iter()
+            .any(|tentative| tentative.address.address == *address)
+    }
+
+    pub fn link_local_ipv6_address(&self) -> Option<Ipv6Addr> {
+        self.ipv6_addresses
+            .iter()
+            .map(|ipv6_address| ipv6_address.address)
+            .find(|address| address.is_link_local())
+    }
+
+    /// Address to use when talking to `destination`: the link-local address for link-local
+    /// and multicast destinations, otherwise one on the same prefix or any global one
+    pub fn ipv6_source_address(&self, destination: &Ipv6Addr) -> Option<Ipv6Addr> {
+        if destination.is_link_local() || destination.is_multicast() {
+            return self.link_local_ipv6_address();
+        }
+        let global = || {
+            self.ipv6_addresses
+                .iter()
+                .find(|ipv6_address| !ipv6_address.address.is_link_local())
+        };
+        self.ipv6_addresses
+            .iter()
+            .find(|ipv6_address| ipv6_address.contains(destination))
+            .or_else(global)
+            .map(|ipv6_address| ipv6_address.address)
+            .or_else(|| self.link_local_ipv6_address())
     }
 
-    pub fn add_ipv6_address(&mut self, ipv6_address: Ipv6Addr) {
-        self.ipv6_addresses.push(ipv6_address);
+    // Whether `address` is reached directly on the link
+    pub fn is_ipv6_on_link(&self, address: &Ipv6Addr) -> bool {
+        address.is_link_local()
+            || self
+                .ipv6_addresses
+                .iter()
+                .any(|ipv6_address| ipv6_address.contains(address))
     }
 
-    pub fn enqueue_frame(&mut self, frame: EthernetFrame, direction: Direction) {
-        match direction {
-            Direction::In => self.in_queue.enqueue(frame),
-            Direction::Out => self.out_queue.enqueue(frame),
+    // Multicast groups the interface listens to: all nodes, all routers on routers, and the
+    // solicited-node group of every address, tentative ones included
+    fn ipv6_groups(&self) -> Vec<Ipv6Addr> {
+        if !self.ipv6_enabled() {
+            return Vec::new();
+        }
+        let mut groups = vec![Ipv6Addr::ALL_NODES];
+        if self.router_advertisements.is_some() {
+            groups.push(Ipv6Addr::ALL_ROUTERS);
+        }
+        let addresses = self
+            .ipv6_addresses
+            .iter()
+            .chain(self.tentative_ipv6_addresses.iter().map(|t| &t.address));
+        groups.extend(addresses.map(|a| a.address.solicited_node_multicast()));
+        groups
+    }
+
+    // Whether a frame sent to `dest` is meant for this interface
+    pub fn accepts_frame(&self, dest: &MacAddress) -> bool {
+        *dest == self.mac_address
+            || dest.is_broadcast()
+            || self
+                .ipv6_groups()
+                .iter()
+                .any(|group| MacAddress::ipv6_multicast(group) == *dest)
+    }
+
+    // Queues a frame, dropping it if the queue is full or the interface is shut down.
+    // Returns whether it was queued.
+    pub fn enqueue_frame(&mut self, frame: EthernetFrame, direction: Direction) -> bool {
+        if self.shutdown {
+            return false;
+        }
+        let result = match (direction, self.egress_scheduler.as_mut()) {
+            (Direction::In, _) => self.in_queue.enqueue(frame),
+            (Direction::Out, Some(scheduler)) => scheduler.enqueue(frame),
+            (Direction::Out, None) => self.out_queue.enqueue(frame),
+        };
+        match result {
+            Ok(()) => true,
+            Err(frame) => {
+                println!(
+                    "\nQueue full, dropping frame from {} to {}",
+                    frame.src, frame.dest
+                );
+                false
+            }
         }
     }
 
     pub fn dequeue_frame(&mut self, direction: Direction) -> Option<EthernetFrame> {
-        match direction {
-            Direction::In => self.in_queue.dequeue(),
-            Direction::Out => self.out_queue.dequeue(),
+        match (direction, self.egress_scheduler.as_mut()) {
+            (Direction::In, _) => self.in_queue.dequeue(),
+            (Direction::Out, Some(scheduler)) => scheduler.dequeue(),
+            (Direction::Out, None) => self.out_queue.dequeue(),
+        }
+    }
+
+    /// Takes the next outgoing frame if the transmitter is idle at `now`.
+    /// The transmitter is then busy until the frame has been serialized onto the wire
+    /// at `bandwidth` bits per second.
+    pub fn start_transmission(
+        &mut self,
+        now: Duration,
+        bandwidth: u64,
+    ) -> Option<(EthernetFrame, Duration)> {
+        if now < self.tx_busy_until {
+            return None;
+        }
+        let frame = self.dequeue_frame(Direction::Out)?;
+        self.tx_started_at = now;
+        self.tx_busy_until = now + transmission_time(frame.wire_size(), bandwidth);
+        self.stats.tx_frames += 1;
+        Some((frame, self.tx_busy_until))
+    }
+
+    /// Takes the frame to send on a shared half-duplex medium if the transmitter is idle and
+    /// not backing off: the one waiting to be retried after a collision, or the next queued
+    pub fn next_half_duplex_frame(&mut self, now: Duration) -> Option<EthernetFrame> {
+        if now < self.tx_busy_until || self.csma_cd.is_backing_off(now) {
+            return None;
+        }
+        self.csma_cd
+            .frame
+            .take()
+            .or_else(|| self.dequeue_frame(Direction::Out))
+    }
+
+    /// Gives back a frame taken with `next_half_duplex_frame` because the medium is busy
+    pub fn defer_frame(&mut self, frame: EthernetFrame) {
+        self.csma_cd.frame = Some(frame);
+    }
+
+    /// Handles a collision of `frame`, sent from `started` and detected at `detected`: the
+    /// interface sends the jam signal, then backs off to retry or drops the frame.
+    /// Returns when the interface is free to transmit again.
+    pub fn collision(
+        &mut self,
+        frame: EthernetFrame,
+        started: Duration,
+        detected: Duration,
+        bandwidth: u64,
+    ) -> Duration {
+        let jam_end = detected + CsmaCd::jam_time(bandwidth);
+        self.tx_busy_until = jam_end;
+        self.stats.collisions += 1;
+        let outcome = self.csma_cd.collision(
+            frame,
+            started,
+            detected,
+            jam_end,
+            bandwidth,
+            &mut rand::thread_rng(),
+        );
+        match outcome {
+            CollisionOutcome::Retry { backoff_until } => backoff_until,
+            CollisionOutcome::LateCollision => {
+                self.stats.late_collisions += 1;
+                println!("\nLate collision, frame dropped");
+                jam_end
+            }
+            CollisionOutcome::ExcessiveCollisions => {
+                self.stats.excessive_collisions += 1;
+                println!("\nExcessive collisions, frame dropped");
+                jam_end
+            }
         }
     }
 
     pub fn send_arp_request(&mut self, target_ip: Ipv4Addr) {
-        if let Some(int_address) = &self.ipv4_address {
+        if let Some(int_address) = self.source_address(&target_ip) {
             let arp_frame = EthernetFrame::arp_request(
                 self.mac_address.clone(),
-                *int_address,
+                int_address,
                 target_ip,
             );
             self.enqueue_frame(arp_frame, Direction::Out);
@@ -141,54 +722,559 @@ impl EthernetInterface {
             println!("Interface does not have an IP address");}
     }
 
-    pub fn process_frame(&mut self, frame: &EthernetFrame) {
+    /// Sends an IPv4 packet to `next_hop`, a neighbor on the attached network.
+    /// Without an ARP entry for the neighbor the packet waits in the ARP table while a
+    /// request goes out, and is sent once the reply arrives.
+    /// Returns whether the packet was sent right away.
+    pub fn send_ipv4_packet(
+        &mut self,
+        packet: Ipv4Packet,
+        next_hop: Ipv4Addr,
+        now: Duration,
+    ) -> bool {
+        let dest = if next_hop.is_broadcast() {
+            Some(MacAddress::broadcast())
+        } else {
+            self.arp_table.get_mac_address(&next_hop, now)
+        };
+        match dest {
+            Some(dest) => {
+                let frame = EthernetFrame::ipv4(self.mac_address.clone(), dest, packet);
+                self.enqueue_frame(frame, Direction::Out);
+                true
+            }
+            None => {
+                if self.arp_table.queue_packet(next_hop, packet, now) {
+                    self.send_arp_request(next_hop);
+                }
+                false
+            }
+        }
+    }
+
+    /// Sends a packet originated by this host: straight to the destination when it is on the
+    /// attached network, through the default gateway otherwise.
+    /// Returns whether the packet was sent right away.
+    pub fn originate_ipv4_packet(&mut self, packet: Ipv4Packet, now: Duration) -> bool {
+        let dest = packet.header.dest;
+        let next_hop = if dest.is_broadcast() || self.is_on_link(&dest) {
+            Some(dest)
+        } else {
+            self.default_gateway
+        };
+        match next_hop {
+            Some(next_hop) => self.send_ipv4_packet(packet, next_hop, now),
+            None => {
+                println!("No route to host {}", dest);
+                false
+            }
+        }
+    }
+
+    // Records the address of a neighbor and sends the packets that were waiting for it
+    fn learn_neighbor(&mut self, ip: Ipv4Addr, mac: MacAddress, now: Duration) {
+        for packet in self.arp_table.learn(ip, mac.clone(), now) {
+            let frame = EthernetFrame::ipv4(self.mac_address.clone(), mac.clone(), packet);
+            self.enqueue_frame(frame, Direction::Out);
+        }
+    }
+
+    /// Retries the ARP requests that went unanswered and ages out the table.
+    /// Returns the packets dropped because their next hop never answered.
+    pub fn update_arp(&mut self, now: Duration) -> Vec<Ipv4Packet> {
+        let update = self.arp_table.update(now);
+        for ip in update.retries {
+            self.send_arp_request(ip);
+        }
+        update.dropped
+    }
+
+    /// Sends an IPv6 packet to `next_hop`, a neighbor on the attached link or a multicast
+    /// group. Without a neighbor cache entry the packet waits while a solicitation goes out.
+    /// Returns whether the packet was sent right away.
+    pub fn send_ipv6_packet(
+        &mut self,
+        packet: Ipv6Packet,
+        next_hop: Ipv6Addr,
+        now: Duration,
+    ) -> bool {
+        let dest = if next_hop.is_multicast() {
+            Some(MacAddress::ipv6_multicast(&next_hop))
+        } else {
+            self.neighbor_cache.lookup(&next_hop, now)
+        };
+        match dest {
+            Some(dest) => {
+                let frame = EthernetFrame::ipv6(self.mac_address.clone(), dest, packet);
+                self.enqueue_frame(frame, Direction::Out);
+                true
+            }
+            None => {
+                if self.neighbor_cache.queue_packet(next_hop, packet, now) {
+                    self.send_neighbor_solicitation(next_hop, None);
+                }
+                false
+            }
+        }
+    }
+
+    /// Sends an IPv6 packet originated by this host: straight to the destination when it is
+    /// on-link, through the default router otherwise.
+    /// Returns whether the packet was sent right away.
+    pub fn originate_ipv6_packet(&mut self, packet: Ipv6Packet, now: Duration) -> bool {
+        let dest = packet.dest;
+        let next_hop = if dest.is_multicast() || self.is_ipv6_on_link(&dest) {
+            Some(dest)
+        } else {
+            self.ipv6_default_gateway
+        };
+        match next_hop {
+            Some(next_hop) => self.send_ipv6_packet(packet, next_hop, now),
+            None => {
+                println!("No route to host {}", dest);
+                false
+            }
+        }
+    }
+
+    // Asks for the link-layer address of `target`: to its solicited-node group while
+    // resolving, or straight to the known address when probing reachability
+    fn send_neighbor_solicitation(&mut self, target: Ipv6Addr, known: Option<MacAddress>) {
+        let Some(src) = self.ipv6_source_address(&target) else {
+            return;
+        };
+        let message = Icmpv6Message::NeighborSolicitation {
+            target,
+            source_link_layer: Some(self.mac_address.clone()),
+        };
+        let (dest, dest_mac) = match known {
+            Some(mac) => (target, mac),
+            None => {
+                let group = target.solicited_node_multicast();
+                (group, MacAddress::ipv6_multicast(&group))
+            }
+        };
+        let packet = Ipv6Packet::icmpv6(src, dest, &message);
+        let frame = EthernetFrame::ipv6(self.mac_address.clone(), dest_mac, packet);
+        self.enqueue_frame(frame, Direction::Out);
+    }
+
+    // Asks whether anyone already uses `target`, from the unspecified address (RFC 4862)
+    fn send_duplicate_address_probe(&mut self, target: Ipv6Addr) {
+        let message = Icmpv6Message::NeighborSolicitation {
+            target,
+            source_link_layer: None,
+        };
+        let group = target.solicited_node_multicast();
+        let packet = Ipv6Packet::icmpv6(Ipv6Addr::UNSPECIFIED, group, &message);
+        let frame = EthernetFrame::ipv6(
+            self.mac_address.clone(),
+            MacAddress::ipv6_multicast(&group),
+            packet,
+        );
+        self.enqueue_frame(frame, Direction::Out);
+    }
+
+    fn send_neighbor_advertisement(&mut self, target: Ipv6Addr, dest: Ipv6Addr, now: Duration) {
+        let message = Icmpv6Message::NeighborAdvertisement {
+            router: self.router_advertisements.is_some(),
+            solicited: !dest.is_multicast(),
+            override_flag: true,
+            target,
+            target_link_layer: Some(self.mac_address.clone()),
+        };
+        self.send_ipv6_packet(Ipv6Packet::icmpv6(target, dest, &message), dest, now);
+    }
+
+    fn send_router_solicitation(&mut self, now: Duration) {
+        let Some(src) = self.link_local_ipv6_address() else {
+            return;
+        };
+        let message = Icmpv6Message::RouterSolicitation {
+            source_link_layer: Some(self.mac_address.clone()),
+        };
+        let packet = Ipv6Packet::icmpv6(src, Ipv6Addr::ALL_ROUTERS, &message);
+        self.send_ipv6_packet(packet, Ipv6Addr::ALL_ROUTERS, now);
+    }
+
+    // Advertises the prefixes of the global addresses to all nodes
+    fn send_router_advertisement(&mut self, now: Duration) {
+        let Some(src) = self.link_local_ipv6_address() else {
+            return;
+        };
+        let Some(config) = self.router_advertisements.as_mut() else {
+            return;
+        };
+        config.advertised(now);
+        let prefixes = self
+            .ipv6_addresses
+            .iter()
+            .filter(|ipv6_address| !ipv6_address.address.is_link_local())
+            .map(|ipv6_address| PrefixInformation {
+                prefix: ipv6_address.network(),
+                on_link: true,
+                autonomous: ipv6_address.prefix_length() == 64,
+                valid_lifetime: config.valid_lifetime.as_secs() as u32,
+                preferred_lifetime: config.preferred_lifetime.as_secs() as u32,
+            })
+            .collect();
+        let message = Icmpv6Message::RouterAdvertisement {
+            hop_limit: 64,
+            managed: false,
+            other: false,
+            router_lifetime: config.router_lifetime.as_secs().min(u64::from(u16::MAX)) as u16,
+            reachable_time: 0,
+            retrans_timer: 0,
+            source_link_layer: Some(self.mac_address.clone()),
+            mtu: Some(u32::from(self.mtu)),
+            prefixes,
+        };
+        let packet = Ipv6Packet::icmpv6(src, Ipv6Addr::ALL_NODES, &message);
+        self.send_ipv6_packet(packet, Ipv6Addr::ALL_NODES, now);
+    }
+
+    // Sends the packets that were waiting for `neighbor` to be resolved
+    fn release_ipv6_packets(&mut self, neighbor: &Ipv6Addr, packets: Vec<Ipv6Packet>) {
+        let Some(entry) = self.neighbor_cache.entry(neighbor) else {
+            return;
+        };
+        let Some(mac) = entry.mac.clone() else {
+            return;
+        };
+        for packet in packets {
+            let frame = EthernetFrame::ipv6(self.mac_address.clone(), mac.clone(), packet);
+            self.enqueue_frame(frame, Direction::Out);
+        }
+    }
+
+    /// Runs the IPv6 timers: neighbor solicitation retries and reachability probes,
+    /// duplicate address detection and periodic router advertisements.
+    /// Returns the packets dropped because their next hop never answered.
+    pub fn update_ipv6(&mut self, now: Duration) -> Vec<Ipv6Packet> {
+        let update = self.neighbor_cache.update(now);
+        for target in update.solicitations {
+            self.send_neighbor_solicitation(target, None);
+        }
+        for (target, mac) in update.probes {
+            self.send_neighbor_solicitation(target, Some(mac));
+        }
+
+        let retrans_timer = self.neighbor_cache.retrans_timer();
+        for i in 0..self.tentative_ipv6_addresses.len() {
+            let tentative = self.tentative_ipv6_addresses[i];
+            if tentative.completes_at.is_none() {
+                self.tentative_ipv6_addresses[i].completes_at = Some(now + retrans_timer);
+                self.send_duplicate_address_probe(tentative.address.address);
+            }
+        }
+        let (completed, tentative): (Vec<_>, Vec<_>) = self
+            .tentative_ipv6_addresses
+            .drain(..)
+            .partition(|tentative| tentative.completes_at.is_some_and(|time| now >= time));
+        self.tentative_ipv6_addresses = tentative;
+        for tentative in completed {
+            let address = tentative.address;
+            println!("\nIPv6 address {} is now usable", address);
+            self.ipv6_addresses.push(address);
+            // Hosts look for routers as soon as they can talk on the link
+            if address.address.is_link_local() && self.router_advertisements.is_none() {
+                self.send_router_solicitation(now);
+            }
+        }
+
+        let advertise = self
+            .router_advertisements
+            .as_ref()
+            .is_some_and(|config| config.is_due(now));
+        if advertise {
+            self.send_router_advertisement(now);
+        }
+        update.dropped
+    }
+
+    // Next time duplicate address detection completes or a router advertisement is due,
+    // if not already scheduled. The neighbor cache schedules its own timers.
+    pub fn next_ipv6_wakeup(&mut self) -> Option<Duration> {
+        let next = self
+            .tentative_ipv6_addresses
+            .iter()
+            .filter_map(|tentative| tentative.completes_at)
+            .chain(
+                self.router_advertisements
+                    .as_ref()
+                    .and_then(|config| config.next_advertisement()),
+            )
+            .min()?;
+        if self.scheduled_ipv6_wakeup == Some(next) {
+            return None;
+        }
+        self.scheduled_ipv6_wakeup = Some(next);
+        Some(next)
+    }
+
+    // Another node uses an address being configured, which is then abandoned (RFC 4862)
+    fn duplicate_detected(&mut self, address: Ipv6Addr) {
+        println!(
+            "\nDuplicate address {} detected, address not configured",
+            address
+        );
+        self.tentative_ipv6_addresses
+            .retain(|tentative| tentative.address.address != address);
+        self.duplicate_ipv6_addresses.push(address);
+    }
+
+    fn process_ipv6_packet(&mut self, packet: &Ipv6Packet, now: Duration) {
+        let dest = packet.dest;
+        if !self.has_ipv6_address(&dest) && !self.ipv6_groups().contains(&dest) {
+            return;
+        }
+        let Some(message) = packet.icmpv6_message() else {
+            println!("Received IPv6 packet: {}", packet);
+            return;
+        };
+        println!("  ICMPv6 from {}: {}", packet.src, message);
+        // Neighbor Discovery messages are only valid if no router forwarded them
+        if packet.hop_limit != 255 {
+            return;
+        }
+        match message {
+            Icmpv6Message::NeighborSolicitation {
+                target,
+                source_link_layer,
+            } => {
+                if self.is_tentative(&target) {
+                    // Another node is probing the same address at the same time
+                    if packet.src.is_unspecified() {
+                        self.duplicate_detected(target);
+                    }
+                    return;
+                }
+                if !self.has_ipv6_address(&target) {
+                    return;
+                }
+                if packet.src.is_unspecified() {
+                    // Defend the address against a node probing for it
+                    self.send_neighbor_advertisement(target, Ipv6Addr::ALL_NODES, now);
+                    return;
+                }
+                if let Some(mac) = source_link_layer {
+                    let released = self.neighbor_cache.learn(packet.src, mac, now);
+                    self.release_ipv6_packets(&packet.src, released);
+                }
+                self.send_neighbor_advertisement(target, packet.src, now);
+            }
+            Icmpv6Message::NeighborAdvertisement {
+                router,
+                solicited,
+                override_flag,
+                target,
+                target_link_layer,
+            } => {
+                if self.is_tentative(&target) {
+                    self.duplicate_detected(target);
+                    return;
+                }
+                let released = self.neighbor_cache.advertised(
+                    target,
+                    target_link_layer,
+                    solicited,
+                    override_flag,
+                    router,
+                    now,
+                );
+                self.release_ipv6_packets(&target, released);
+                if !router && self.ipv6_default_gateway == Some(target) {
+                    self.ipv6_default_gateway = None;
+                }
+            }
+            Icmpv6Message::RouterSolicitation { source_link_layer } => {
+                if self.router_advertisements.is_none() {
+                    return;
+                }
+                if let (Some(mac), false) = (source_link_layer, packet.src.is_unspecified()) {
+                    let released = self.neighbor_cache.learn(packet.src, mac, now);
+                    self.release_ipv6_packets(&packet.src, released);
+                }
+                self.send_router_advertisement(now);
+            }
+            Icmpv6Message::RouterAdvertisement {
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                source_link_layer,
+                prefixes,
+                ..
+            } => {
+                // Routers do not configure themselves from other routers
+                if self.router_advertisements.is_some() || !packet.src.is_link_local() {
+                    return;
+                }
+                let router = packet.src;
+                if let Some(mac) = source_link_layer {
+                    let released = self.neighbor_cache.learn(router, mac, now);
+                    self.release_ipv6_packets(&router, released);
+                }
+                self.neighbor_cache.set_router(&router, true);
+                if reachable_time != 0 || retrans_timer != 0 {
+                    let reachable_time = match reachable_time {
+                        0 => self.neighbor_cache.reachable_time(),
+                        ms => Duration::from_millis(u64::from(ms)),
+                    };
+                    let retrans_timer = match retrans_timer {
+                        0 => self.neighbor_cache.retrans_timer(),
+                        ms => Duration::from_millis(u64::from(ms)),
+                    };
+                    self.neighbor_cache
+                        .set_timers(reachable_time, retrans_timer);
+                }
+                if router_lifetime > 0 {
+                    self.ipv6_default_gateway = Some(router);
+                } else if self.ipv6_default_gateway == Some(router) {
+                    self.ipv6_default_gateway = None;
+                }
+                // Stateless address autoconfiguration (RFC 4862 section 5.5.3)
+                for prefix in prefixes {
+                    let usable = prefix.autonomous
+                        && prefix.prefix.prefix_length() == 64
+                        && !prefix.prefix.address().is_link_local()
+                        && prefix.valid_lifetime > 0
+                        && prefix.preferred_lifetime <= prefix.valid_lifetime;
+                    if !usable {
+                        continue;
+                    }
+                    let address = eui64_address(&prefix.prefix, &self.mac_address);
+                    if let Ok(address) = Ipv6Interface::new(address, 64) {
+                        self.add_tentative_address(address);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn process_frame(&mut self, frame: &EthernetFrame, now: Duration) {
         match &frame.payload {
             EthernetPayload::Dummy => {
                 println!("Received dummy frame");
             }
+            EthernetPayload::ARP(arp) if self.is_conflict(arp) => {
+                println!(
+                    "\nDuplicate address {} claimed by {}",
+                    arp.sender_ip, arp.sender_mac
+                );
+                self.address_conflicts
+                    .push((arp.sender_ip, arp.sender_mac.clone()));
+                // Defend the address, but not so often that two stations keep answering
+                // each other forever
+                let defend = self
+                    .last_defense
+                    .is_none_or(|last| now >= last + Self::DEFEND_INTERVAL);
+                if defend {
+                    self.last_defense = Some(now);
+                    self.send_gratuitous_arp();
+                }
+            }
             EthernetPayload::ARP(arp) => match arp.operation {
                 ArpOperation::Request => {
-                    println!("");
+                    println!();
                     println!("Received ARP request");
                     let target_ip = &arp.target_ip;
                     println!("  Who has IP address {}?", target_ip);
-                    if let Some(int_address) = &self.ipv4_address {
-                        if target_ip == int_address {
+                    if self.ipv4_address.is_some() {
+                        if self.has_ipv4_address(target_ip) {
                             println!("  I have IP address {}", target_ip);
                             let reply_frame = frame.arp_reply(arp, self.mac_address.clone());
                             self.enqueue_frame(reply_frame, Direction::Out);
+                            // The asker is about to talk to us, so we will need its address too
+                            self.learn_neighbor(arp.sender_ip, arp.sender_mac.clone(), now);
                         } else {
                             println!("  I don't have IP address {}", target_ip);
+                            // Refresh what we already know about the asker (RFC 826)
+                            if self.arp_table.contains(&arp.sender_ip, now) {
+                                self.learn_neighbor(arp.sender_ip, arp.sender_mac.clone(), now);
+                            }
                         }
                     }
                 }
                 ArpOperation::Reply => {
-                    println!("");
+                    println!();
                     println!("Received ARP reply");
                     let sender_ip = &arp.sender_ip;
                     let sender_mac = &arp.sender_mac;
                     println!("  {} is at {}", sender_ip, sender_mac);
-                    self.arp_table
-                        .add_entry(sender_ip.clone(), sender_mac.clone());
+                    self.learn_neighbor(*sender_ip, sender_mac.clone(), now);
                 }
             },
-            EthernetPayload::ICMP => {
-                println!("Received ICMP frame");
-            }
             EthernetPayload::IPv4(ip_packet) => {
                 println!("Received IP frame: {:?}", ip_packet);
+                let dest = ip_packet.header.dest;
+                // Replies come from the address the packet was sent to
+                let own = if dest.is_broadcast() {
+                    self.source_address(&ip_packet.header.src)
+                } else {
+                    self.has_ipv4_address(&dest).then_some(dest)
+                };
+                let Some(own) = own else {
+                    return;
+                };
+                if let Some(message) = ip_packet.icmp_message() {
+                    println!("  ICMP from {}: {}", ip_packet.header.src, message);
+                }
+                if let Some(reply) = icmp::reply_to(ip_packet, own) {
+                    self.originate_ipv4_packet(reply, now);
+                }
             }
+            EthernetPayload::IPv6(ip_packet) => self.process_ipv6_packet(ip_packet, now),
             _ => {
                 println!("Received frame with unknown payload");
             }
         }
     }
 
+    // Whether another station claims the address of this interface
+    fn is_conflict(&self, arp: &ArpPacket) -> bool {
+        self.has_ipv4_address(&arp.sender_ip) && arp.sender_mac != self.mac_address
+    }
+
+    /// Formats the state of the interface like the first lines of "show interfaces"
+    pub fn show(&self, interface_name: &str) -> String {
+        let mut output = format!(
+            "{} is {}, line protocol is {}\n",
+            interface_name,
+            self.status(),
+            self.line_protocol()
+        );
+        output.push_str(&format!(
+            "  Hardware is Ethernet, address is {}\n",
+            self.mac_address.to_dotted_string()
+        ));
+        if let Some(address) = &self.ipv4_address {
+            output.push_str(&format!("  Internet address is {}\n", address));
+        }
+        output.push_str(&format!(
+            "  MTU {} bytes, BW {} Kbit/sec\n",
+            self.mtu,
+            self.line_rate() / 1000
+        ));
+        match self.link_mode {
+            Some(mode) => output.push_str(&format!("  {}\n", mode)),
+            None => output.push_str("  Auto-duplex, Auto-speed\n"),
+        }
+        output.push_str(&format!(
+            "     {} packets input, {} CRC\n",
+            self.stats.rx_frames, self.stats.rx_crc_errors
+        ));
+        output.push_str(&format!(
+            "     {} packets output, {} collisions, {} late collision\n",
+            self.stats.tx_frames, self.stats.collisions, self.stats.late_collisions
+        ));
+        output
+    }
+
     /// Short-circuits the queues by moving the first item from the in_queue to the out_queue
     /// This is useful for testing purposes
     pub fn short_circuit_queues(&mut self) {
         if let Some(item) = self.in_queue.dequeue() {
-            self.out_queue.enqueue(item);
+            self.enqueue_frame(item, Direction::Out);
         }
     }
 }
@@ -1,19 +1,29 @@
+use arp::AddressConflict;
 use bevy::prelude::*;
-use systems::{peek_queues, process_frames, update_interfaces};
+use systems::{peek_queues, process_frames, report_address_conflicts, update_interfaces};
 
 pub mod address;
 pub mod arp;
 pub mod interface;
 pub mod pdu;
+pub mod qos;
+pub mod stp;
+pub mod switch;
 pub mod systems;
 
 pub struct Layer2Plugin;
 
 impl Plugin for Layer2Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
+        app.add_event::<AddressConflict>().add_systems(
             FixedUpdate,
-            (peek_queues, update_interfaces, process_frames).chain(),
+            (
+                peek_queues,
+                update_interfaces,
+                process_frames,
+                report_address_conflicts,
+            )
+                .chain(),
         );
     }
 }
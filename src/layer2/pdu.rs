@@ -1,32 +1,92 @@
 use super::address::MacAddress;
 use super::arp::{ArpOperation, ArpPacket};
+use super::stp::Bpdu;
 use crate::layer1::crc::crc32;
 use crate::layer3::{
     address::Ipv4Addr,
-    pdu::{Ipv4Packet, Ipv6Packet},
+    pdu::{Ipv4Packet, Ipv6Packet, PacketError},
 };
 use bevy::prelude::*;
 use std::fmt;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    // Fewer bytes than the header requires
+    Truncated { expected: usize, actual: usize },
+    UnsupportedEthertype(u16),
+    InvalidArp(String),
+    InvalidBpdu(String),
+    InvalidPacket(PacketError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Truncated { expected, actual } => write!(
+                f,
+                "Frame truncated: expected at least {} bytes, got {}",
+                expected, actual
+            ),
+            FrameError::UnsupportedEthertype(ethertype) => {
+                write!(f, "Unsupported ethertype 0x{:04X}", ethertype)
+            }
+            FrameError::InvalidArp(reason) => write!(f, "Invalid ARP packet: {}", reason),
+            FrameError::InvalidBpdu(reason) => write!(f, "Invalid BPDU: {}", reason),
+            FrameError::InvalidPacket(error) => write!(f, "Invalid payload: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<PacketError> for FrameError {
+    fn from(error: PacketError) -> Self {
+        FrameError::InvalidPacket(error)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum EthernetPayload {
     IPv4(Ipv4Packet),
     IPv6(Ipv6Packet),
-    ICMP,
     ARP(ArpPacket),
+    // Carried in an 802.3 frame behind an LLC header
+    STP(Bpdu),
     Dummy,
 }
 
 impl EthernetPayload {
+    // DSAP, SSAP and control field of LLC frames carrying BPDUs
+    const STP_LLC_HEADER: [u8; 3] = [0x42, 0x42, 0x03];
+
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
             EthernetPayload::IPv4(packet) => packet.to_bytes(),
-            EthernetPayload::IPv6(packet) => unimplemented!(),
-            EthernetPayload::ICMP => unimplemented!(),
+            EthernetPayload::IPv6(packet) => packet.to_bytes(),
             EthernetPayload::ARP(arp_packet) => arp_packet.to_bytes(),
+            EthernetPayload::STP(bpdu) => {
+                let mut bytes = Self::STP_LLC_HEADER.to_vec();
+                bytes.extend_from_slice(&bpdu.to_bytes());
+                bytes
+            }
             EthernetPayload::Dummy => Vec::new(),
         }
     }
+
+    pub fn from_bytes(ethertype: Ethertype, bytes: &[u8]) -> Result<Self, FrameError> {
+        match ethertype {
+            Ethertype::IPv4 => Ok(EthernetPayload::IPv4(Ipv4Packet::from_bytes(bytes)?)),
+            Ethertype::IPv6 => Ok(EthernetPayload::IPv6(Ipv6Packet::from_bytes(bytes)?)),
+            Ethertype::ARP => Ok(EthernetPayload::ARP(ArpPacket::from_bytes(bytes)?)),
+            Ethertype::LLC => match bytes.get(..3) {
+                Some(header) if header == Self::STP_LLC_HEADER => {
+                    Ok(EthernetPayload::STP(Bpdu::from_bytes(&bytes[3..])?))
+                }
+                _ => Err(FrameError::InvalidBpdu("not an STP LLC frame".to_string())),
+            },
+            Ethertype::Unknown => Ok(EthernetPayload::Dummy),
+        }
+    }
 }
 
 impl fmt::Display for EthernetPayload {
@@ -34,8 +94,8 @@ impl fmt::Display for EthernetPayload {
         match self {
             EthernetPayload::IPv4(packet) => write!(f, "{}", packet),
             EthernetPayload::IPv6(packet) => write!(f, "{}", packet),
-            EthernetPayload::ICMP => write!(f, "ICMP"),
             EthernetPayload::ARP(packet) => write!(f, "{}", packet),
+            EthernetPayload::STP(bpdu) => write!(f, "{}", bpdu),
             EthernetPayload::Dummy => write!(f, "Dummy Payload"),
         }
     }
@@ -50,14 +110,49 @@ pub struct VlanTag {
 }
 
 impl VlanTag {
+    pub const TPID: [u8; 2] = [0x81, 0x00];
+
+    // Creates an 802.1Q tag for VLAN `vid` (12 bits) with priority code point `pcp` (3 bits)
+    pub fn new(vid: u16, pcp: u8) -> Self {
+        Self {
+            tpid: Self::TPID,
+            pcp: pcp & 0x07,
+            dei: 0,
+            vid: (vid & 0x0FFF).to_be_bytes(),
+        }
+    }
+
+    pub fn vid(&self) -> u16 {
+        u16::from_be_bytes(self.vid)
+    }
+
+    pub fn pcp(&self) -> u8 {
+        self.pcp
+    }
+
+    // Drop eligible indicator
+    pub fn dei(&self) -> bool {
+        self.dei == 1
+    }
+
+    // Encodes the TPID followed by the 16-bit TCI (PCP, DEI and the 12-bit VID)
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(&self.tpid);
-        let pcp_dei = (self.pcp << 5) | self.dei;
-        bytes.push(pcp_dei);
-        bytes.extend_from_slice(&self.vid);
+        let pcp_dei_vid = (self.pcp << 5) | ((self.dei & 0x01) << 4) | (self.vid[0] & 0x0F);
+        bytes.push(pcp_dei_vid);
+        bytes.push(self.vid[1]);
         bytes
     }
+
+    pub fn from_bytes(bytes: &[u8; 4]) -> Self {
+        Self {
+            tpid: [bytes[0], bytes[1]],
+            pcp: bytes[2] >> 5,
+            dei: (bytes[2] >> 4) & 0x01,
+            vid: [bytes[2] & 0x0F, bytes[3]],
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -65,6 +160,8 @@ pub enum Ethertype {
     IPv4,    // 0x0800
     IPv6,    // 0x86DD
     ARP,     // 0x0806
+    // 802.3 frame whose type field holds the payload length (up to 0x05DC)
+    LLC,
     Unknown, // 0x0000
 }
 
@@ -74,14 +171,33 @@ impl Ethertype {
             Ethertype::IPv4 => [0x08, 0x00],
             Ethertype::IPv6 => [0x86, 0xDD],
             Ethertype::ARP => [0x08, 0x06],
+            // The actual length is written by `EthernetFrame::to_bytes`
+            Ethertype::LLC => [0x05, 0xDC],
             Ethertype::Unknown => [0x00, 0x00],
         }
     }
+
+    pub fn from_value(value: [u8; 2]) -> Result<Self, FrameError> {
+        match value {
+            [0x08, 0x00] => Ok(Ethertype::IPv4),
+            [0x86, 0xDD] => Ok(Ethertype::IPv6),
+            [0x08, 0x06] => Ok(Ethertype::ARP),
+            [0x00, 0x00] => Ok(Ethertype::Unknown),
+            _ if u16::from_be_bytes(value) <= 0x05DC => Ok(Ethertype::LLC),
+            _ => Err(FrameError::UnsupportedEthertype(u16::from_be_bytes(value))),
+        }
+    }
 }
 
 impl fmt::Display for Ethertype {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ARP")
+        match self {
+            Ethertype::IPv4 => write!(f, "IPv4 (0x0800)"),
+            Ethertype::IPv6 => write!(f, "IPv6 (0x86DD)"),
+            Ethertype::ARP => write!(f, "ARP (0x0806)"),
+            Ethertype::LLC => write!(f, "802.3 LLC"),
+            Ethertype::Unknown => write!(f, "Unknown (0x0000)"),
+        }
     }
 }
 
@@ -92,19 +208,28 @@ pub struct EthernetFrame {
     pub vlan: Option<VlanTag>,
     pub ethertype: Ethertype,
     pub payload: EthernetPayload,
+    // Bytes received after the payload, such as the padding up to the minimum frame size.
+    // They are covered by the FCS, so they are kept to verify it.
+    pub padding: Vec<u8>,
+    // CRC32 of the frame, most significant byte first as returned by `crc32`
     pub fcs: [u8; 4],
 }
 
 impl EthernetFrame {
+    const HEADER_LENGTH: usize = 14;
+
     pub fn new(src: MacAddress, dest: MacAddress) -> Self {
-        Self {
+        let mut frame = Self {
             dest,
             src,
             vlan: None,
             ethertype: Ethertype::Unknown,
             payload: EthernetPayload::Dummy,
+            padding: Vec::new(),
             fcs: [0; 4],
-        }
+        };
+        frame.fcs = crc32(&frame.to_bytes());
+        frame
     }
 
     pub fn arp_request(src: MacAddress, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
@@ -130,7 +255,61 @@ impl EthernetFrame {
         frame
     }
 
-    // Converts the Ethernet frame to a byte vector excluding the FCS
+    pub fn ipv4(src: MacAddress, dest: MacAddress, packet: Ipv4Packet) -> Self {
+        let mut frame = Self::new(src, dest);
+        frame.ethertype = Ethertype::IPv4;
+        frame.payload = EthernetPayload::IPv4(packet);
+        frame.fcs = crc32(&frame.to_bytes());
+        frame
+    }
+
+    pub fn ipv6(src: MacAddress, dest: MacAddress, packet: Ipv6Packet) -> Self {
+        let mut frame = Self::new(src, dest);
+        frame.ethertype = Ethertype::IPv6;
+        frame.payload = EthernetPayload::IPv6(packet);
+        frame.fcs = crc32(&frame.to_bytes());
+        frame
+    }
+
+    // Frame carrying a BPDU to the STP bridge group address
+    pub fn bpdu(src: MacAddress, bpdu: Bpdu) -> Self {
+        let mut frame = Self::new(src, MacAddress::bridge_group());
+        frame.ethertype = Ethertype::LLC;
+        frame.payload = EthernetPayload::STP(bpdu);
+        frame.fcs = crc32(&frame.to_bytes());
+        frame
+    }
+
+    // Recomputes the FCS after the frame has been modified
+    pub fn update_fcs(&mut self) {
+        self.fcs = crc32(&self.to_bytes());
+    }
+
+    // Checks the FCS against a CRC32 computed over the frame contents
+    pub fn has_valid_fcs(&self) -> bool {
+        self.fcs == crc32(&self.to_bytes())
+    }
+
+    // 802.1p priority of a tagged frame
+    pub fn priority_code_point(&self) -> Option<u8> {
+        self.vlan.as_ref().map(|tag| tag.pcp())
+    }
+
+    // Differentiated services code point of the IP packet the frame carries
+    pub fn dscp(&self) -> Option<u8> {
+        match &self.payload {
+            EthernetPayload::IPv4(packet) => Some(packet.header.dscp),
+            EthernetPayload::IPv6(packet) => Some(packet.traffic_class >> 2),
+            _ => None,
+        }
+    }
+
+    // Size of the frame on the wire including the FCS, padded to the 64 byte minimum
+    pub fn wire_size(&self) -> usize {
+        (self.to_bytes().len() + self.fcs.len()).max(64)
+    }
+
+    // Converts the Ethernet frame to a byte vector excluding the FCS, padding included
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(&self.dest.to_bytes());
@@ -138,8 +317,81 @@ impl EthernetFrame {
         if let Some(vlan) = &self.vlan {
             bytes.extend_from_slice(&vlan.to_bytes());
         }
-        bytes.extend_from_slice(&self.ethertype.get_value());
-        bytes.extend_from_slice(&self.payload.to_bytes());
+        let payload = self.payload.to_bytes();
+        match self.ethertype {
+            // 802.3 frames carry the payload length instead of an ethertype
+            Ethertype::LLC => bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes()),
+            ethertype => bytes.extend_from_slice(&ethertype.get_value()),
+        }
+        bytes.extend_from_slice(&payload);
+        bytes.extend_from_slice(&self.padding);
+        bytes
+    }
+
+    /// Parses a frame from bytes excluding the FCS, as produced by `to_bytes`.
+    /// The FCS is taken as given, most significant byte first, so that the receiver can
+    /// verify it. Whatever follows the payload is kept as padding.
+    pub fn from_bytes(bytes: &[u8], fcs: [u8; 4]) -> Result<Self, FrameError> {
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(FrameError::Truncated {
+                expected: Self::HEADER_LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        let dest = MacAddress::from_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]]);
+        let src = MacAddress::from_bytes([bytes[6], bytes[7], bytes[8], bytes[9], bytes[10], bytes[11]]);
+
+        // An 802.1Q tag sits between the source MAC and the ethertype
+        let mut offset = 12;
+        let mut vlan = None;
+        if bytes[offset..offset + 2] == VlanTag::TPID {
+            if bytes.len() < Self::HEADER_LENGTH + 4 {
+                return Err(FrameError::Truncated {
+                    expected: Self::HEADER_LENGTH + 4,
+                    actual: bytes.len(),
+                });
+            }
+            vlan = Some(VlanTag::from_bytes(&[
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ]));
+            offset += 4;
+        }
+
+        let ethertype = Ethertype::from_value([bytes[offset], bytes[offset + 1]])?;
+        let payload = EthernetPayload::from_bytes(ethertype, &bytes[offset + 2..])?;
+        let end = (offset + 2 + payload.to_bytes().len()).min(bytes.len());
+
+        Ok(Self {
+            dest,
+            src,
+            vlan,
+            ethertype,
+            payload,
+            padding: bytes[end..].to_vec(),
+            fcs,
+        })
+    }
+
+    /// Parses a frame as captured on the wire, with the FCS in the last four bytes.
+    /// The FCS is transmitted least significant byte first.
+    pub fn from_wire(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() < Self::HEADER_LENGTH + 4 {
+            return Err(FrameError::Truncated {
+                expected: Self::HEADER_LENGTH + 4,
+                actual: bytes.len(),
+            });
+        }
+        let (frame, fcs) = bytes.split_at(bytes.len() - 4);
+        Self::from_bytes(frame, [fcs[3], fcs[2], fcs[1], fcs[0]])
+    }
+
+    // Serializes the frame followed by its FCS, least significant byte first
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        bytes.extend(self.fcs.iter().rev());
         bytes
     }
 }
@@ -167,3 +419,54 @@ impl fmt::Display for EthernetFrame {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Minimum-size ARP request for 192.168.1.1 from 192.168.1.10, padded to 60 bytes,
+    // followed by its FCS 0x69B0551E in transmission order
+    const ARP_REQUEST: [u8; 64] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x15, 0x5d, 0x01, 0x23, 0x01, 0x08, 0x06, 0x00,
+        0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x00, 0x15, 0x5d, 0x01, 0x23, 0x01, 0xc0, 0xa8,
+        0x01, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xa8, 0x01, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x1e, 0x55, 0xb0, 0x69,
+    ];
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), [0xCB, 0xF4, 0x39, 0x26]);
+    }
+
+    #[test]
+    fn parses_padded_frame_from_wire() {
+        let frame = EthernetFrame::from_wire(&ARP_REQUEST).unwrap();
+        assert!(frame.has_valid_fcs());
+        assert_eq!(frame.fcs, [0x69, 0xB0, 0x55, 0x1E]);
+        assert_eq!(frame.padding, vec![0; 18]);
+        let EthernetPayload::ARP(arp) = &frame.payload else {
+            panic!("not an ARP frame");
+        };
+        assert_eq!(arp.target_ip, Ipv4Addr::new("192.168.1.1").unwrap());
+        assert_eq!(frame.to_wire(), ARP_REQUEST);
+    }
+
+    #[test]
+    fn writes_fcs_least_significant_byte_first() {
+        let mut frame = EthernetFrame::from_wire(&ARP_REQUEST).unwrap();
+        frame.padding.clear();
+        frame.update_fcs();
+        let wire = frame.to_wire();
+        let fcs = crc32(&wire[..wire.len() - 4]);
+        assert_eq!(wire[wire.len() - 4..], [fcs[3], fcs[2], fcs[1], fcs[0]]);
+        assert!(EthernetFrame::from_wire(&wire).unwrap().has_valid_fcs());
+    }
+
+    #[test]
+    fn detects_corrupted_frame() {
+        let mut bytes = ARP_REQUEST;
+        bytes[40] ^= 0x01;
+        assert!(!EthernetFrame::from_wire(&bytes).unwrap().has_valid_fcs());
+    }
+}
@@ -0,0 +1,523 @@
+use super::interface::{Queue, QueueCapacity, QueueItem};
+use super::pdu::EthernetFrame;
+use rand::Rng;
+use std::fmt;
+
+/// Criterion putting a frame in a traffic class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassMatch {
+    Any,
+    // 802.1p priority code point of the VLAN tag ("match cos")
+    Cos(u8),
+    // DSCP of the IPv4 or IPv6 packet ("match dscp")
+    Dscp(u8),
+}
+
+impl ClassMatch {
+    pub fn matches(&self, frame: &EthernetFrame) -> bool {
+        match self {
+            ClassMatch::Any => true,
+            ClassMatch::Cos(cos) => frame.priority_code_point() == Some(*cos),
+            ClassMatch::Dscp(dscp) => frame.dscp() == Some(*dscp),
+        }
+    }
+}
+
+impl fmt::Display for ClassMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClassMatch::Any => write!(f, "any"),
+            ClassMatch::Cos(cos) => write!(f, "cos {}", cos),
+            ClassMatch::Dscp(dscp) => write!(f, "dscp {}", dscp),
+        }
+    }
+}
+
+/// Drop thresholds of RED for one IP precedence, in frames of average queue depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedProfile {
+    // Below this average depth nothing is dropped early
+    pub min_threshold: usize,
+    // At or above this average depth every frame is dropped
+    pub max_threshold: usize,
+    // One frame in this many is dropped when the average reaches the maximum threshold
+    pub mark_probability_denominator: u32,
+}
+
+impl RedProfile {
+    pub fn new(
+        min_threshold: usize,
+        max_threshold: usize,
+        mark_probability_denominator: u32,
+    ) -> Self {
+        Self {
+            min_threshold,
+            max_threshold,
+            mark_probability_denominator,
+        }
+    }
+
+    // Probability of dropping a frame given the average queue depth
+    fn drop_probability(&self, average: f64) -> f64 {
+        if average < self.min_threshold as f64 {
+            0.0
+        } else if average >= self.max_threshold as f64 {
+            1.0
+        } else {
+            let range = (self.max_threshold - self.min_threshold) as f64;
+            let fraction = (average - self.min_threshold as f64) / range;
+            fraction / f64::from(self.mark_probability_denominator.max(1))
+        }
+    }
+}
+
+/// Weighted random early detection. Frames are dropped with a probability growing with the
+/// average depth of the queue, before it fills up, so that TCP senders slow down one at a
+/// time instead of all at once. Each IP precedence has its own thresholds; frames without an
+/// IP packet use those of precedence 0. Plain RED uses the same thresholds for all of them.
+#[derive(Debug, Clone)]
+pub struct Wred {
+    pub profiles: [RedProfile; 8],
+    // The average moves by 1/2^n of the difference with the current depth
+    pub exponential_weight: u8,
+    average: f64,
+    // Frames dropped early, before the queue was full
+    pub random_drops: u64,
+}
+
+impl Wred {
+    pub const DEFAULT_EXPONENTIAL_WEIGHT: u8 = 9;
+
+    // Thresholds of "random-detect" on IOS: from 20 frames for precedence 0 up to 34 for
+    // precedence 7, all dropping everything at 40
+    pub fn new() -> Self {
+        let profiles =
+            std::array::from_fn(|precedence| RedProfile::new(20 + 2 * precedence, 40, 10));
+        Self::with_profiles(profiles)
+    }
+
+    // Plain RED: the same thresholds whatever the precedence
+    pub fn red(profile: RedProfile) -> Self {
+        Self::with_profiles([profile; 8])
+    }
+
+    pub fn with_profiles(profiles: [RedProfile; 8]) -> Self {
+        Self {
+            profiles,
+            exponential_weight: Self::DEFAULT_EXPONENTIAL_WEIGHT,
+            average: 0.0,
+            random_drops: 0,
+        }
+    }
+
+    pub fn average_depth(&self) -> f64 {
+        self.average
+    }
+
+    /// Updates the average with the current depth of the queue and decides whether the
+    /// frame arriving now is dropped
+    fn should_drop<R: Rng>(&mut self, depth: usize, frame: &EthernetFrame, rng: &mut R) -> bool {
+        let weight = 1.0 / f64::from(1u32 << self.exponential_weight.min(31));
+        self.average += (depth as f64 - self.average) * weight;
+        let precedence = frame.dscp().map_or(0, |dscp| usize::from(dscp >> 3));
+        let probability = self.profiles[precedence].drop_probability(self.average);
+        let drop = probability > 0.0 && rng.gen_bool(probability.min(1.0));
+        if drop {
+            self.random_drops += 1;
+        }
+        drop
+    }
+}
+
+impl Default for Wred {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A class of traffic with its own queue ("class-map" and its "policy-map" actions)
+#[derive(Debug, Clone)]
+pub struct TrafficClass {
+    pub name: String,
+    // The frame belongs to the class if any of these match
+    pub matches: Vec<ClassMatch>,
+    // Share of the bandwidth under weighted fair queuing, relative to the other classes
+    pub weight: u32,
+    // Served before every other class under weighted fair queuing ("priority")
+    pub priority: bool,
+    pub wred: Option<Wred>,
+    pub queue: Queue<EthernetFrame>,
+    pub transmitted_frames: u64,
+    pub transmitted_bytes: u64,
+    // Bytes the class may still send in the current round of weighted fair queuing
+    deficit: usize,
+}
+
+impl TrafficClass {
+    // Default "queue-limit" of IOS
+    pub const DEFAULT_QUEUE_LIMIT: usize = 64;
+
+    pub fn new(name: &str, matches: Vec<ClassMatch>) -> Self {
+        Self {
+            name: name.to_string(),
+            matches,
+            weight: 1,
+            priority: false,
+            wred: None,
+            queue: Queue::new(QueueCapacity::Frames(Self::DEFAULT_QUEUE_LIMIT)),
+            transmitted_frames: 0,
+            transmitted_bytes: 0,
+            deficit: 0,
+        }
+    }
+
+    // The class catching the frames no other class matched
+    pub fn class_default() -> Self {
+        Self::new("class-default", vec![ClassMatch::Any])
+    }
+
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_priority(mut self) -> Self {
+        self.priority = true;
+        self
+    }
+
+    pub fn with_wred(mut self, wred: Wred) -> Self {
+        self.wred = Some(wred);
+        self
+    }
+
+    pub fn with_queue_limit(mut self, capacity: QueueCapacity) -> Self {
+        self.queue.set_capacity(capacity);
+        self
+    }
+
+    pub fn matches(&self, frame: &EthernetFrame) -> bool {
+        self.matches
+            .iter()
+            .any(|criterion| criterion.matches(frame))
+    }
+
+    // Queues the frame unless RED drops it early or the queue is full
+    #[allow(clippy::result_large_err)]
+    fn enqueue(&mut self, frame: EthernetFrame) -> Result<(), EthernetFrame> {
+        if let Some(wred) = self.wred.as_mut() {
+            if wred.should_drop(self.queue.len(), &frame, &mut rand::thread_rng()) {
+                return Err(frame);
+            }
+        }
+        self.queue.enqueue(frame)
+    }
+
+    fn dequeue(&mut self) -> Option<EthernetFrame> {
+        let frame = self.queue.dequeue()?;
+        self.transmitted_frames += 1;
+        self.transmitted_bytes += frame.size() as u64;
+        Some(frame)
+    }
+
+    fn drops(&self) -> u64 {
+        self.queue.stats.dropped + self.wred.as_ref().map_or(0, |wred| wred.random_drops)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingDiscipline {
+    // Classes are served in order; a class only sends when all those before it are empty
+    StrictPriority,
+    // Class-based weighted fair queuing: classes share the bandwidth in proportion to their
+    // weight, after the priority classes
+    WeightedFair,
+}
+
+impl fmt::Display for SchedulingDiscipline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchedulingDiscipline::StrictPriority => write!(f, "Priority queueing"),
+            SchedulingDiscipline::WeightedFair => write!(f, "Class-based weighted fair queueing"),
+        }
+    }
+}
+
+/// Egress queuing of an interface replacing its single FIFO. Frames go to the first class
+/// they match, and to the last class if they match none.
+#[derive(Debug, Clone)]
+pub struct EgressScheduler {
+    pub discipline: SchedulingDiscipline,
+    pub classes: Vec<TrafficClass>,
+    // Class whose turn it is under weighted fair queuing, and whether it got its quantum yet
+    current: usize,
+    credited: bool,
+}
+
+impl EgressScheduler {
+    // Bytes of credit a class earns per unit of weight in each round of weighted fair queuing
+    pub const QUANTUM: usize = 500;
+
+    pub fn new(discipline: SchedulingDiscipline, mut classes: Vec<TrafficClass>) -> Self {
+        if classes.is_empty() {
+            classes.push(TrafficClass::class_default());
+        }
+        Self {
+            discipline,
+            classes,
+            current: 0,
+            credited: false,
+        }
+    }
+
+    // Classes from the highest priority to the lowest
+    pub fn strict_priority(classes: Vec<TrafficClass>) -> Self {
+        Self::new(SchedulingDiscipline::StrictPriority, classes)
+    }
+
+    pub fn weighted_fair(classes: Vec<TrafficClass>) -> Self {
+        Self::new(SchedulingDiscipline::WeightedFair, classes)
+    }
+
+    // A single queue managed by (W)RED
+    pub fn random_detect(wred: Wred) -> Self {
+        Self::weighted_fair(vec![TrafficClass::class_default().with_wred(wred)])
+    }
+
+    // Index of the class the frame goes to
+    pub fn classify(&self, frame: &EthernetFrame) -> usize {
+        self.classes
+            .iter()
+            .position(|class| class.matches(frame))
+            .unwrap_or(self.classes.len() - 1)
+    }
+
+    // Queues the frame in its class. Returns the frame if it was dropped.
+    #[allow(clippy::result_large_err)]
+    pub fn enqueue(&mut self, frame: EthernetFrame) -> Result<(), EthernetFrame> {
+        let class = self.classify(&frame);
+        self.classes[class].enqueue(frame)
+    }
+
+    // Next frame to transmit
+    pub fn dequeue(&mut self) -> Option<EthernetFrame> {
+        match self.discipline {
+            SchedulingDiscipline::StrictPriority => self
+                .classes
+                .iter_mut()
+                .find(|class| !class.queue.is_empty())?
+                .dequeue(),
+            SchedulingDiscipline::WeightedFair => self.dequeue_weighted_fair(),
+        }
+    }
+
+    // Deficit round robin: on its turn a class earns a quantum proportional to its weight
+    // and sends frames while its credit covers them
+    fn dequeue_weighted_fair(&mut self) -> Option<EthernetFrame> {
+        if let Some(class) = self
+            .classes
+            .iter_mut()
+            .find(|class| class.priority && !class.queue.is_empty())
+        {
+            return class.dequeue();
+        }
+        if self.classes.iter().all(|class| class.queue.is_empty()) {
+            return None;
+        }
+        loop {
+            let class = &mut self.classes[self.current];
+            match class.queue.peek().map(|frame| frame.size()) {
+                Some(size) if !class.priority => {
+                    if !self.credited {
+                        class.deficit += class.weight.max(1) as usize * Self::QUANTUM;
+                        self.credited = true;
+                    }
+                    if size <= class.deficit {
+                        class.deficit -= size;
+                        return class.dequeue();
+                    }
+                }
+                // Idle classes do not save up credit
+                _ => class.deficit = 0,
+            }
+            self.current = (self.current + 1) % self.classes.len();
+            self.credited = false;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.iter().map(|class| class.queue.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.iter().all(|class| class.queue.is_empty())
+    }
+
+    // "show policy-map interface"
+    pub fn show(&self, interface_name: &str) -> String {
+        let mut output = format!(
+            " {}\n\n  Service-policy output: {}\n",
+            interface_name, self.discipline
+        );
+        for class in &self.classes {
+            output.push_str(&format!("\n    Class-map: {} (match-any)\n", class.name));
+            for criterion in &class.matches {
+                output.push_str(&format!("      Match: {}\n", criterion));
+            }
+            match self.discipline {
+                SchedulingDiscipline::WeightedFair if class.priority => {
+                    output.push_str("      Priority: Strict\n")
+                }
+                SchedulingDiscipline::WeightedFair => {
+                    output.push_str(&format!("      Bandwidth weight {}\n", class.weight))
+                }
+                SchedulingDiscipline::StrictPriority => {}
+            }
+            let limit = match class.queue.capacity() {
+                QueueCapacity::Frames(frames) => format!("{} packets", frames),
+                QueueCapacity::Bytes(bytes) => format!("{} bytes", bytes),
+            };
+            output.push_str(&format!("      Queue limit {}\n", limit));
+            output.push_str(&format!(
+                "      (queue depth/total drops) {}/{}\n",
+                class.queue.len(),
+                class.drops()
+            ));
+            output.push_str(&format!(
+                "      (pkts output/bytes output) {}/{}\n",
+                class.transmitted_frames, class.transmitted_bytes
+            ));
+            if let Some(wred) = &class.wred {
+                output.push_str(&format!(
+                    "      Random-detect: mean queue depth {:.0}, random drops {}, tail drops {}\n",
+                    wred.average_depth(),
+                    wred.random_drops,
+                    class.queue.stats.dropped
+                ));
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer2::address::MacAddress;
+    use crate::layer2::pdu::VlanTag;
+    use crate::layer3::address::Ipv4Addr;
+    use crate::layer3::pdu::{IpPayload, Ipv4Packet};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // A 100-byte frame carrying an IPv4 packet with the given DSCP
+    fn frame(dscp: u8) -> EthernetFrame {
+        let mut packet = Ipv4Packet::new(
+            Ipv4Addr::new("10.0.0.1").unwrap(),
+            Ipv4Addr::new("10.0.0.2").unwrap(),
+            IpPayload { data: vec![0; 66] },
+        );
+        packet.header.dscp = dscp;
+        packet.update_header();
+        EthernetFrame::ipv4(MacAddress::random(), MacAddress::random(), packet)
+    }
+
+    fn tagged_frame(dscp: u8, cos: u8) -> EthernetFrame {
+        let mut frame = frame(dscp);
+        frame.vlan = Some(VlanTag::new(10, cos));
+        frame
+    }
+
+    fn fill(scheduler: &mut EgressScheduler, dscp: u8, count: usize) {
+        for _ in 0..count {
+            scheduler.enqueue(frame(dscp)).unwrap();
+        }
+    }
+
+    #[test]
+    fn classifies_by_cos_and_dscp() {
+        let scheduler = EgressScheduler::strict_priority(vec![
+            TrafficClass::new("voice", vec![ClassMatch::Dscp(46)]),
+            TrafficClass::new("video", vec![ClassMatch::Cos(4)]),
+            TrafficClass::new("bulk", vec![ClassMatch::Cos(1)]),
+        ]);
+        assert_eq!(scheduler.classify(&frame(46)), 0);
+        assert_eq!(scheduler.classify(&tagged_frame(46, 4)), 0);
+        assert_eq!(scheduler.classify(&tagged_frame(0, 4)), 1);
+        assert_eq!(scheduler.classify(&tagged_frame(0, 1)), 2);
+        // Frames matching no class go to the last one
+        assert_eq!(scheduler.classify(&frame(0)), 2);
+        assert_eq!(scheduler.classify(&tagged_frame(0, 5)), 2);
+    }
+
+    #[test]
+    fn serves_classes_in_strict_priority_order() {
+        let mut scheduler = EgressScheduler::strict_priority(vec![
+            TrafficClass::new("high", vec![ClassMatch::Dscp(46)]),
+            TrafficClass::class_default(),
+        ]);
+        fill(&mut scheduler, 0, 3);
+        fill(&mut scheduler, 46, 2);
+        let order: Vec<_> = std::iter::from_fn(|| scheduler.dequeue())
+            .map(|frame| frame.dscp().unwrap())
+            .collect();
+        assert_eq!(order, [46, 46, 0, 0, 0]);
+    }
+
+    #[test]
+    fn shares_bandwidth_by_weight() {
+        let mut scheduler = EgressScheduler::weighted_fair(vec![
+            TrafficClass::new("gold", vec![ClassMatch::Dscp(10)]).with_weight(3),
+            TrafficClass::class_default().with_weight(1),
+        ]);
+        fill(&mut scheduler, 10, 60);
+        fill(&mut scheduler, 0, 60);
+        for _ in 0..40 {
+            assert_eq!(scheduler.dequeue().unwrap().size(), 100);
+        }
+        let gold = scheduler.classes[0].transmitted_bytes;
+        let default = scheduler.classes[1].transmitted_bytes;
+        assert_eq!((gold, default), (3000, 1000));
+    }
+
+    #[test]
+    fn drains_priority_class_first_under_weighted_fair() {
+        let mut scheduler = EgressScheduler::weighted_fair(vec![
+            TrafficClass::new("voice", vec![ClassMatch::Dscp(46)]).with_priority(),
+            TrafficClass::new("gold", vec![ClassMatch::Dscp(10)]).with_weight(10),
+            TrafficClass::class_default(),
+        ]);
+        fill(&mut scheduler, 10, 5);
+        fill(&mut scheduler, 0, 5);
+        scheduler.dequeue().unwrap();
+        fill(&mut scheduler, 46, 3);
+        for _ in 0..3 {
+            assert_eq!(scheduler.dequeue().unwrap().dscp(), Some(46));
+        }
+        assert_eq!(scheduler.len(), 9);
+        assert!(scheduler.classes[0].queue.is_empty());
+    }
+
+    #[test]
+    fn red_drop_probability_grows_between_thresholds() {
+        let profile = RedProfile::new(20, 40, 10);
+        assert_eq!(profile.drop_probability(10.0), 0.0);
+        assert_eq!(profile.drop_probability(20.0), 0.0);
+        assert_eq!(profile.drop_probability(30.0), 0.05);
+        assert_eq!(profile.drop_probability(40.0), 1.0);
+        assert_eq!(profile.drop_probability(50.0), 1.0);
+    }
+
+    #[test]
+    fn wred_uses_thresholds_of_precedence() {
+        let mut wred = Wred::new();
+        // Follow the current depth without averaging
+        wred.exponential_weight = 0;
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!(!wred.should_drop(19, &frame(0), &mut rng));
+        assert!(wred.should_drop(40, &frame(46), &mut rng));
+        assert_eq!(wred.average_depth(), 40.0);
+        // Precedence 5 only starts dropping at 30 frames
+        assert!(!wred.should_drop(29, &frame(46), &mut rng));
+        assert_eq!(wred.random_drops, 1);
+    }
+}
@@ -0,0 +1,1089 @@
+use super::address::MacAddress;
+use super::pdu::FrameError;
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+/// Identifies a bridge in the spanning tree: a configurable priority followed by its MAC address.
+/// The bridge with the lowest identifier is elected root.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BridgeId {
+    pub priority: u16,
+    pub mac: MacAddress,
+}
+
+impl BridgeId {
+    pub const DEFAULT_PRIORITY: u16 = 32768;
+    const LENGTH: usize = 8;
+
+    pub fn new(priority: u16, mac: MacAddress) -> Self {
+        Self { priority, mac }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[..2].copy_from_slice(&self.priority.to_be_bytes());
+        bytes[2..].copy_from_slice(&self.mac.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; 8]) -> Self {
+        Self {
+            priority: u16::from_be_bytes([bytes[0], bytes[1]]),
+            mac: MacAddress::from_bytes([bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+impl Ord for BridgeId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.priority, self.mac.to_bytes()).cmp(&(other.priority, other.mac.to_bytes()))
+    }
+}
+
+impl PartialOrd for BridgeId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BridgeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.priority, self.mac.to_dotted_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StpVersion {
+    // IEEE 802.1D spanning tree
+    Stp,
+    // IEEE 802.1w rapid spanning tree
+    Rstp,
+}
+
+impl StpVersion {
+    // Protocol version identifier carried in BPDUs
+    pub fn get_value(&self) -> u8 {
+        match self {
+            StpVersion::Stp => 0,
+            StpVersion::Rstp => 2,
+        }
+    }
+}
+
+impl fmt::Display for StpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StpVersion::Stp => write!(f, "ieee"),
+            StpVersion::Rstp => write!(f, "rstp"),
+        }
+    }
+}
+
+/// Bridge protocol data unit: a configuration BPDU for STP or an RST BPDU for RSTP.
+/// Topology change notification BPDUs are not used; changes are flagged in regular BPDUs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bpdu {
+    pub version: StpVersion,
+    pub flags: u8,
+    pub root_id: BridgeId,
+    pub root_path_cost: u32,
+    pub bridge_id: BridgeId,
+    pub port_id: u16,
+    pub message_age: Duration,
+    pub max_age: Duration,
+    pub hello_time: Duration,
+    pub forward_delay: Duration,
+}
+
+impl Bpdu {
+    pub const TOPOLOGY_CHANGE: u8 = 0x01;
+    pub const PROPOSAL: u8 = 0x02;
+    pub const LEARNING: u8 = 0x10;
+    pub const FORWARDING: u8 = 0x20;
+    pub const AGREEMENT: u8 = 0x40;
+    pub const TOPOLOGY_CHANGE_ACK: u8 = 0x80;
+    // Two bits holding the role of the sending port in RST BPDUs
+    const PORT_ROLE_MASK: u8 = 0x0C;
+    const CONFIG_LENGTH: usize = 35;
+    // RST BPDUs end with an extra Version 1 Length byte
+    const RST_LENGTH: usize = 36;
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+
+    // Role of the sending port, only announced by RST BPDUs
+    pub fn port_role(&self) -> Option<PortRole> {
+        match (self.flags & Self::PORT_ROLE_MASK) >> 2 {
+            1 => Some(PortRole::Alternate),
+            2 => Some(PortRole::Root),
+            3 => Some(PortRole::Designated),
+            _ => None,
+        }
+    }
+
+    fn role_flags(role: PortRole) -> u8 {
+        let value = match role {
+            PortRole::Alternate | PortRole::Backup => 1,
+            PortRole::Root => 2,
+            PortRole::Designated => 3,
+            PortRole::Disabled => 0,
+        };
+        value << 2
+    }
+
+    fn priority_vector(&self) -> PriorityVector {
+        PriorityVector {
+            root: self.root_id.clone(),
+            root_path_cost: self.root_path_cost,
+            bridge: self.bridge_id.clone(),
+            port: self.port_id,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&[0x00, 0x00]); // Protocol identifier
+        bytes.push(self.version.get_value());
+        bytes.push(match self.version {
+            StpVersion::Stp => 0x00,
+            StpVersion::Rstp => 0x02,
+        });
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.root_id.to_bytes());
+        bytes.extend_from_slice(&self.root_path_cost.to_be_bytes());
+        bytes.extend_from_slice(&self.bridge_id.to_bytes());
+        bytes.extend_from_slice(&self.port_id.to_be_bytes());
+        bytes.extend_from_slice(&timer_to_bytes(self.message_age));
+        bytes.extend_from_slice(&timer_to_bytes(self.max_age));
+        bytes.extend_from_slice(&timer_to_bytes(self.hello_time));
+        bytes.extend_from_slice(&timer_to_bytes(self.forward_delay));
+        if self.version == StpVersion::Rstp {
+            bytes.push(0x00); // Version 1 Length
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FrameError> {
+        if bytes.len() < 4 {
+            return Err(FrameError::Truncated {
+                expected: 4,
+                actual: bytes.len(),
+            });
+        }
+        if bytes[0..2] != [0x00, 0x00] {
+            return Err(FrameError::InvalidBpdu(format!(
+                "unknown protocol identifier 0x{:02X}{:02X}",
+                bytes[0], bytes[1]
+            )));
+        }
+        let (version, length) = match (bytes[2], bytes[3]) {
+            (_, 0x00) => (StpVersion::Stp, Self::CONFIG_LENGTH),
+            (2, 0x02) => (StpVersion::Rstp, Self::RST_LENGTH),
+            (_, 0x80) => {
+                return Err(FrameError::InvalidBpdu(
+                    "topology change notification BPDUs are not supported".to_string(),
+                ))
+            }
+            (version, bpdu_type) => {
+                return Err(FrameError::InvalidBpdu(format!(
+                    "unsupported version {} and type 0x{:02X}",
+                    version, bpdu_type
+                )))
+            }
+        };
+        if bytes.len() < length {
+            return Err(FrameError::Truncated {
+                expected: length,
+                actual: bytes.len(),
+            });
+        }
+
+        let bridge_id = |offset: usize| {
+            let mut id = [0; BridgeId::LENGTH];
+            id.copy_from_slice(&bytes[offset..offset + BridgeId::LENGTH]);
+            BridgeId::from_bytes(&id)
+        };
+        let timer = |offset: usize| timer_from_bytes([bytes[offset], bytes[offset + 1]]);
+        Ok(Self {
+            version,
+            flags: bytes[4],
+            root_id: bridge_id(5),
+            root_path_cost: u32::from_be_bytes([bytes[13], bytes[14], bytes[15], bytes[16]]),
+            bridge_id: bridge_id(17),
+            port_id: u16::from_be_bytes([bytes[25], bytes[26]]),
+            message_age: timer(27),
+            max_age: timer(29),
+            hello_time: timer(31),
+            forward_delay: timer(33),
+        })
+    }
+}
+
+impl fmt::Display for Bpdu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BPDU ({}) root {} cost {} bridge {} port 0x{:04X} flags 0x{:02X}",
+            self.version, self.root_id, self.root_path_cost, self.bridge_id, self.port_id, self.flags
+        )
+    }
+}
+
+// BPDU timers are expressed in units of 1/256 of a second
+fn timer_to_bytes(timer: Duration) -> [u8; 2] {
+    ((timer.as_nanos() * 256 / 1_000_000_000) as u16).to_be_bytes()
+}
+
+fn timer_from_bytes(bytes: [u8; 2]) -> Duration {
+    Duration::from_nanos(u64::from(u16::from_be_bytes(bytes)) * 1_000_000_000 / 256)
+}
+
+/// Default 802.1D path cost of a port running at `bandwidth` bits per second
+pub fn port_cost(bandwidth: u64) -> u32 {
+    match bandwidth {
+        b if b >= 10_000_000_000 => 2,
+        b if b >= 1_000_000_000 => 4,
+        b if b >= 100_000_000 => 19,
+        b if b >= 10_000_000 => 100,
+        _ => 250,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortRole {
+    // Best path towards the root bridge
+    Root,
+    // Forwards towards the segment on behalf of this bridge
+    Designated,
+    // Another path towards the root, kept blocked
+    Alternate,
+    // Redundant connection to a segment this bridge is already designated for
+    Backup,
+    Disabled,
+}
+
+impl fmt::Display for PortRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortRole::Root => f.pad("Root"),
+            PortRole::Designated => f.pad("Desg"),
+            PortRole::Alternate => f.pad("Altn"),
+            PortRole::Backup => f.pad("Back"),
+            PortRole::Disabled => f.pad("Disb"),
+        }
+    }
+}
+
+/// Port states of 802.1D. RSTP folds Disabled, Blocking and Listening into a single
+/// discarding state, which is represented as Blocking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    Disabled,
+    Blocking,
+    Listening,
+    Learning,
+    Forwarding,
+}
+
+impl PortState {
+    // Whether source addresses of frames received on the port are learned
+    pub fn learns(&self) -> bool {
+        matches!(self, PortState::Learning | PortState::Forwarding)
+    }
+
+    // Whether data frames are received and sent on the port
+    pub fn forwards(&self) -> bool {
+        *self == PortState::Forwarding
+    }
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortState::Disabled => f.pad("DIS"),
+            PortState::Blocking => f.pad("BLK"),
+            PortState::Listening => f.pad("LIS"),
+            PortState::Learning => f.pad("LRN"),
+            PortState::Forwarding => f.pad("FWD"),
+        }
+    }
+}
+
+// Compared field by field, lower is better
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PriorityVector {
+    root: BridgeId,
+    root_path_cost: u32,
+    bridge: BridgeId,
+    port: u16,
+}
+
+// Best information heard on a port
+#[derive(Debug, Clone)]
+struct ReceivedInfo {
+    vector: PriorityVector,
+    message_age: Duration,
+    expires_at: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct StpPort {
+    // Port priority in the high byte, port number in the low byte
+    pub port_id: u16,
+    pub cost: u32,
+    // Edge ports (PortFast) lead to end hosts and forward straight away.
+    // Receiving a BPDU turns the port back into a regular one.
+    pub edge: bool,
+    // Ports whose link is down take no part in the spanning tree
+    pub enabled: bool,
+    pub role: PortRole,
+    pub state: PortState,
+    received: Option<ReceivedInfo>,
+    // When the port moves on to the next of Listening, Learning and Forwarding
+    next_transition: Option<Duration>,
+    // RSTP: the bridge downstream agreed to let this designated port forward
+    agreed: bool,
+    // RSTP: the designated bridge of this segment proposed to forward towards us
+    proposed: bool,
+    // Topology changes are announced on the port until then
+    tc_until: Duration,
+}
+
+impl StpPort {
+    const DEFAULT_PRIORITY: u16 = 128;
+
+    fn new(number: u16, cost: u32, edge: bool) -> Self {
+        Self {
+            port_id: (Self::DEFAULT_PRIORITY << 8) | (number & 0xFF),
+            cost,
+            edge,
+            enabled: true,
+            role: PortRole::Designated,
+            state: PortState::Blocking,
+            received: None,
+            next_transition: None,
+            agreed: false,
+            proposed: false,
+            tc_until: Duration::ZERO,
+        }
+    }
+}
+
+/// Spanning tree protocol instance of a bridge, attached to the same entity as its `Switch`.
+/// Switches without it forward on every port.
+#[derive(Component, Debug)]
+pub struct SpanningTree {
+    pub version: StpVersion,
+    pub bridge_id: BridgeId,
+    pub hello_time: Duration,
+    pub max_age: Duration,
+    pub forward_delay: Duration,
+    // Ordered by port number
+    ports: Vec<(Entity, StpPort)>,
+    edge_ports: HashSet<Entity>,
+    root_id: BridgeId,
+    root_path_cost: u32,
+    root_port: Option<Entity>,
+    // Message age of the root information heard on the root port
+    root_message_age: Duration,
+    next_hello: Duration,
+    // The bridge information changed and is sent without waiting for the hello timer
+    send_now: bool,
+    topology_changed: bool,
+    scheduled_wakeup: Option<Duration>,
+}
+
+impl SpanningTree {
+    pub const DEFAULT_HELLO_TIME: Duration = Duration::from_secs(2);
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(20);
+    pub const DEFAULT_FORWARD_DELAY: Duration = Duration::from_secs(15);
+
+    pub fn new(version: StpVersion) -> Self {
+        Self::with_bridge_id(
+            version,
+            BridgeId::new(BridgeId::DEFAULT_PRIORITY, MacAddress::random()),
+        )
+    }
+
+    pub fn with_bridge_id(version: StpVersion, bridge_id: BridgeId) -> Self {
+        Self {
+            version,
+            root_id: bridge_id.clone(),
+            bridge_id,
+            hello_time: Self::DEFAULT_HELLO_TIME,
+            max_age: Self::DEFAULT_MAX_AGE,
+            forward_delay: Self::DEFAULT_FORWARD_DELAY,
+            ports: Vec::new(),
+            edge_ports: HashSet::new(),
+            root_path_cost: 0,
+            root_port: None,
+            root_message_age: Duration::ZERO,
+            next_hello: Duration::ZERO,
+            send_now: true,
+            topology_changed: false,
+            scheduled_wakeup: None,
+        }
+    }
+
+    pub fn set_priority(&mut self, priority: u16) {
+        self.bridge_id.priority = priority;
+        self.send_now = true;
+    }
+
+    // Configures a port as an edge port (PortFast)
+    pub fn set_edge_port(&mut self, port: Entity, edge: bool) {
+        if edge {
+            self.edge_ports.insert(port);
+        } else {
+            self.edge_ports.remove(&port);
+        }
+        if let Some((_, stp_port)) = self.ports.iter_mut().find(|(entity, _)| *entity == port) {
+            stp_port.edge = edge;
+        }
+    }
+
+    // Adds a port to the spanning tree, numbered after the ones already added
+    pub fn add_port(&mut self, port: Entity, cost: u32) {
+        if self.port(port).is_some() {
+            return;
+        }
+        let number = self.ports.len() as u16 + 1;
+        let edge = self.edge_ports.contains(&port);
+        self.ports.push((port, StpPort::new(number, cost, edge)));
+        self.send_now = true;
+    }
+
+    /// Enables or disables a port as its link comes up or goes down. A disabled port forgets
+    /// what it heard, so losing the root port makes the bridge look for another path.
+    pub fn set_port_enabled(&mut self, port: Entity, enabled: bool) {
+        let Some((_, stp_port)) = self.ports.iter_mut().find(|(entity, _)| *entity == port) else {
+            return;
+        };
+        if stp_port.enabled == enabled {
+            return;
+        }
+        stp_port.enabled = enabled;
+        stp_port.received = None;
+        stp_port.proposed = false;
+        stp_port.agreed = false;
+        stp_port.tc_until = Duration::ZERO;
+        self.send_now = true;
+    }
+
+    pub fn port(&self, port: Entity) -> Option<&StpPort> {
+        self.ports
+            .iter()
+            .find(|(entity, _)| *entity == port)
+            .map(|(_, stp_port)| stp_port)
+    }
+
+    // Ports that have not been added to the spanning tree are disabled
+    pub fn port_state(&self, port: Entity) -> PortState {
+        self.port(port).map_or(PortState::Disabled, |stp_port| stp_port.state)
+    }
+
+    pub fn port_role(&self, port: Entity) -> PortRole {
+        self.port(port).map_or(PortRole::Disabled, |stp_port| stp_port.role)
+    }
+
+    pub fn root_id(&self) -> &BridgeId {
+        &self.root_id
+    }
+
+    pub fn root_path_cost(&self) -> u32 {
+        self.root_path_cost
+    }
+
+    pub fn root_port(&self) -> Option<Entity> {
+        self.root_port
+    }
+
+    pub fn is_root_bridge(&self) -> bool {
+        self.root_id == self.bridge_id
+    }
+
+    // Returns true once after a topology change, when learned addresses should be flushed
+    pub fn take_topology_change(&mut self) -> bool {
+        std::mem::take(&mut self.topology_changed)
+    }
+
+    // How long a topology change is announced
+    fn tc_duration(&self) -> Duration {
+        match self.version {
+            StpVersion::Stp => self.max_age + self.forward_delay,
+            StpVersion::Rstp => self.hello_time * 2,
+        }
+    }
+
+    /// Records a BPDU received on `port`. Superior information, or any update from the
+    /// bridge port the stored information came from, replaces what the port holds.
+    pub fn receive_bpdu(&mut self, port: Entity, bpdu: &Bpdu, now: Duration) {
+        // The information has travelled through too many bridges
+        if bpdu.message_age >= bpdu.max_age {
+            return;
+        }
+        let rapid = self.version == StpVersion::Rstp;
+        let lifetime = match self.version {
+            StpVersion::Stp => bpdu.max_age - bpdu.message_age,
+            StpVersion::Rstp => self.hello_time * 3,
+        };
+        let tc_duration = self.tc_duration();
+        let Some(index) = self.ports.iter().position(|(entity, _)| *entity == port) else {
+            return;
+        };
+
+        let stp_port = &mut self.ports[index].1;
+        if !stp_port.enabled {
+            return;
+        }
+        stp_port.edge = false;
+        if rapid
+            && bpdu.has_flag(Bpdu::AGREEMENT)
+            && stp_port.role == PortRole::Designated
+            && bpdu.root_id == self.root_id
+        {
+            stp_port.agreed = true;
+        }
+        let vector = bpdu.priority_vector();
+        let replace = match &stp_port.received {
+            None => true,
+            Some(info) => {
+                vector <= info.vector
+                    || (vector.bridge == info.vector.bridge && vector.port == info.vector.port)
+            }
+        };
+        if replace {
+            stp_port.proposed = rapid && bpdu.has_flag(Bpdu::PROPOSAL);
+            stp_port.received = Some(ReceivedInfo {
+                vector,
+                message_age: bpdu.message_age,
+                expires_at: now + lifetime,
+            });
+        }
+
+        if bpdu.has_flag(Bpdu::TOPOLOGY_CHANGE) {
+            self.topology_changed = true;
+            // Relay the change along the active topology, but not back where it came from
+            if matches!(stp_port.role, PortRole::Root | PortRole::Designated) {
+                for (entity, other) in self.ports.iter_mut() {
+                    if *entity != port
+                        && !other.edge
+                        && matches!(other.role, PortRole::Root | PortRole::Designated)
+                        && other.tc_until <= now
+                    {
+                        other.tc_until = now + tc_duration;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the protocol at `now`: ages out stale information, elects the root bridge,
+    /// assigns port roles, advances port states and returns the BPDUs to send on each port.
+    pub fn update(&mut self, now: Duration) -> Vec<(Entity, Bpdu)> {
+        let rapid = self.version == StpVersion::Rstp;
+        for (_, port) in self.ports.iter_mut() {
+            if port.received.as_ref().is_some_and(|info| info.expires_at <= now) {
+                port.received = None;
+                port.proposed = false;
+            }
+        }
+
+        // Root election: the best path heard on any port competes with the bridge itself
+        let own = PriorityVector {
+            root: self.bridge_id.clone(),
+            root_path_cost: 0,
+            bridge: self.bridge_id.clone(),
+            port: 0,
+        };
+        let mut best: Option<(PriorityVector, u16, usize)> = None;
+        for (index, (_, port)) in self.ports.iter().enumerate() {
+            let Some(info) = &port.received else {
+                continue;
+            };
+            // Our own BPDUs looped back do not lead to the root
+            if info.vector.bridge == self.bridge_id {
+                continue;
+            }
+            let candidate = PriorityVector {
+                root_path_cost: info.vector.root_path_cost.saturating_add(port.cost),
+                ..info.vector.clone()
+            };
+            // Ties are broken by the lower receiving port
+            if best
+                .as_ref()
+                .is_none_or(|(vector, port_id, _)| (&candidate, port.port_id) < (vector, *port_id))
+            {
+                best = Some((candidate, port.port_id, index));
+            }
+        }
+        let root_index = match best {
+            Some((vector, _, index)) if vector < own => {
+                if vector.root != self.root_id || vector.root_path_cost != self.root_path_cost {
+                    self.send_now = true;
+                }
+                self.root_id = vector.root;
+                self.root_path_cost = vector.root_path_cost;
+                self.root_message_age = self.ports[index]
+                    .1
+                    .received
+                    .as_ref()
+                    .map_or(Duration::ZERO, |info| info.message_age);
+                Some(index)
+            }
+            _ => {
+                if self.root_id != self.bridge_id {
+                    self.send_now = true;
+                }
+                self.root_id = self.bridge_id.clone();
+                self.root_path_cost = 0;
+                self.root_message_age = Duration::ZERO;
+                None
+            }
+        };
+        self.root_port = root_index.map(|index| self.ports[index].0);
+
+        // Port roles: designated unless someone else offers the segment a better path
+        for (index, (_, port)) in self.ports.iter_mut().enumerate() {
+            let role = if !port.enabled {
+                PortRole::Disabled
+            } else if Some(index) == root_index {
+                PortRole::Root
+            } else {
+                let designated = PriorityVector {
+                    root: self.root_id.clone(),
+                    root_path_cost: self.root_path_cost,
+                    bridge: self.bridge_id.clone(),
+                    port: port.port_id,
+                };
+                match &port.received {
+                    Some(info) if info.vector < designated && info.vector.bridge == self.bridge_id => {
+                        PortRole::Backup
+                    }
+                    Some(info) if info.vector < designated => PortRole::Alternate,
+                    _ => PortRole::Designated,
+                }
+            };
+            if role != port.role {
+                port.role = role;
+                port.agreed = false;
+                self.send_now = true;
+            }
+        }
+
+        // RSTP: proposals are answered with an agreement once the port cannot cause a loop.
+        // Alternate and backup ports already discard. On the root port the bridge first syncs
+        // by blocking every other non-edge designated port, after which the root port may
+        // forward at once.
+        let mut agreements = Vec::new();
+        for index in 0..self.ports.len() {
+            let port = &mut self.ports[index].1;
+            if !std::mem::take(&mut port.proposed) || !rapid {
+                continue;
+            }
+            match port.role {
+                PortRole::Alternate | PortRole::Backup => agreements.push(index),
+                PortRole::Root => {
+                    for (other, (_, port)) in self.ports.iter_mut().enumerate() {
+                        if other != index && port.role == PortRole::Designated && !port.edge {
+                            port.state = PortState::Blocking;
+                            port.next_transition = None;
+                            port.agreed = false;
+                        }
+                    }
+                    agreements.push(index);
+                    self.send_now = true;
+                }
+                PortRole::Designated | PortRole::Disabled => {}
+            }
+        }
+
+        // Port states
+        let mut topology_change = false;
+        for (_, port) in self.ports.iter_mut() {
+            match port.role {
+                PortRole::Alternate | PortRole::Backup | PortRole::Disabled => {
+                    if port.state.forwards() && !rapid {
+                        topology_change = true;
+                    }
+                    port.state = if port.enabled {
+                        PortState::Blocking
+                    } else {
+                        PortState::Disabled
+                    };
+                    port.next_transition = None;
+                }
+                PortRole::Root | PortRole::Designated => {
+                    if port.state.forwards() {
+                        continue;
+                    }
+                    if port.edge || (rapid && (port.role == PortRole::Root || port.agreed)) {
+                        port.state = PortState::Forwarding;
+                        port.next_transition = None;
+                        topology_change |= !port.edge;
+                        continue;
+                    }
+                    match (port.state, port.next_transition) {
+                        (PortState::Blocking | PortState::Disabled, _) => {
+                            port.state = if rapid {
+                                PortState::Learning
+                            } else {
+                                PortState::Listening
+                            };
+                            port.next_transition = Some(now + self.forward_delay);
+                        }
+                        (PortState::Listening, Some(at)) if at <= now => {
+                            port.state = PortState::Learning;
+                            port.next_transition = Some(now + self.forward_delay);
+                        }
+                        (PortState::Learning, Some(at)) if at <= now => {
+                            port.state = PortState::Forwarding;
+                            port.next_transition = None;
+                            topology_change = true;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if topology_change {
+            self.topology_changed = true;
+            let tc_until = now + self.tc_duration();
+            for (_, port) in self.ports.iter_mut() {
+                if !port.edge && matches!(port.role, PortRole::Root | PortRole::Designated) {
+                    port.tc_until = tc_until;
+                }
+            }
+            self.send_now = true;
+        }
+
+        // Designated ports send BPDUs every hello time; topology changes also go out the root port
+        let mut bpdus = Vec::new();
+        if self.send_now || now >= self.next_hello {
+            for (entity, port) in &self.ports {
+                let announces_change = port.role == PortRole::Root && port.tc_until > now;
+                if port.role == PortRole::Designated || announces_change {
+                    bpdus.push((*entity, self.bpdu_for(port, now)));
+                }
+            }
+            self.send_now = false;
+            self.next_hello = now + self.hello_time;
+        }
+        for index in agreements {
+            let (entity, port) = &self.ports[index];
+            let mut bpdu = self.bpdu_for(port, now);
+            bpdu.flags |= Bpdu::AGREEMENT;
+            bpdus.push((*entity, bpdu));
+        }
+        bpdus
+    }
+
+    fn bpdu_for(&self, port: &StpPort, now: Duration) -> Bpdu {
+        let mut flags = 0;
+        if port.tc_until > now {
+            flags |= Bpdu::TOPOLOGY_CHANGE;
+        }
+        if self.version == StpVersion::Rstp {
+            flags |= Bpdu::role_flags(port.role);
+            if port.state.learns() {
+                flags |= Bpdu::LEARNING;
+            }
+            if port.state.forwards() {
+                flags |= Bpdu::FORWARDING;
+            }
+            if port.role == PortRole::Designated && !port.state.forwards() && !port.edge {
+                flags |= Bpdu::PROPOSAL;
+            }
+        }
+        // Every bridge on the way from the root adds a second to the message age
+        let message_age = if self.is_root_bridge() {
+            Duration::ZERO
+        } else {
+            self.root_message_age + Duration::from_secs(1)
+        };
+        Bpdu {
+            version: self.version,
+            flags,
+            root_id: self.root_id.clone(),
+            root_path_cost: self.root_path_cost,
+            bridge_id: self.bridge_id.clone(),
+            port_id: port.port_id,
+            message_age,
+            max_age: self.max_age,
+            hello_time: self.hello_time,
+            forward_delay: self.forward_delay,
+        }
+    }
+
+    /// Next instant at which a timer expires, if a wakeup has not already been requested for it
+    pub fn next_wakeup(&mut self, now: Duration) -> Option<Duration> {
+        let next = self
+            .ports
+            .iter()
+            .flat_map(|(_, port)| {
+                [
+                    port.next_transition,
+                    port.received.as_ref().map(|info| info.expires_at),
+                    Some(port.tc_until).filter(|tc_until| *tc_until > now),
+                ]
+            })
+            .flatten()
+            .chain([self.next_hello])
+            .min()?;
+        if self.scheduled_wakeup == Some(next) {
+            return None;
+        }
+        self.scheduled_wakeup = Some(next);
+        Some(next)
+    }
+
+    /// Formats the state of the spanning tree like "show spanning-tree",
+    /// using `port_name` to label the ports
+    pub fn show<F: Fn(Entity) -> String>(&self, port_name: F) -> String {
+        let timers = format!(
+            "Hello Time {:>3} sec  Max Age {:>2} sec  Forward Delay {:>2} sec",
+            self.hello_time.as_secs(),
+            self.max_age.as_secs(),
+            self.forward_delay.as_secs()
+        );
+        let mut output = String::new();
+        output.push_str(&format!(
+            "  Spanning tree enabled protocol {}\n",
+            self.version
+        ));
+        output.push_str(&format!("  Root ID    Priority    {}\n", self.root_id.priority));
+        output.push_str(&format!(
+            "             Address     {}\n",
+            self.root_id.mac.to_dotted_string()
+        ));
+        match self.root_port.and_then(|root_port| {
+            self.port(root_port).map(|port| (root_port, port))
+        }) {
+            Some((root_port, port)) => {
+                output.push_str(&format!("             Cost        {}\n", self.root_path_cost));
+                output.push_str(&format!(
+                    "             Port        {} ({})\n",
+                    port.port_id & 0xFF,
+                    port_name(root_port)
+                ));
+            }
+            None => output.push_str("             This bridge is the root\n"),
+        }
+        output.push_str(&format!("             {}\n\n", timers));
+        output.push_str(&format!("  Bridge ID  Priority    {}\n", self.bridge_id.priority));
+        output.push_str(&format!(
+            "             Address     {}\n",
+            self.bridge_id.mac.to_dotted_string()
+        ));
+        output.push_str(&format!("             {}\n\n", timers));
+        output.push_str("Interface           Role Sts Cost      Prio.Nbr Type\n");
+        output.push_str("------------------- ---- --- --------- -------- --------------------------------\n");
+        for (entity, port) in &self.ports {
+            let priority_number = format!("{}.{}", port.port_id >> 8, port.port_id & 0xFF);
+            output.push_str(&format!(
+                "{:<19} {:<4} {:<3} {:<9} {:<8} {}\n",
+                port_name(*entity),
+                port.role,
+                port.state,
+                port.cost,
+                priority_number,
+                if port.edge { "P2p Edge" } else { "P2p" }
+            ));
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bridge_id(priority: u16, last_octet: u8) -> BridgeId {
+        BridgeId::new(
+            priority,
+            MacAddress::from_bytes([0x00, 0x11, 0x22, 0x00, 0x00, last_octet]),
+        )
+    }
+
+    fn config_bpdu() -> Bpdu {
+        Bpdu {
+            version: StpVersion::Stp,
+            flags: Bpdu::TOPOLOGY_CHANGE,
+            root_id: bridge_id(4096, 1),
+            root_path_cost: 19,
+            bridge_id: bridge_id(32768, 2),
+            port_id: 0x8003,
+            message_age: Duration::from_secs(1),
+            max_age: SpanningTree::DEFAULT_MAX_AGE,
+            hello_time: SpanningTree::DEFAULT_HELLO_TIME,
+            forward_delay: SpanningTree::DEFAULT_FORWARD_DELAY,
+        }
+    }
+
+    #[test]
+    fn encodes_config_bpdu() {
+        let bpdu = config_bpdu();
+        let bytes = bpdu.to_bytes();
+        assert_eq!(bytes.len(), 35);
+        assert_eq!(bytes[..5], [0x00, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(bytes[5..13], bpdu.root_id.to_bytes());
+        assert_eq!(bytes[13..17], [0, 0, 0, 19]);
+        assert_eq!(bytes[25..27], [0x80, 0x03]);
+        // Timers in 1/256 of a second: 1, 20, 2 and 15 seconds
+        assert_eq!(
+            bytes[27..35],
+            [0x01, 0x00, 0x14, 0x00, 0x02, 0x00, 0x0F, 0x00]
+        );
+        assert_eq!(Bpdu::from_bytes(&bytes).unwrap(), bpdu);
+    }
+
+    #[test]
+    fn round_trips_rst_bpdu() {
+        let bpdu = Bpdu {
+            version: StpVersion::Rstp,
+            flags: Bpdu::PROPOSAL | Bpdu::LEARNING | Bpdu::role_flags(PortRole::Designated),
+            ..config_bpdu()
+        };
+        let bytes = bpdu.to_bytes();
+        assert_eq!(bytes.len(), 36);
+        assert_eq!(bytes[2..4], [0x02, 0x02]);
+        let parsed = Bpdu::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, bpdu);
+        assert_eq!(parsed.port_role(), Some(PortRole::Designated));
+        assert!(parsed.has_flag(Bpdu::PROPOSAL));
+        assert!(!parsed.has_flag(Bpdu::AGREEMENT));
+    }
+
+    #[test]
+    fn rejects_malformed_bpdus() {
+        let bytes = config_bpdu().to_bytes();
+        assert_eq!(
+            Bpdu::from_bytes(&bytes[..20]).unwrap_err(),
+            FrameError::Truncated {
+                expected: 35,
+                actual: 20
+            }
+        );
+        assert!(matches!(
+            Bpdu::from_bytes(&[0x00, 0x00, 0x00, 0x80]),
+            Err(FrameError::InvalidBpdu(_))
+        ));
+        assert!(matches!(
+            Bpdu::from_bytes(&[0x00, 0x01, 0x00, 0x00]),
+            Err(FrameError::InvalidBpdu(_))
+        ));
+    }
+
+    // Bridges exchanging BPDUs over point-to-point links between their ports
+    struct Network {
+        bridges: Vec<SpanningTree>,
+        // Each port with the bridge and the port at the other end of its link
+        links: Vec<(Entity, usize, Entity)>,
+    }
+
+    impl Network {
+        fn new(bridges: Vec<SpanningTree>) -> Self {
+            Self {
+                bridges,
+                links: Vec::new(),
+            }
+        }
+
+        fn connect(&mut self, a: (usize, u32), b: (usize, u32), cost: u32) {
+            let (port_a, port_b) = (Entity::from_raw(a.1), Entity::from_raw(b.1));
+            self.bridges[a.0].add_port(port_a, cost);
+            self.bridges[b.0].add_port(port_b, cost);
+            self.links.push((port_a, b.0, port_b));
+            self.links.push((port_b, a.0, port_a));
+        }
+
+        fn run(&mut self, rounds: u64) {
+            for round in 0..rounds {
+                let now = SpanningTree::DEFAULT_HELLO_TIME * round as u32;
+                for index in 0..self.bridges.len() {
+                    for (port, bpdu) in self.bridges[index].update(now) {
+                        let (_, peer, peer_port) =
+                            self.links.iter().find(|(p, _, _)| *p == port).unwrap();
+                        self.bridges[*peer].receive_bpdu(*peer_port, &bpdu, now);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn elects_root_bridge_and_root_ports() {
+        let [a, b, c] = [1, 2, 3].map(|last_octet| {
+            SpanningTree::with_bridge_id(
+                StpVersion::Stp,
+                bridge_id(BridgeId::DEFAULT_PRIORITY, last_octet),
+            )
+        });
+        let mut network = Network::new(vec![c, b, a]);
+        let (a, b, c) = (2, 1, 0);
+        // Two parallel links between A and B, a slow link from A to C and a fast one from B
+        network.connect((a, 1), (b, 11), 19);
+        network.connect((a, 2), (b, 12), 19);
+        network.connect((a, 3), (c, 21), 100);
+        network.connect((b, 13), (c, 22), 4);
+        network.run(10);
+
+        // Equal priorities: the lowest MAC address wins
+        let root = bridge_id(BridgeId::DEFAULT_PRIORITY, 1);
+        for bridge in &network.bridges {
+            assert_eq!(bridge.root_id(), &root);
+        }
+        let bridge_a = &network.bridges[a];
+        assert!(bridge_a.is_root_bridge());
+        assert_eq!(bridge_a.root_port(), None);
+        for port in [1, 2, 3] {
+            assert_eq!(
+                bridge_a.port_role(Entity::from_raw(port)),
+                PortRole::Designated
+            );
+        }
+
+        // Same cost and designated bridge on both links: the lower sending port wins
+        let bridge_b = &network.bridges[b];
+        assert_eq!(bridge_b.root_port(), Some(Entity::from_raw(11)));
+        assert_eq!(bridge_b.root_path_cost(), 19);
+        assert_eq!(
+            bridge_b.port_role(Entity::from_raw(12)),
+            PortRole::Alternate
+        );
+        assert_eq!(
+            bridge_b.port_role(Entity::from_raw(13)),
+            PortRole::Designated
+        );
+
+        // The path through B costs 19 + 4, less than the direct link to A
+        let bridge_c = &network.bridges[c];
+        assert_eq!(bridge_c.root_port(), Some(Entity::from_raw(22)));
+        assert_eq!(bridge_c.root_path_cost(), 23);
+        assert_eq!(
+            bridge_c.port_role(Entity::from_raw(21)),
+            PortRole::Alternate
+        );
+        assert_eq!(
+            bridge_c.port_state(Entity::from_raw(21)),
+            PortState::Blocking
+        );
+    }
+
+    #[test]
+    fn lower_priority_wins_over_lower_mac() {
+        let mut network = Network::new(vec![
+            SpanningTree::with_bridge_id(StpVersion::Rstp, bridge_id(32768, 1)),
+            SpanningTree::with_bridge_id(StpVersion::Rstp, bridge_id(4096, 2)),
+        ]);
+        network.connect((0, 1), (1, 2), 4);
+        network.run(3);
+        assert!(network.bridges[1].is_root_bridge());
+        assert_eq!(network.bridges[0].root_port(), Some(Entity::from_raw(1)));
+        assert_eq!(network.bridges[0].root_id(), &bridge_id(4096, 2));
+    }
+}
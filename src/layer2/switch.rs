@@ -0,0 +1,264 @@
+use super::address::MacAddress;
+use super::pdu::{EthernetFrame, VlanTag};
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+pub const DEFAULT_VLAN: u16 = 1;
+
+/// Marks an interface as a port of a `Switch` and holds its VLAN configuration.
+/// Frames received on switch ports are forwarded by the switch instead of being
+/// processed by the interface itself.
+#[derive(Component, Debug, Clone)]
+pub struct SwitchPort {
+    pub mode: PortMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortMode {
+    // Carries a single VLAN, untagged
+    Access { vlan: u16 },
+    // Carries several VLANs tagged, except the native VLAN which is sent untagged.
+    // `allowed_vlans` set to None allows every VLAN.
+    Trunk {
+        allowed_vlans: Option<Vec<u16>>,
+        native_vlan: u16,
+    },
+}
+
+impl SwitchPort {
+    pub fn access(vlan: u16) -> Self {
+        Self {
+            mode: PortMode::Access { vlan },
+        }
+    }
+
+    pub fn trunk(native_vlan: u16, allowed_vlans: Option<Vec<u16>>) -> Self {
+        Self {
+            mode: PortMode::Trunk {
+                allowed_vlans,
+                native_vlan,
+            },
+        }
+    }
+
+    // Whether frames of `vlan` may enter or leave through this port
+    pub fn carries(&self, vlan: u16) -> bool {
+        match &self.mode {
+            PortMode::Access { vlan: access_vlan } => *access_vlan == vlan,
+            PortMode::Trunk { allowed_vlans, .. } => allowed_vlans
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(&vlan)),
+        }
+    }
+
+    /// Classifies a frame received on this port into a VLAN.
+    /// Returns None if the port does not accept the frame.
+    pub fn ingress(&self, frame: &EthernetFrame) -> Option<u16> {
+        let vlan = match (&self.mode, frame.vlan) {
+            (PortMode::Access { vlan }, None) => *vlan,
+            // Access ports do not accept tagged frames
+            (PortMode::Access { .. }, Some(_)) => return None,
+            (PortMode::Trunk { native_vlan, .. }, None) => *native_vlan,
+            (PortMode::Trunk { .. }, Some(tag)) => tag.vid(),
+        };
+        if !self.carries(vlan) {
+            return None;
+        }
+        Some(vlan)
+    }
+
+    /// Prepares a copy of a frame classified into `vlan` to be sent out of this port,
+    /// pushing an 802.1Q tag on trunks unless it belongs to the native VLAN.
+    /// Returns None if the port does not carry the VLAN.
+    pub fn egress(&self, frame: &EthernetFrame, vlan: u16) -> Option<EthernetFrame> {
+        if !self.carries(vlan) {
+            return None;
+        }
+        let tag = match &self.mode {
+            PortMode::Access { .. } => None,
+            PortMode::Trunk { native_vlan, .. } if *native_vlan == vlan => None,
+            // Keep the priority the frame came in with
+            PortMode::Trunk { .. } => Some(VlanTag::new(
+                vlan,
+                frame.vlan.map_or(0, |tag| tag.pcp()),
+            )),
+        };
+        let mut frame = frame.clone();
+        if frame.vlan.map(|tag| tag.vid()) != tag.map(|tag| tag.vid()) {
+            frame.vlan = tag;
+            frame.update_fcs();
+        }
+        Some(frame)
+    }
+}
+
+impl Default for SwitchPort {
+    fn default() -> Self {
+        Self::access(DEFAULT_VLAN)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacEntryType {
+    Dynamic,
+    Static,
+}
+
+impl fmt::Display for MacEntryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacEntryType::Dynamic => f.pad("DYNAMIC"),
+            MacEntryType::Static => f.pad("STATIC"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MacTableEntry {
+    pub vlan: u16,
+    pub port: Entity,
+    pub entry_type: MacEntryType,
+    // Simulation time at which the address was last seen as a source
+    pub last_seen: Duration,
+}
+
+#[derive(Debug)]
+pub struct MacAddressTable {
+    // Addresses are learned separately in every VLAN
+    entries: HashMap<(u16, MacAddress), MacTableEntry>,
+    aging_time: Duration,
+}
+
+impl MacAddressTable {
+    pub const DEFAULT_AGING_TIME: Duration = Duration::from_secs(300);
+
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            aging_time: Self::DEFAULT_AGING_TIME,
+        }
+    }
+
+    pub fn aging_time(&self) -> Duration {
+        self.aging_time
+    }
+
+    pub fn set_aging_time(&mut self, aging_time: Duration) {
+        self.aging_time = aging_time;
+    }
+
+    /// Records that `mac` was seen as a source on `port` in `vlan`.
+    /// Static entries are never overwritten by learning.
+    pub fn learn(&mut self, vlan: u16, mac: MacAddress, port: Entity, now: Duration) {
+        match self.entries.get_mut(&(vlan, mac.clone())) {
+            Some(entry) if entry.entry_type == MacEntryType::Static => {}
+            Some(entry) => {
+                entry.port = port;
+                entry.last_seen = now;
+            }
+            None => {
+                self.entries.insert(
+                    (vlan, mac),
+                    MacTableEntry {
+                        vlan,
+                        port,
+                        entry_type: MacEntryType::Dynamic,
+                        last_seen: now,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn add_static_entry(&mut self, vlan: u16, mac: MacAddress, port: Entity) {
+        self.entries.insert(
+            (vlan, mac),
+            MacTableEntry {
+                vlan,
+                port,
+                entry_type: MacEntryType::Static,
+                last_seen: Duration::ZERO,
+            },
+        );
+    }
+
+    // Returns the port behind which `mac` lives in `vlan`, ignoring entries that have aged out
+    pub fn lookup(&self, vlan: u16, mac: &MacAddress, now: Duration) -> Option<Entity> {
+        self.entries
+            .get(&(vlan, mac.clone()))
+            .filter(|entry| !self.is_expired(entry, now))
+            .map(|entry| entry.port)
+    }
+
+    // Removes dynamic entries that have not been refreshed within the aging time
+    pub fn age_out(&mut self, now: Duration) {
+        let aging_time = self.aging_time;
+        self.entries.retain(|_, entry| {
+            entry.entry_type == MacEntryType::Static || now.saturating_sub(entry.last_seen) <= aging_time
+        });
+    }
+
+    // Removes every dynamic entry
+    pub fn clear_dynamic(&mut self) {
+        self.entries
+            .retain(|_, entry| entry.entry_type == MacEntryType::Static);
+    }
+
+    // Removes the dynamic entries learned on `port`, e.g. when its link goes down
+    pub fn flush_port(&mut self, port: Entity) {
+        self.entries
+            .retain(|_, entry| entry.entry_type == MacEntryType::Static || entry.port != port);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn is_expired(&self, entry: &MacTableEntry, now: Duration) -> bool {
+        entry.entry_type == MacEntryType::Dynamic
+            && now.saturating_sub(entry.last_seen) > self.aging_time
+    }
+
+    /// Formats the table like "show mac address-table", using `port_name` to label the ports
+    pub fn show<F: Fn(Entity) -> String>(&self, now: Duration, port_name: F) -> String {
+        let mut entries: Vec<(&MacAddress, &MacTableEntry)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !self.is_expired(entry, now))
+            .map(|((_, mac), entry)| (mac, entry))
+            .collect();
+        entries.sort_by_key(|(mac, entry)| (entry.vlan, mac.to_bytes()));
+
+        let mut output = String::new();
+        output.push_str("          Mac Address Table\n");
+        output.push_str("-------------------------------------------\n\n");
+        output.push_str("Vlan    Mac Address       Type        Ports\n");
+        output.push_str("----    -----------       --------    -----\n");
+        for (mac, entry) in &entries {
+            output.push_str(&format!(
+                "{:>4}    {:<14}    {:<8}    {}\n",
+                entry.vlan,
+                mac.to_dotted_string(),
+                entry.entry_type,
+                port_name(entry.port)
+            ));
+        }
+        output.push_str(&format!(
+            "Total Mac Addresses for this criterion: {}\n",
+            entries.len()
+        ));
+        output
+    }
+}
+
+impl Default for MacAddressTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
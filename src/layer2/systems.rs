@@ -1,9 +1,10 @@
-use super::{address::MacAddress, interface::Interface, pdu::EthernetFrame};
+use super::{arp::AddressConflict, interface::Interface, switch::SwitchPort};
+use crate::simulation::entity::{EventScheduler, SimClock};
 use bevy::prelude::*;
 
-pub fn peek_queues(query_interface: Query<(&mut Interface, &Name)>) {
+pub fn peek_queues(clock: Res<SimClock>, query_interface: Query<(&mut Interface, &Name)>) {
     println!("--------------------------------");
-    println!("Time step");
+    println!("Time step: {:?}", clock.now());
     for (interface, name) in query_interface.iter() {
         if let Interface::Ethernet(int) = interface {
             println!("\n  Peeking queues for interface {:?}", name);
@@ -12,6 +13,10 @@ pub fn peek_queues(query_interface: Query<(&mut Interface, &Name)>) {
                 Some(f) => println!("    Incoming queue: {}", f),
                 None => println!("    Incoming queue: Empty"),
             }
+            if let Some(scheduler) = &int.egress_scheduler {
+                println!("    Outgoing queues: {} frames", scheduler.len());
+                continue;
+            }
             let frame = int.out_queue.peek();
             match frame {
                 Some(f) => println!("    Outgoing queue: {}", f),
@@ -21,21 +26,59 @@ pub fn peek_queues(query_interface: Query<(&mut Interface, &Name)>) {
     }
 }
 
-pub fn update_interfaces(mut query_interface: Query<&mut Interface>) {
+// Retries the outstanding ARP requests and neighbor solicitations, gives up on the neighbors
+// that never answered, and runs duplicate address detection and router advertisements
+pub fn update_interfaces(
+    clock: Res<SimClock>,
+    mut scheduler: ResMut<EventScheduler>,
+    mut query_interface: Query<&mut Interface>,
+) {
+    let now = clock.now();
     for mut interface in query_interface.iter_mut() {
         if let Interface::Ethernet(int) = &mut *interface {
             // int.short_circuit_queues();
+            for packet in int.update_arp(now) {
+                println!(
+                    "\nDropping packet to {}: ARP resolution failed",
+                    packet.header.dest
+                );
+            }
+            for packet in int.update_ipv6(now) {
+                println!(
+                    "\nDropping packet to {}: neighbor resolution failed",
+                    packet.dest
+                );
+            }
+            let wakeups = [
+                int.arp_table.next_wakeup(),
+                int.neighbor_cache.next_wakeup(),
+                int.next_ipv6_wakeup(),
+            ];
+            for wakeup in wakeups.into_iter().flatten() {
+                scheduler.schedule_wakeup(wakeup);
+            }
         }
     }
 }
 
-pub fn process_frames(mut interfaces: Query<&mut Interface>) {
+// Switch ports are left to the switch, which forwards their frames instead
+pub fn process_frames(
+    clock: Res<SimClock>,
+    mut interfaces: Query<&mut Interface, Without<SwitchPort>>,
+) {
+    let now = clock.now();
     for mut interface in interfaces.iter_mut() {
         if let Interface::Ethernet(int) = &mut *interface {
             while !int.in_queue.is_empty() {
                 let frame = int.in_queue.dequeue().unwrap();
-                if frame.dest == int.mac_address || frame.dest == MacAddress::broadcast() {
-                    int.process_frame(&frame);
+                if !frame.has_valid_fcs() {
+                    int.stats.rx_crc_errors += 1;
+                    println!("\nDropping frame with invalid FCS from {}", frame.src);
+                    continue;
+                }
+                int.stats.rx_frames += 1;
+                if int.accepts_frame(&frame.dest) {
+                    int.process_frame(&frame, now);
                     println!("\nARP Table for interface:\n{}", int.arp_table);
                 } else {
                     println!(
@@ -46,3 +89,23 @@ pub fn process_frames(mut interfaces: Query<&mut Interface>) {
         }
     }
 }
+
+// Reports the duplicate addresses detected by the interfaces as `AddressConflict` events
+pub fn report_address_conflicts(
+    clock: Res<SimClock>,
+    mut interfaces: Query<(Entity, &mut Interface)>,
+    mut conflicts: EventWriter<AddressConflict>,
+) {
+    for (entity, mut interface) in interfaces.iter_mut() {
+        if let Interface::Ethernet(int) = &mut *interface {
+            for (address, mac) in int.take_address_conflicts() {
+                conflicts.send(AddressConflict {
+                    interface: entity,
+                    address,
+                    mac,
+                    time: clock.now(),
+                });
+            }
+        }
+    }
+}
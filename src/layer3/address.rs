@@ -1,5 +1,31 @@
-use regex::Regex;
 use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidIpv4(String),
+    InvalidIpv6(String),
+    InvalidMac(String),
+    InvalidPrefixLength(String),
+    // Subnet masks must be a run of ones followed by zeros
+    InvalidMask(Ipv4Addr),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidIpv4(value) => write!(f, "Invalid IPv4 address '{}'", value),
+            AddressError::InvalidIpv6(value) => write!(f, "Invalid IPv6 address '{}'", value),
+            AddressError::InvalidMac(value) => write!(f, "Invalid MAC address '{}'", value),
+            AddressError::InvalidPrefixLength(value) => {
+                write!(f, "Invalid prefix length '{}'", value)
+            }
+            AddressError::InvalidMask(mask) => write!(f, "Invalid subnet mask {}", mask),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Copy)]
 pub struct Ipv4Addr {
@@ -7,28 +33,9 @@ pub struct Ipv4Addr {
 }
 
 impl Ipv4Addr {
-    pub fn new(value: &str) -> Self {
-        let value = value.to_string();
-        let ipv4_address_regex = Regex::new(r"^(?:[0-9]{1,3}\.){3}[0-9]{1,3}$").unwrap();
-        // TODO: implement a custom error type
-        if !ipv4_address_regex.is_match(&value) {
-            panic!("Invalid IPv4 address format");
-        }
-        let octets: Vec<u8> = value
-            .split(".")
-            .map(|octet| octet.parse::<u8>().unwrap())
-            .collect();
-        Self {
-            octets: [octets[0], octets[1], octets[2], octets[3]],
-        }
-    }
-
-    pub fn to_string(&self) -> String {
-        self.octets
-            .iter()
-            .map(|octet| octet.to_string())
-            .collect::<Vec<String>>()
-            .join(".")
+    // Parses dotted decimal notation, e.g. "192.168.1.1"
+    pub fn new(value: &str) -> Result<Self, AddressError> {
+        value.parse()
     }
 
     pub fn get_network_address(&self, subnet_mask: &Ipv4Addr) -> Ipv4Addr {
@@ -52,6 +59,31 @@ impl Ipv4Addr {
         let [first, second, third, fourth] = self.octets;
         [first, second, third, fourth]
     }
+
+    pub fn from_u32(value: u32) -> Self {
+        Self {
+            octets: value.to_be_bytes(),
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        u32::from_be_bytes(self.octets)
+    }
+
+    // Subnet mask with the `prefix_length` most significant bits set
+    pub fn from_prefix_length(prefix_length: u8) -> Self {
+        let prefix_length = u32::from(prefix_length.min(32));
+        Self::from_u32(u32::MAX.checked_shl(32 - prefix_length).unwrap_or(0))
+    }
+
+    // Number of leading ones when used as a subnet mask
+    pub fn prefix_length(&self) -> u8 {
+        self.to_u32().leading_ones() as u8
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.octets == [255, 255, 255, 255]
+    }
 }
 
 impl fmt::Display for Ipv4Addr {
@@ -64,47 +96,745 @@ impl fmt::Display for Ipv4Addr {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+impl FromStr for Ipv4Addr {
+    type Err = AddressError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || AddressError::InvalidIpv4(value.to_string());
+        let mut octets = [0; 4];
+        let mut parts = value.split('.');
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or_else(invalid)?;
+            // u8::from_str would also accept signs, e.g. "+1"
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            *octet = part.parse().map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self { octets })
+    }
+}
+
+impl TryFrom<&str> for Ipv4Addr {
+    type Error = AddressError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// An IPv4 network given by its address and prefix length, e.g. 10.1.0.0/16.
+/// Host bits are always cleared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Ipv4Network {
+    address: Ipv4Addr,
+    prefix_length: u8,
+}
+
+impl Ipv4Network {
+    pub fn new(address: Ipv4Addr, prefix_length: u8) -> Result<Self, AddressError> {
+        if prefix_length > 32 {
+            return Err(AddressError::InvalidPrefixLength(prefix_length.to_string()));
+        }
+        let mask = Ipv4Addr::from_prefix_length(prefix_length);
+        Ok(Self {
+            address: address.get_network_address(&mask),
+            prefix_length,
+        })
+    }
+
+    pub fn with_mask(address: Ipv4Addr, mask: Ipv4Addr) -> Result<Self, AddressError> {
+        Self::new(address, mask_prefix_length(&mask)?)
+    }
+
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    pub fn mask(&self) -> Ipv4Addr {
+        Ipv4Addr::from_prefix_length(self.prefix_length)
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.address.to_u32() | !self.mask().to_u32())
+    }
+
+    pub fn contains(&self, address: &Ipv4Addr) -> bool {
+        address.get_network_address(&self.mask()) == self.address
+    }
+
+    // Whether `other` is this network or one of its subnets
+    pub fn contains_network(&self, other: &Ipv4Network) -> bool {
+        other.prefix_length >= self.prefix_length && self.contains(&other.address)
+    }
+
+    // Number of addresses in the network, network and broadcast addresses included
+    pub fn size(&self) -> u64 {
+        1 << (32 - u32::from(self.prefix_length))
+    }
+
+    /// First and last address that can be assigned to a host. Point-to-point /31 networks
+    /// use both of their addresses (RFC 3021) and a /32 is a single host.
+    pub fn host_range(&self) -> (Ipv4Addr, Ipv4Addr) {
+        let (first, last) = (self.address.to_u32(), self.broadcast().to_u32());
+        if self.prefix_length >= 31 {
+            (Ipv4Addr::from_u32(first), Ipv4Addr::from_u32(last))
+        } else {
+            (Ipv4Addr::from_u32(first + 1), Ipv4Addr::from_u32(last - 1))
+        }
+    }
+
+    // Every address of the network, from the network address to the broadcast address
+    pub fn iter(&self) -> impl Iterator<Item = Ipv4Addr> {
+        (self.address.to_u32()..=self.broadcast().to_u32()).map(Ipv4Addr::from_u32)
+    }
+
+    // Every address that can be assigned to a host
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let (first, last) = self.host_range();
+        (first.to_u32()..=last.to_u32()).map(Ipv4Addr::from_u32)
+    }
+}
+
+impl fmt::Display for Ipv4Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+impl FromStr for Ipv4Network {
+    type Err = AddressError;
+
+    // Accepts "10.0.0.0/8", "10.0.0.0/255.0.0.0" and "10.0.0.0 255.0.0.0"
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = parse_with_prefix(value)?;
+        Self::new(address, prefix_length)
+    }
+}
+
+impl TryFrom<&str> for Ipv4Network {
+    type Error = AddressError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// An address assigned to an interface together with the prefix length of its network,
+/// e.g. 10.1.2.3/16
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Ipv4Interface {
+    pub address: Ipv4Addr,
+    prefix_length: u8,
+}
+
+impl Ipv4Interface {
+    pub fn new(address: Ipv4Addr, prefix_length: u8) -> Result<Self, AddressError> {
+        if prefix_length > 32 {
+            return Err(AddressError::InvalidPrefixLength(prefix_length.to_string()));
+        }
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+
+    pub fn with_mask(address: Ipv4Addr, mask: Ipv4Addr) -> Result<Self, AddressError> {
+        Self::new(address, mask_prefix_length(&mask)?)
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    pub fn mask(&self) -> Ipv4Addr {
+        Ipv4Addr::from_prefix_length(self.prefix_length)
+    }
+
+    pub fn network(&self) -> Ipv4Network {
+        Ipv4Network {
+            address: self.address.get_network_address(&self.mask()),
+            prefix_length: self.prefix_length,
+        }
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        self.network().broadcast()
+    }
+
+    // Whether `address` is on the same network as the interface
+    pub fn contains(&self, address: &Ipv4Addr) -> bool {
+        self.network().contains(address)
+    }
+}
+
+impl fmt::Display for Ipv4Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+impl FromStr for Ipv4Interface {
+    type Err = AddressError;
+
+    // Accepts "10.0.0.1/24", "10.0.0.1/255.255.255.0" and "10.0.0.1 255.255.255.0"
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = parse_with_prefix(value)?;
+        Self::new(address, prefix_length)
+    }
+}
+
+impl TryFrom<&str> for Ipv4Interface {
+    type Error = AddressError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// Prefix length of a contiguous subnet mask
+fn mask_prefix_length(mask: &Ipv4Addr) -> Result<u8, AddressError> {
+    let prefix_length = mask.prefix_length();
+    if Ipv4Addr::from_prefix_length(prefix_length) != *mask {
+        return Err(AddressError::InvalidMask(*mask));
+    }
+    Ok(prefix_length)
+}
+
+// Splits "address/prefix", "address/mask" or "address mask"
+fn parse_with_prefix(value: &str) -> Result<(Ipv4Addr, u8), AddressError> {
+    let value = value.trim();
+    let (address, suffix) = value
+        .split_once('/')
+        .or_else(|| value.split_once(char::is_whitespace))
+        .ok_or_else(|| AddressError::InvalidPrefixLength(value.to_string()))?;
+    let address = address.trim().parse()?;
+    let suffix = suffix.trim();
+    let prefix_length = if suffix.contains('.') {
+        mask_prefix_length(&suffix.parse()?)?
+    } else {
+        suffix
+            .parse::<u8>()
+            .map_err(|_| AddressError::InvalidPrefixLength(suffix.to_string()))?
+    };
+    Ok((address, prefix_length))
+}
+
+/// A 128-bit IPv6 address, stored as its eight 16-bit groups
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Ipv6Addr {
-    pub value: String,
+    pub segments: [u16; 8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ipv6Scope {
+    Unspecified,
+    Loopback,
+    // fe80::/10, only valid on the attached link
+    LinkLocal,
+    // fc00::/7, routable within a site but not on the Internet (RFC 4193)
+    UniqueLocal,
+    Multicast,
+    Global,
+}
+
+impl fmt::Display for Ipv6Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ipv6Scope::Unspecified => f.pad("unspecified"),
+            Ipv6Scope::Loopback => f.pad("loopback"),
+            Ipv6Scope::LinkLocal => f.pad("link-local"),
+            Ipv6Scope::UniqueLocal => f.pad("unique-local"),
+            Ipv6Scope::Multicast => f.pad("multicast"),
+            Ipv6Scope::Global => f.pad("global"),
+        }
+    }
 }
 
 impl Ipv6Addr {
-    pub fn new(value: &str) -> Self {
-        // TODO: Consider using Rust's built-in Ipv6Addr type
-        // use std::net::Ipv6Addr;
-        // Something like this:
-        // fn validate_ipv6_address(address: &str) -> bool {
-        //    address.parse::<Ipv6Addr>().is_ok()
-        // }
-        let value = value.to_string();
-        let ipv6_address_regex = Regex::new(r"^(?:[0-9A-Fa-f]{1,4}:){7}(?:[0-9A-Fa-f]{1,4}|:)|(?:[0-9A-Fa-f]{1,4}:){6}(?::[0-9A-Fa-f]{1,4}|:[0-9A-Fa-f]{1,4}:\d+\.\d+\.\d+\.\d+|:)|(?:[0-9A-Fa-f]{1,4}:){5}(?::[0-9A-Fa-f]{1,4}|(?::[0-9A-Fa-f]{1,4}){1,2}|:[0-9A-Fa-f]{1,4}:\d+\.\d+\.\d+\.\d+|:)|(?:[0-9A-Fa-f]{1,4}:){4}(?::[0-9A-Fa-f]{1,4}|(?::[0-9A-Fa-f]{1,4}){1,3}|:[0-9A-Fa-f]{1,4}:\d+\.\d+\.\d+\.\d+|:)|(?:[0-9A-Fa-f]{1,4}:){3}(?::[0-9A-Fa-f]{1,4}|(?::[0-9A-Fa-f]{1,4}){1,4}|:[0-9A-Fa-f]{1,4}:\d+\.\d+\.\d+\.\d+|:)|(?:[0-9A-Fa-f]{1,4}:){2}(?::[0-9A-Fa-f]{1,4}|(?::[0-9A-Fa-f]{1,4}){1,5}|:[0-9A-Fa-f]{1,4}:\d+\.\d+\.\d+\.\d+|:)|(?:[0-9A-Fa-f]{1,4}:)(?::[0-9A-Fa-f]{1,4}|(?::[0-9A-Fa-f]{1,4}){1,6}|:[0-9A-Fa-f]{1,4}:\d+\.\d+\.\d+\.\d+|:)|(?:::(?:[0-9A-Fa-f]{1,4}|(?::[0-9A-Fa-f]{1,4}){1,7}|[0-9A-Fa-f]{1,4}:\d+\.\d+\.\d+\.\d+|:))$
-        ").unwrap();
-        // TODO: implement a custom error type
-        if !ipv6_address_regex.is_match(&value) {
-            panic!("Invalid IPv6 address format");
+    pub const UNSPECIFIED: Self = Self { segments: [0; 8] };
+    pub const LOCALHOST: Self = Self {
+        segments: [0, 0, 0, 0, 0, 0, 0, 1],
+    };
+    // Link-local scope multicast groups of every node and every router
+    pub const ALL_NODES: Self = Self {
+        segments: [0xff02, 0, 0, 0, 0, 0, 0, 1],
+    };
+    pub const ALL_ROUTERS: Self = Self {
+        segments: [0xff02, 0, 0, 0, 0, 0, 0, 2],
+    };
+
+    pub fn new(value: &str) -> Result<Self, AddressError> {
+        value.parse()
+    }
+
+    pub fn from_segments(segments: [u16; 8]) -> Self {
+        Self { segments }
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self::from_u128(u128::from_be_bytes(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.to_u128().to_be_bytes()
+    }
+
+    pub fn from_u128(value: u128) -> Self {
+        let mut segments = [0; 8];
+        for (i, segment) in segments.iter_mut().enumerate() {
+            *segment = (value >> (112 - 16 * i)) as u16;
+        }
+        Self { segments }
+    }
+
+    pub fn to_u128(&self) -> u128 {
+        self.segments
+            .iter()
+            .fold(0, |value, &segment| (value << 16) | u128::from(segment))
+    }
+
+    // Mask with the `prefix_length` most significant bits set
+    pub fn from_prefix_length(prefix_length: u8) -> Self {
+        let prefix_length = u32::from(prefix_length.min(128));
+        Self::from_u128(u128::MAX.checked_shl(128 - prefix_length).unwrap_or(0))
+    }
+
+    // The address with everything past the first `prefix_length` bits cleared
+    pub fn network_address(&self, prefix_length: u8) -> Ipv6Addr {
+        Self::from_u128(self.to_u128() & Self::from_prefix_length(prefix_length).to_u128())
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        *self == Self::LOCALHOST
+    }
+
+    pub fn is_link_local(&self) -> bool {
+        self.segments[0] & 0xffc0 == 0xfe80
+    }
+
+    pub fn is_unique_local(&self) -> bool {
+        self.segments[0] & 0xfe00 == 0xfc00
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.segments[0] & 0xff00 == 0xff00
+    }
+
+    pub fn scope(&self) -> Ipv6Scope {
+        if self.is_unspecified() {
+            Ipv6Scope::Unspecified
+        } else if self.is_loopback() {
+            Ipv6Scope::Loopback
+        } else if self.is_multicast() {
+            Ipv6Scope::Multicast
+        } else if self.is_link_local() {
+            Ipv6Scope::LinkLocal
+        } else if self.is_unique_local() {
+            Ipv6Scope::UniqueLocal
+        } else {
+            Ipv6Scope::Global
+        }
+    }
+
+    // Group joined for every unicast address to receive neighbor solicitations for it,
+    // ff02::1:ffXX:XXXX with the last 24 bits of the address (RFC 4291)
+    pub fn solicited_node_multicast(&self) -> Ipv6Addr {
+        Self::from_u128(0xff02_0000_0000_0000_0000_0001_ff00_0000 | (self.to_u128() & 0xff_ffff))
+    }
+
+    // The IPv4 address embedded in an IPv4-mapped address, ::ffff:a.b.c.d
+    pub fn to_ipv4_mapped(&self) -> Option<Ipv4Addr> {
+        match self.segments {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => {
+                Some(Ipv4Addr::from_u32((u32::from(high) << 16) | u32::from(low)))
+            }
+            _ => None,
         }
-        Self { value }
     }
 }
 
 impl fmt::Display for Ipv6Addr {
+    // Canonical text representation (RFC 5952): lowercase, no leading zeros, and the longest
+    // run of two or more zero groups (the first one on a tie) replaced by "::"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ipv4) = self.to_ipv4_mapped() {
+            return f.pad(&format!("::ffff:{}", ipv4));
+        }
+        let mut longest = (0, 0);
+        let mut start = 0;
+        for (i, segment) in self.segments.iter().enumerate() {
+            if *segment != 0 {
+                start = i + 1;
+            } else if i + 1 - start > longest.1 {
+                longest = (start, i + 1 - start);
+            }
+        }
+        let groups = |segments: &[u16]| {
+            segments
+                .iter()
+                .map(|segment| format!("{:x}", segment))
+                .collect::<Vec<_>>()
+                .join(":")
+        };
+        let text = if longest.1 >= 2 {
+            let (start, length) = longest;
+            format!(
+                "{}::{}",
+                groups(&self.segments[..start]),
+                groups(&self.segments[start + length..])
+            )
+        } else {
+            groups(&self.segments)
+        };
+        f.pad(&text)
+    }
+}
+
+impl FromStr for Ipv6Addr {
+    type Err = AddressError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .parse::<std::net::Ipv6Addr>()
+            .map(Self::from)
+            .map_err(|_| AddressError::InvalidIpv6(value.to_string()))
+    }
+}
+
+impl TryFrom<&str> for Ipv6Addr {
+    type Error = AddressError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<std::net::Ipv6Addr> for Ipv6Addr {
+    fn from(address: std::net::Ipv6Addr) -> Self {
+        Self::from_segments(address.segments())
+    }
+}
+
+impl From<Ipv6Addr> for std::net::Ipv6Addr {
+    fn from(address: Ipv6Addr) -> Self {
+        let [a, b, c, d, e, f, g, h] = address.segments;
+        std::net::Ipv6Addr::new(a, b, c, d, e, f, g, h)
+    }
+}
+
+/// An IPv6 prefix, e.g. 2001:db8::/32. Bits past the prefix are always cleared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Ipv6Network {
+    address: Ipv6Addr,
+    prefix_length: u8,
+}
+
+impl Ipv6Network {
+    pub fn new(address: Ipv6Addr, prefix_length: u8) -> Result<Self, AddressError> {
+        if prefix_length > 128 {
+            return Err(AddressError::InvalidPrefixLength(prefix_length.to_string()));
+        }
+        Ok(Self {
+            address: address.network_address(prefix_length),
+            prefix_length,
+        })
+    }
+
+    pub fn address(&self) -> Ipv6Addr {
+        self.address
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    pub fn contains(&self, address: &Ipv6Addr) -> bool {
+        address.network_address(self.prefix_length) == self.address
+    }
+
+    // Last address of the prefix
+    pub fn last_address(&self) -> Ipv6Addr {
+        let mask = Ipv6Addr::from_prefix_length(self.prefix_length).to_u128();
+        Ipv6Addr::from_u128(self.address.to_u128() | !mask)
+    }
+}
+
+impl fmt::Display for Ipv6Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+impl FromStr for Ipv6Network {
+    type Err = AddressError;
+
+    // Accepts "2001:db8::/32"
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = parse_ipv6_with_prefix(value)?;
+        Self::new(address, prefix_length)
+    }
+}
+
+impl TryFrom<&str> for Ipv6Network {
+    type Error = AddressError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// An IPv6 address assigned to an interface together with the length of its prefix,
+/// e.g. 2001:db8::1/64
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Ipv6Interface {
+    pub address: Ipv6Addr,
+    prefix_length: u8,
+}
+
+impl Ipv6Interface {
+    pub fn new(address: Ipv6Addr, prefix_length: u8) -> Result<Self, AddressError> {
+        if prefix_length > 128 {
+            return Err(AddressError::InvalidPrefixLength(prefix_length.to_string()));
+        }
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    pub fn network(&self) -> Ipv6Network {
+        Ipv6Network {
+            address: self.address.network_address(self.prefix_length),
+            prefix_length: self.prefix_length,
+        }
+    }
+
+    // Whether `address` is on the same prefix as the interface
+    pub fn contains(&self, address: &Ipv6Addr) -> bool {
+        self.network().contains(address)
+    }
+}
+
+impl fmt::Display for Ipv6Interface {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.value)
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+impl FromStr for Ipv6Interface {
+    type Err = AddressError;
+
+    // Accepts "2001:db8::1/64"
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = parse_ipv6_with_prefix(value)?;
+        Self::new(address, prefix_length)
+    }
+}
+
+impl TryFrom<&str> for Ipv6Interface {
+    type Error = AddressError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
     }
 }
 
+// Splits "address/prefix"
+fn parse_ipv6_with_prefix(value: &str) -> Result<(Ipv6Addr, u8), AddressError> {
+    let (address, prefix_length) = value
+        .trim()
+        .split_once('/')
+        .ok_or_else(|| AddressError::InvalidPrefixLength(value.to_string()))?;
+    let prefix_length = prefix_length
+        .parse::<u8>()
+        .map_err(|_| AddressError::InvalidPrefixLength(prefix_length.to_string()))?;
+    Ok((address.parse()?, prefix_length))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum IpAddr {
     V4(Ipv4Addr),
     V6(Ipv6Addr),
 }
 
 impl IpAddr {
-    pub fn new(value: &str) -> Self {
-        if value.contains(":") {
-            IpAddr::V6(Ipv6Addr::new(value))
+    pub fn new(value: &str) -> Result<Self, AddressError> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for IpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpAddr::V4(address) => write!(f, "{}", address),
+            IpAddr::V6(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+impl FromStr for IpAddr {
+    type Err = AddressError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.contains(':') {
+            Ok(IpAddr::V6(value.parse()?))
         } else {
-            IpAddr::V4(Ipv4Addr::new(value))
+            Ok(IpAddr::V4(value.parse()?))
+        }
+    }
+}
+
+impl TryFrom<&str> for IpAddr {
+    type Error = AddressError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ipv4_addresses() {
+        assert_eq!(
+            "192.168.1.1".parse::<Ipv4Addr>().unwrap().octets,
+            [192, 168, 1, 1]
+        );
+        assert_eq!("0.0.0.0".parse::<Ipv4Addr>().unwrap().octets, [0; 4]);
+    }
+
+    #[test]
+    fn rejects_invalid_ipv4_addresses() {
+        for value in [
+            "300.1.1.1",
+            "1.2.3",
+            "1.2.3.4.5",
+            "+1.2.3.4",
+            "",
+            "1..2.3",
+            "a.b.c.d",
+        ] {
+            assert_eq!(
+                value.parse::<Ipv4Addr>().unwrap_err(),
+                AddressError::InvalidIpv4(value.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn parses_prefixes_and_masks() {
+        let network: Ipv4Network = "10.1.2.3/16".parse().unwrap();
+        assert_eq!(network.to_string(), "10.1.0.0/16");
+        let network: Ipv4Network = "10.0.0.0 255.0.0.0".parse().unwrap();
+        assert_eq!(network.prefix_length(), 8);
+        let interface: Ipv4Interface = "10.0.0.1/255.255.255.0".parse().unwrap();
+        assert_eq!(interface.to_string(), "10.0.0.1/24");
+    }
+
+    #[test]
+    fn rejects_invalid_prefix_length() {
+        assert_eq!(
+            "10.0.0.1/33".parse::<Ipv4Interface>().unwrap_err(),
+            AddressError::InvalidPrefixLength("33".to_string())
+        );
+        assert_eq!(
+            "10.0.0.0/33".parse::<Ipv4Network>().unwrap_err(),
+            AddressError::InvalidPrefixLength("33".to_string())
+        );
+        assert_eq!(
+            "10.0.0.1/x".parse::<Ipv4Interface>().unwrap_err(),
+            AddressError::InvalidPrefixLength("x".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_non_contiguous_mask() {
+        let mask = Ipv4Addr::new("255.0.255.0").unwrap();
+        assert_eq!(
+            "10.0.0.1 255.0.255.0".parse::<Ipv4Interface>().unwrap_err(),
+            AddressError::InvalidMask(mask)
+        );
+        assert_eq!(
+            "10.0.0.0 255.0.255.0".parse::<Ipv4Network>().unwrap_err(),
+            AddressError::InvalidMask(mask)
+        );
+    }
+
+    fn ipv6(value: &str) -> Ipv6Addr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn formats_ipv6_canonically() {
+        for (value, text) in [
+            ("0:0:0:0:0:0:0:0", "::"),
+            ("1:0:0:0:0:0:0:0", "1::"),
+            ("0:0:0:0:0:0:0:1", "::1"),
+            ("2001:DB8:0000:0:0:0:0:0001", "2001:db8::1"),
+            // A single zero group is not shortened
+            ("2001:db8:0:1:1:1:1:1", "2001:db8:0:1:1:1:1:1"),
+            // The first of two runs of the same length is shortened
+            ("2001:db8:0:0:1:0:0:1", "2001:db8::1:0:0:1"),
+            // The longest run wins
+            ("2001:db8:0:0:1:0:0:0", "2001:db8:0:0:1::"),
+            ("2001:db8::1:0:0:0:1", "2001:db8:0:1::1"),
+            ("2001:0:0:1:0:0:0:1", "2001:0:0:1::1"),
+            ("0:0:0:0:0:ffff:c000:0201", "::ffff:192.0.2.1"),
+        ] {
+            assert_eq!(ipv6(value).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn classifies_ipv6_scopes() {
+        for (value, scope) in [
+            ("::", Ipv6Scope::Unspecified),
+            ("::1", Ipv6Scope::Loopback),
+            ("fe80::1", Ipv6Scope::LinkLocal),
+            ("febf:ffff::1", Ipv6Scope::LinkLocal),
+            ("fec0::1", Ipv6Scope::Global),
+            ("fc00::1", Ipv6Scope::UniqueLocal),
+            ("fd12:3456::1", Ipv6Scope::UniqueLocal),
+            ("ff02::1", Ipv6Scope::Multicast),
+            ("ff0e::1", Ipv6Scope::Multicast),
+            ("2001:db8::1", Ipv6Scope::Global),
+        ] {
+            assert_eq!(ipv6(value).scope(), scope, "{}", value);
+        }
+    }
+
+    #[test]
+    fn converts_ipv6_to_and_from_std() {
+        for value in [
+            "::",
+            "::1",
+            "fe80::211:22ff:fe33:4455",
+            "2001:db8::1:0:0:1",
+            "::ffff:192.0.2.1",
+        ] {
+            let std_address: std::net::Ipv6Addr = value.parse().unwrap();
+            let address = Ipv6Addr::from(std_address);
+            assert_eq!(address, ipv6(value));
+            assert_eq!(std::net::Ipv6Addr::from(address), std_address);
+            assert_eq!(address.to_string(), std_address.to_string());
         }
     }
 }
@@ -0,0 +1,18 @@
+// Internet checksum (RFC 1071) used by the IPv4 header and the ICMP family of protocols.
+// It is the one's complement of the one's complement sum of the data taken as 16-bit words.
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u32::from(u16::from_be_bytes([chunk[0], chunk[1]]));
+    }
+    // An odd trailing byte is padded with a zero byte
+    if let [last] = chunks.remainder() {
+        sum += u32::from(u16::from_be_bytes([*last, 0]));
+    }
+    // Fold the carries back into the lower 16 bits
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,386 @@
+use super::address::Ipv4Addr;
+use super::checksum::internet_checksum;
+use super::pdu::{Ipv4Packet, PacketError, Protocols};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnreachableCode {
+    Network,                    // 0
+    Host,                       // 1
+    Protocol,                   // 2
+    Port,                       // 3
+    FragmentationNeeded,        // 4
+    AdministrativelyProhibited, // 13
+}
+
+impl UnreachableCode {
+    pub fn get_value(&self) -> u8 {
+        match self {
+            UnreachableCode::Network => 0,
+            UnreachableCode::Host => 1,
+            UnreachableCode::Protocol => 2,
+            UnreachableCode::Port => 3,
+            UnreachableCode::FragmentationNeeded => 4,
+            UnreachableCode::AdministrativelyProhibited => 13,
+        }
+    }
+
+    pub fn from_value(value: u8) -> Result<Self, PacketError> {
+        match value {
+            0 => Ok(UnreachableCode::Network),
+            1 => Ok(UnreachableCode::Host),
+            2 => Ok(UnreachableCode::Protocol),
+            3 => Ok(UnreachableCode::Port),
+            4 => Ok(UnreachableCode::FragmentationNeeded),
+            13 => Ok(UnreachableCode::AdministrativelyProhibited),
+            _ => Err(PacketError::UnsupportedIcmp {
+                icmp_type: IcmpMessage::DESTINATION_UNREACHABLE,
+                code: value,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for UnreachableCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnreachableCode::Network => write!(f, "Destination net unreachable"),
+            UnreachableCode::Host => write!(f, "Destination host unreachable"),
+            UnreachableCode::Protocol => write!(f, "Destination protocol unreachable"),
+            UnreachableCode::Port => write!(f, "Destination port unreachable"),
+            UnreachableCode::FragmentationNeeded => write!(f, "Fragmentation needed and DF set"),
+            UnreachableCode::AdministrativelyProhibited => {
+                write!(f, "Communication administratively prohibited")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeExceededCode {
+    TtlExceeded,        // 0
+    FragmentReassembly, // 1
+}
+
+impl TimeExceededCode {
+    pub fn get_value(&self) -> u8 {
+        match self {
+            TimeExceededCode::TtlExceeded => 0,
+            TimeExceededCode::FragmentReassembly => 1,
+        }
+    }
+
+    pub fn from_value(value: u8) -> Result<Self, PacketError> {
+        match value {
+            0 => Ok(TimeExceededCode::TtlExceeded),
+            1 => Ok(TimeExceededCode::FragmentReassembly),
+            _ => Err(PacketError::UnsupportedIcmp {
+                icmp_type: IcmpMessage::TIME_EXCEEDED,
+                code: value,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for TimeExceededCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeExceededCode::TtlExceeded => write!(f, "Time to live exceeded"),
+            TimeExceededCode::FragmentReassembly => write!(f, "Fragment reassembly time exceeded"),
+        }
+    }
+}
+
+/// ICMP message as carried in the payload of an IPv4 packet (RFC 792).
+/// Error messages quote the IP header and the first 8 payload bytes of the offending packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IcmpMessage {
+    EchoReply {
+        identifier: u16,
+        sequence: u16,
+        data: Vec<u8>,
+    },
+    EchoRequest {
+        identifier: u16,
+        sequence: u16,
+        data: Vec<u8>,
+    },
+    DestinationUnreachable {
+        code: UnreachableCode,
+        // Only meaningful for FragmentationNeeded (RFC 1191)
+        next_hop_mtu: u16,
+        original: Vec<u8>,
+    },
+    TimeExceeded {
+        code: TimeExceededCode,
+        original: Vec<u8>,
+    },
+}
+
+impl IcmpMessage {
+    pub const ECHO_REPLY: u8 = 0;
+    pub const DESTINATION_UNREACHABLE: u8 = 3;
+    pub const ECHO_REQUEST: u8 = 8;
+    pub const TIME_EXCEEDED: u8 = 11;
+    const HEADER_LENGTH: usize = 8;
+    // Payload bytes of the offending packet quoted after its header
+    const QUOTED_PAYLOAD_LENGTH: usize = 8;
+
+    pub fn echo_request(identifier: u16, sequence: u16, data: Vec<u8>) -> Self {
+        IcmpMessage::EchoRequest {
+            identifier,
+            sequence,
+            data,
+        }
+    }
+
+    pub fn destination_unreachable(code: UnreachableCode, packet: &Ipv4Packet) -> Self {
+        IcmpMessage::DestinationUnreachable {
+            code,
+            next_hop_mtu: 0,
+            original: Self::quote(packet),
+        }
+    }
+
+    pub fn fragmentation_needed(packet: &Ipv4Packet, next_hop_mtu: u16) -> Self {
+        IcmpMessage::DestinationUnreachable {
+            code: UnreachableCode::FragmentationNeeded,
+            next_hop_mtu,
+            original: Self::quote(packet),
+        }
+    }
+
+    pub fn time_exceeded(packet: &Ipv4Packet) -> Self {
+        IcmpMessage::TimeExceeded {
+            code: TimeExceededCode::TtlExceeded,
+            original: Self::quote(packet),
+        }
+    }
+
+    fn quote(packet: &Ipv4Packet) -> Vec<u8> {
+        let mut bytes = packet.to_bytes();
+        bytes.truncate(packet.header_length() + Self::QUOTED_PAYLOAD_LENGTH);
+        bytes
+    }
+
+    pub fn icmp_type(&self) -> u8 {
+        match self {
+            IcmpMessage::EchoReply { .. } => Self::ECHO_REPLY,
+            IcmpMessage::DestinationUnreachable { .. } => Self::DESTINATION_UNREACHABLE,
+            IcmpMessage::EchoRequest { .. } => Self::ECHO_REQUEST,
+            IcmpMessage::TimeExceeded { .. } => Self::TIME_EXCEEDED,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            IcmpMessage::EchoReply { .. } | IcmpMessage::EchoRequest { .. } => 0,
+            IcmpMessage::DestinationUnreachable { code, .. } => code.get_value(),
+            IcmpMessage::TimeExceeded { code, .. } => code.get_value(),
+        }
+    }
+
+    // Error messages must never trigger further ICMP errors
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            IcmpMessage::DestinationUnreachable { .. } | IcmpMessage::TimeExceeded { .. }
+        )
+    }
+
+    // Header and leading payload bytes of the packet an error message refers to
+    pub fn original(&self) -> Option<&[u8]> {
+        match self {
+            IcmpMessage::DestinationUnreachable { original, .. }
+            | IcmpMessage::TimeExceeded { original, .. } => Some(original),
+            _ => None,
+        }
+    }
+
+    // Identifier and sequence number of the echo request an error message refers to
+    pub fn quoted_echo(&self) -> Option<(u16, u16)> {
+        let original = self.original()?;
+        let header_length = usize::from(original.first()? & 0x0F) * 4;
+        if *original.get(9)? != Protocols::ICMP.get_value() {
+            return None;
+        }
+        let echo = original.get(header_length..header_length + 8)?;
+        if echo[0] != Self::ECHO_REQUEST {
+            return None;
+        }
+        Some((
+            u16::from_be_bytes([echo[4], echo[5]]),
+            u16::from_be_bytes([echo[6], echo[7]]),
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.icmp_type(), self.code(), 0, 0];
+        match self {
+            IcmpMessage::EchoReply {
+                identifier,
+                sequence,
+                data,
+            }
+            | IcmpMessage::EchoRequest {
+                identifier,
+                sequence,
+                data,
+            } => {
+                bytes.extend_from_slice(&identifier.to_be_bytes());
+                bytes.extend_from_slice(&sequence.to_be_bytes());
+                bytes.extend_from_slice(data);
+            }
+            IcmpMessage::DestinationUnreachable {
+                next_hop_mtu,
+                original,
+                ..
+            } => {
+                bytes.extend_from_slice(&[0, 0]); // Unused
+                bytes.extend_from_slice(&next_hop_mtu.to_be_bytes());
+                bytes.extend_from_slice(original);
+            }
+            IcmpMessage::TimeExceeded { original, .. } => {
+                bytes.extend_from_slice(&[0, 0, 0, 0]); // Unused
+                bytes.extend_from_slice(original);
+            }
+        }
+        let checksum = internet_checksum(&bytes);
+        bytes[2..4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    // Parses an ICMP message, verifying its checksum
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(PacketError::Truncated {
+                expected: Self::HEADER_LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        let checksum = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if internet_checksum(bytes) != 0 {
+            let mut message = bytes.to_vec();
+            message[2..4].copy_from_slice(&[0, 0]);
+            return Err(PacketError::ChecksumMismatch {
+                expected: internet_checksum(&message),
+                actual: checksum,
+            });
+        }
+
+        let (icmp_type, code) = (bytes[0], bytes[1]);
+        let identifier = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let sequence = u16::from_be_bytes([bytes[6], bytes[7]]);
+        let rest = bytes[Self::HEADER_LENGTH..].to_vec();
+        match (icmp_type, code) {
+            (Self::ECHO_REPLY, 0) => Ok(IcmpMessage::EchoReply {
+                identifier,
+                sequence,
+                data: rest,
+            }),
+            (Self::ECHO_REQUEST, 0) => Ok(IcmpMessage::EchoRequest {
+                identifier,
+                sequence,
+                data: rest,
+            }),
+            (Self::DESTINATION_UNREACHABLE, code) => Ok(IcmpMessage::DestinationUnreachable {
+                code: UnreachableCode::from_value(code)?,
+                next_hop_mtu: sequence,
+                original: rest,
+            }),
+            (Self::TIME_EXCEEDED, code) => Ok(IcmpMessage::TimeExceeded {
+                code: TimeExceededCode::from_value(code)?,
+                original: rest,
+            }),
+            (icmp_type, code) => Err(PacketError::UnsupportedIcmp { icmp_type, code }),
+        }
+    }
+}
+
+impl fmt::Display for IcmpMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcmpMessage::EchoReply {
+                identifier,
+                sequence,
+                data,
+            } => write!(
+                f,
+                "Echo reply (id {}, seq {}, {} bytes)",
+                identifier,
+                sequence,
+                data.len()
+            ),
+            IcmpMessage::EchoRequest {
+                identifier,
+                sequence,
+                data,
+            } => write!(
+                f,
+                "Echo request (id {}, seq {}, {} bytes)",
+                identifier,
+                sequence,
+                data.len()
+            ),
+            IcmpMessage::DestinationUnreachable {
+                code: UnreachableCode::FragmentationNeeded,
+                next_hop_mtu,
+                ..
+            } => write!(
+                f,
+                "{} (next-hop MTU {})",
+                UnreachableCode::FragmentationNeeded,
+                next_hop_mtu
+            ),
+            IcmpMessage::DestinationUnreachable { code, .. } => write!(f, "{}", code),
+            IcmpMessage::TimeExceeded { code, .. } => write!(f, "{}", code),
+        }
+    }
+}
+
+/// Builds the ICMP error `message` from `source` back to the sender of `packet`, unless the
+/// rules of RFC 1122 forbid it: no errors about ICMP errors, broadcasts or non-initial fragments.
+pub fn error_for(packet: &Ipv4Packet, source: Ipv4Addr, message: IcmpMessage) -> Option<Ipv4Packet> {
+    if packet.header.dest.is_broadcast() || packet.header.fragment_offset != 0 {
+        return None;
+    }
+    if packet.icmp_message().is_some_and(|icmp| icmp.is_error()) {
+        return None;
+    }
+    Some(Ipv4Packet::icmp(source, packet.header.src, &message))
+}
+
+/// Answer of a host to a packet delivered to its address `local`: an echo reply to echo
+/// requests, and port or protocol unreachable for transports nothing listens on
+pub fn reply_to(packet: &Ipv4Packet, local: Ipv4Addr) -> Option<Ipv4Packet> {
+    match packet.header.protocol {
+        Protocols::ICMP => match packet.icmp_message()? {
+            IcmpMessage::EchoRequest {
+                identifier,
+                sequence,
+                data,
+            } => Some(Ipv4Packet::icmp(
+                local,
+                packet.header.src,
+                &IcmpMessage::EchoReply {
+                    identifier,
+                    sequence,
+                    data,
+                },
+            )),
+            _ => None,
+        },
+        Protocols::UDP => error_for(
+            packet,
+            local,
+            IcmpMessage::destination_unreachable(UnreachableCode::Port, packet),
+        ),
+        // TCP would answer with a reset, which is not modeled
+        Protocols::TCP => None,
+        _ => error_for(
+            packet,
+            local,
+            IcmpMessage::destination_unreachable(UnreachableCode::Protocol, packet),
+        ),
+    }
+}
@@ -0,0 +1,330 @@
+use super::address::{Ipv6Addr, Ipv6Network};
+use super::checksum::internet_checksum;
+use super::pdu::{PacketError, Protocols};
+use crate::layer2::address::MacAddress;
+use std::fmt;
+
+/// Prefix Information option of a router advertisement (RFC 4861 section 4.6.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixInformation {
+    pub prefix: Ipv6Network,
+    // Addresses with this prefix are reached directly on the link
+    pub on_link: bool,
+    // Hosts may form addresses from this prefix (SLAAC)
+    pub autonomous: bool,
+    // In seconds
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+}
+
+/// ICMPv6 Neighbor Discovery messages (RFC 4861), as carried in the payload of an IPv6 packet.
+/// The link-layer address options are decoded; other options are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Icmpv6Message {
+    RouterSolicitation {
+        source_link_layer: Option<MacAddress>,
+    },
+    RouterAdvertisement {
+        // Hop limit hosts should use, 0 if unspecified
+        hop_limit: u8,
+        managed: bool,
+        other: bool,
+        // In seconds; 0 means the router is not a default router
+        router_lifetime: u16,
+        // In milliseconds, 0 if unspecified
+        reachable_time: u32,
+        retrans_timer: u32,
+        source_link_layer: Option<MacAddress>,
+        mtu: Option<u32>,
+        prefixes: Vec<PrefixInformation>,
+    },
+    NeighborSolicitation {
+        target: Ipv6Addr,
+        source_link_layer: Option<MacAddress>,
+    },
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        // Whether the advertisement replaces a link-layer address already cached
+        override_flag: bool,
+        target: Ipv6Addr,
+        target_link_layer: Option<MacAddress>,
+    },
+}
+
+impl Icmpv6Message {
+    pub const ROUTER_SOLICITATION: u8 = 133;
+    pub const ROUTER_ADVERTISEMENT: u8 = 134;
+    pub const NEIGHBOR_SOLICITATION: u8 = 135;
+    pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+    const HEADER_LENGTH: usize = 4;
+
+    // Option types
+    const SOURCE_LINK_LAYER: u8 = 1;
+    const TARGET_LINK_LAYER: u8 = 2;
+    const PREFIX_INFORMATION: u8 = 3;
+    const MTU: u8 = 5;
+
+    pub fn icmp_type(&self) -> u8 {
+        match self {
+            Icmpv6Message::RouterSolicitation { .. } => Self::ROUTER_SOLICITATION,
+            Icmpv6Message::RouterAdvertisement { .. } => Self::ROUTER_ADVERTISEMENT,
+            Icmpv6Message::NeighborSolicitation { .. } => Self::NEIGHBOR_SOLICITATION,
+            Icmpv6Message::NeighborAdvertisement { .. } => Self::NEIGHBOR_ADVERTISEMENT,
+        }
+    }
+
+    // Link-layer address of the sender, from the source or target link-layer address option
+    pub fn link_layer_address(&self) -> Option<&MacAddress> {
+        match self {
+            Icmpv6Message::RouterSolicitation { source_link_layer }
+            | Icmpv6Message::RouterAdvertisement {
+                source_link_layer, ..
+            }
+            | Icmpv6Message::NeighborSolicitation {
+                source_link_layer, ..
+            } => source_link_layer.as_ref(),
+            Icmpv6Message::NeighborAdvertisement {
+                target_link_layer, ..
+            } => target_link_layer.as_ref(),
+        }
+    }
+
+    /// Encodes the message, with the checksum covering the IPv6 pseudo-header of a packet
+    /// from `src` to `dest` (RFC 8200 section 8.1)
+    pub fn to_bytes(&self, src: &Ipv6Addr, dest: &Ipv6Addr) -> Vec<u8> {
+        let mut bytes = vec![self.icmp_type(), 0, 0, 0];
+        match self {
+            Icmpv6Message::RouterSolicitation { source_link_layer } => {
+                bytes.extend_from_slice(&[0, 0, 0, 0]); // Reserved
+                link_layer_option(&mut bytes, Self::SOURCE_LINK_LAYER, source_link_layer);
+            }
+            Icmpv6Message::RouterAdvertisement {
+                hop_limit,
+                managed,
+                other,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                source_link_layer,
+                mtu,
+                prefixes,
+            } => {
+                bytes.push(*hop_limit);
+                bytes.push((u8::from(*managed) << 7) | (u8::from(*other) << 6));
+                bytes.extend_from_slice(&router_lifetime.to_be_bytes());
+                bytes.extend_from_slice(&reachable_time.to_be_bytes());
+                bytes.extend_from_slice(&retrans_timer.to_be_bytes());
+                link_layer_option(&mut bytes, Self::SOURCE_LINK_LAYER, source_link_layer);
+                if let Some(mtu) = mtu {
+                    bytes.extend_from_slice(&[Self::MTU, 1, 0, 0]);
+                    bytes.extend_from_slice(&mtu.to_be_bytes());
+                }
+                for prefix in prefixes {
+                    bytes.extend_from_slice(&[
+                        Self::PREFIX_INFORMATION,
+                        4,
+                        prefix.prefix.prefix_length(),
+                        (u8::from(prefix.on_link) << 7) | (u8::from(prefix.autonomous) << 6),
+                    ]);
+                    bytes.extend_from_slice(&prefix.valid_lifetime.to_be_bytes());
+                    bytes.extend_from_slice(&prefix.preferred_lifetime.to_be_bytes());
+                    bytes.extend_from_slice(&[0, 0, 0, 0]); // Reserved
+                    bytes.extend_from_slice(&prefix.prefix.address().to_bytes());
+                }
+            }
+            Icmpv6Message::NeighborSolicitation {
+                target,
+                source_link_layer,
+            } => {
+                bytes.extend_from_slice(&[0, 0, 0, 0]); // Reserved
+                bytes.extend_from_slice(&target.to_bytes());
+                link_layer_option(&mut bytes, Self::SOURCE_LINK_LAYER, source_link_layer);
+            }
+            Icmpv6Message::NeighborAdvertisement {
+                router,
+                solicited,
+                override_flag,
+                target,
+                target_link_layer,
+            } => {
+                bytes.push(
+                    (u8::from(*router) << 7)
+                        | (u8::from(*solicited) << 6)
+                        | (u8::from(*override_flag) << 5),
+                );
+                bytes.extend_from_slice(&[0, 0, 0]); // Reserved
+                bytes.extend_from_slice(&target.to_bytes());
+                link_layer_option(&mut bytes, Self::TARGET_LINK_LAYER, target_link_layer);
+            }
+        }
+        let checksum = internet_checksum(&with_pseudo_header(src, dest, &bytes));
+        bytes[2..4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    // Parses a message sent from `src` to `dest`, verifying its checksum
+    pub fn from_bytes(bytes: &[u8], src: &Ipv6Addr, dest: &Ipv6Addr) -> Result<Self, PacketError> {
+        let truncated = |expected: usize| PacketError::Truncated {
+            expected,
+            actual: bytes.len(),
+        };
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(truncated(Self::HEADER_LENGTH));
+        }
+        let checksum = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if internet_checksum(&with_pseudo_header(src, dest, bytes)) != 0 {
+            let mut message = bytes.to_vec();
+            message[2..4].copy_from_slice(&[0, 0]);
+            return Err(PacketError::ChecksumMismatch {
+                expected: internet_checksum(&with_pseudo_header(src, dest, &message)),
+                actual: checksum,
+            });
+        }
+
+        let (icmp_type, code) = (bytes[0], bytes[1]);
+        let body_length = match icmp_type {
+            Self::ROUTER_SOLICITATION => 4,
+            Self::ROUTER_ADVERTISEMENT => 12,
+            Self::NEIGHBOR_SOLICITATION | Self::NEIGHBOR_ADVERTISEMENT => 20,
+            _ => return Err(PacketError::UnsupportedIcmp { icmp_type, code }),
+        };
+        let options_start = Self::HEADER_LENGTH + body_length;
+        if bytes.len() < options_start {
+            return Err(truncated(options_start));
+        }
+        let body = &bytes[Self::HEADER_LENGTH..options_start];
+        let options = parse_options(&bytes[options_start..])
+            .ok_or(PacketError::UnsupportedIcmp { icmp_type, code })?;
+        let link_layer = |option_type: u8| {
+            options
+                .iter()
+                .find(|(kind, data)| *kind == option_type && data.len() >= 6)
+                .map(|(_, data)| {
+                    MacAddress::from_bytes([data[0], data[1], data[2], data[3], data[4], data[5]])
+                })
+        };
+        let target = || {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&body[4..20]);
+            Ipv6Addr::from_bytes(octets)
+        };
+
+        match icmp_type {
+            Self::ROUTER_SOLICITATION => Ok(Icmpv6Message::RouterSolicitation {
+                source_link_layer: link_layer(Self::SOURCE_LINK_LAYER),
+            }),
+            Self::ROUTER_ADVERTISEMENT => {
+                let mtu = options
+                    .iter()
+                    .find(|(kind, data)| *kind == Self::MTU && data.len() >= 6)
+                    .map(|(_, data)| u32::from_be_bytes([data[2], data[3], data[4], data[5]]));
+                let prefixes = options
+                    .iter()
+                    .filter(|(kind, data)| *kind == Self::PREFIX_INFORMATION && data.len() >= 30)
+                    .filter_map(|(_, data)| {
+                        let mut octets = [0; 16];
+                        octets.copy_from_slice(&data[14..30]);
+                        Some(PrefixInformation {
+                            prefix: Ipv6Network::new(Ipv6Addr::from_bytes(octets), data[0]).ok()?,
+                            on_link: data[1] & 0x80 != 0,
+                            autonomous: data[1] & 0x40 != 0,
+                            valid_lifetime: u32::from_be_bytes([
+                                data[2], data[3], data[4], data[5],
+                            ]),
+                            preferred_lifetime: u32::from_be_bytes([
+                                data[6], data[7], data[8], data[9],
+                            ]),
+                        })
+                    })
+                    .collect();
+                Ok(Icmpv6Message::RouterAdvertisement {
+                    hop_limit: body[0],
+                    managed: body[1] & 0x80 != 0,
+                    other: body[1] & 0x40 != 0,
+                    router_lifetime: u16::from_be_bytes([body[2], body[3]]),
+                    reachable_time: u32::from_be_bytes([body[4], body[5], body[6], body[7]]),
+                    retrans_timer: u32::from_be_bytes([body[8], body[9], body[10], body[11]]),
+                    source_link_layer: link_layer(Self::SOURCE_LINK_LAYER),
+                    mtu,
+                    prefixes,
+                })
+            }
+            Self::NEIGHBOR_SOLICITATION => Ok(Icmpv6Message::NeighborSolicitation {
+                target: target(),
+                source_link_layer: link_layer(Self::SOURCE_LINK_LAYER),
+            }),
+            _ => Ok(Icmpv6Message::NeighborAdvertisement {
+                router: body[0] & 0x80 != 0,
+                solicited: body[0] & 0x40 != 0,
+                override_flag: body[0] & 0x20 != 0,
+                target: target(),
+                target_link_layer: link_layer(Self::TARGET_LINK_LAYER),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Icmpv6Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Icmpv6Message::RouterSolicitation { .. } => write!(f, "Router solicitation"),
+            Icmpv6Message::RouterAdvertisement {
+                router_lifetime,
+                prefixes,
+                ..
+            } => {
+                write!(f, "Router advertisement (lifetime {}s", router_lifetime)?;
+                for prefix in prefixes {
+                    write!(f, ", prefix {}", prefix.prefix)?;
+                }
+                write!(f, ")")
+            }
+            Icmpv6Message::NeighborSolicitation { target, .. } => {
+                write!(f, "Neighbor solicitation for {}", target)
+            }
+            Icmpv6Message::NeighborAdvertisement {
+                target,
+                target_link_layer,
+                ..
+            } => match target_link_layer {
+                Some(mac) => write!(f, "Neighbor advertisement: {} is at {}", target, mac),
+                None => write!(f, "Neighbor advertisement for {}", target),
+            },
+        }
+    }
+}
+
+// Appends a link-layer address option, if there is an address to announce
+fn link_layer_option(bytes: &mut Vec<u8>, option_type: u8, mac: &Option<MacAddress>) {
+    if let Some(mac) = mac {
+        bytes.extend_from_slice(&[option_type, 1]);
+        bytes.extend_from_slice(&mac.to_bytes());
+    }
+}
+
+// Splits the options into (type, data) pairs, or None if one of them is malformed
+fn parse_options(mut bytes: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut options = Vec::new();
+    while !bytes.is_empty() {
+        // The length is in units of 8 octets, type and length fields included
+        let length = usize::from(*bytes.get(1)?) * 8;
+        if length == 0 || bytes.len() < length {
+            return None;
+        }
+        options.push((bytes[0], &bytes[2..length]));
+        bytes = &bytes[length..];
+    }
+    Some(options)
+}
+
+// Prepends the pseudo-header the ICMPv6 checksum is computed over
+fn with_pseudo_header(src: &Ipv6Addr, dest: &Ipv6Addr, message: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(40 + message.len());
+    bytes.extend_from_slice(&src.to_bytes());
+    bytes.extend_from_slice(&dest.to_bytes());
+    bytes.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&[0, 0, 0, Protocols::ICMPv6.get_value()]);
+    bytes.extend_from_slice(message);
+    bytes
+}
@@ -1,2 +1,7 @@
 pub mod address;
-pub mod pdu;
\ No newline at end of file
+pub mod checksum;
+pub mod icmp;
+pub mod icmpv6;
+pub mod ndp;
+pub mod pdu;
+pub mod routing;
@@ -0,0 +1,634 @@
+use super::address::{Ipv6Addr, Ipv6Interface, Ipv6Network};
+use super::pdu::Ipv6Packet;
+use crate::layer2::address::MacAddress;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+// Interface identifier derived from a MAC address by modified EUI-64 (RFC 4291 appendix A):
+// ff:fe is inserted in the middle and the universal/local bit is flipped
+pub fn eui64_interface_id(mac: &MacAddress) -> u64 {
+    let [a, b, c, d, e, f] = mac.to_bytes();
+    u64::from_be_bytes([a ^ 0x02, b, c, 0xff, 0xfe, d, e, f])
+}
+
+// Address formed from a /64 prefix and the EUI-64 identifier of `mac` (RFC 4862)
+pub fn eui64_address(prefix: &Ipv6Network, mac: &MacAddress) -> Ipv6Addr {
+    Ipv6Addr::from_u128(prefix.address().to_u128() | u128::from(eui64_interface_id(mac)))
+}
+
+// fe80::/64 address every IPv6 interface configures for itself
+pub fn link_local_address(mac: &MacAddress) -> Ipv6Interface {
+    let prefix = Ipv6Network::new(Ipv6Addr::from_segments([0xfe80, 0, 0, 0, 0, 0, 0, 0]), 64)
+        .expect("valid prefix length");
+    Ipv6Interface::new(eui64_address(&prefix, mac), 64).expect("valid prefix length")
+}
+
+/// An address undergoing duplicate address detection, not usable until it completes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TentativeAddress {
+    pub address: Ipv6Interface,
+    // Simulation time at which nobody having objected, the address becomes usable.
+    // None until the neighbor solicitation probing it has been sent.
+    pub completes_at: Option<Duration>,
+}
+
+/// Router advertisement settings of an interface acting as an IPv6 router.
+/// The advertised prefixes are those of its global addresses.
+#[derive(Debug, Clone)]
+pub struct RouterAdvertisements {
+    pub interval: Duration,
+    pub router_lifetime: Duration,
+    // Lifetimes of the advertised prefixes
+    pub valid_lifetime: Duration,
+    pub preferred_lifetime: Duration,
+    next_advertisement: Option<Duration>,
+}
+
+impl RouterAdvertisements {
+    // Cisco IOS defaults
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(200);
+    pub const DEFAULT_ROUTER_LIFETIME: Duration = Duration::from_secs(1800);
+    pub const DEFAULT_VALID_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+    pub const DEFAULT_PREFERRED_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    pub fn new() -> Self {
+        Self {
+            interval: Self::DEFAULT_INTERVAL,
+            router_lifetime: Self::DEFAULT_ROUTER_LIFETIME,
+            valid_lifetime: Self::DEFAULT_VALID_LIFETIME,
+            preferred_lifetime: Self::DEFAULT_PREFERRED_LIFETIME,
+            next_advertisement: None,
+        }
+    }
+
+    // Whether the periodic advertisement is due; the first one goes out right away
+    pub fn is_due(&self, now: Duration) -> bool {
+        self.next_advertisement.is_none_or(|next| now >= next)
+    }
+
+    // Records that an advertisement was sent, solicited or not
+    pub fn advertised(&mut self, now: Duration) {
+        self.next_advertisement = Some(now + self.interval);
+    }
+
+    pub fn next_advertisement(&self) -> Option<Duration> {
+        self.next_advertisement
+    }
+}
+
+impl Default for RouterAdvertisements {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Neighbor unreachability detection states (RFC 4861 section 7.3.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborState {
+    // Address resolution is in progress
+    Incomplete,
+    // Recently confirmed reachable by a solicited advertisement
+    Reachable,
+    // Not confirmed lately; traffic to it moves the entry to Delay
+    Stale,
+    // Waiting for upper layers to confirm reachability before probing
+    Delay,
+    // Sending unicast solicitations to confirm reachability
+    Probe,
+}
+
+impl fmt::Display for NeighborState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NeighborState::Incomplete => f.pad("INCMP"),
+            NeighborState::Reachable => f.pad("REACH"),
+            NeighborState::Stale => f.pad("STALE"),
+            NeighborState::Delay => f.pad("DELAY"),
+            NeighborState::Probe => f.pad("PROBE"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NeighborEntry {
+    // None while the entry is incomplete
+    pub mac: Option<MacAddress>,
+    pub state: NeighborState,
+    // Simulation time at which the entry entered its state, or the last solicitation was
+    // sent while incomplete or probing
+    pub updated: Duration,
+    // Solicitations sent without an answer so far
+    pub probes: u8,
+    pub is_router: bool,
+    // Packets waiting for the address to be resolved
+    pending: VecDeque<Ipv6Packet>,
+}
+
+impl NeighborEntry {
+    fn new(mac: Option<MacAddress>, state: NeighborState, now: Duration) -> Self {
+        Self {
+            mac,
+            state,
+            updated: now,
+            probes: 0,
+            is_router: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn enter(&mut self, state: NeighborState, now: Duration) {
+        self.state = state;
+        self.updated = now;
+        self.probes = 0;
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// What became of the entries after `NeighborCache::update`
+#[derive(Debug, Default)]
+pub struct NeighborUpdate {
+    // Addresses to send another multicast solicitation for
+    pub solicitations: Vec<Ipv6Addr>,
+    // Neighbors to probe with a unicast solicitation
+    pub probes: Vec<(Ipv6Addr, MacAddress)>,
+    // Packets dropped because their next hop never answered
+    pub dropped: Vec<Ipv6Packet>,
+}
+
+/// IPv6 to MAC address cache maintained by Neighbor Discovery (RFC 4861), the IPv6
+/// counterpart of the ARP table. Entries move through the NUD states as advertisements
+/// arrive and as traffic is sent to them; unreachable neighbors are removed.
+#[derive(Debug)]
+pub struct NeighborCache {
+    entries: HashMap<Ipv6Addr, NeighborEntry>,
+    reachable_time: Duration,
+    retrans_timer: Duration,
+    scheduled_wakeup: Option<Duration>,
+}
+
+impl NeighborCache {
+    pub const DEFAULT_REACHABLE_TIME: Duration = Duration::from_secs(30);
+    pub const DEFAULT_RETRANS_TIMER: Duration = Duration::from_secs(1);
+    pub const DELAY_FIRST_PROBE_TIME: Duration = Duration::from_secs(5);
+    pub const MAX_MULTICAST_SOLICIT: u8 = 3;
+    pub const MAX_UNICAST_SOLICIT: u8 = 3;
+    // Packets held per incomplete entry; more are dropped
+    pub const MAX_PENDING: usize = 3;
+
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            reachable_time: Self::DEFAULT_REACHABLE_TIME,
+            retrans_timer: Self::DEFAULT_RETRANS_TIMER,
+            scheduled_wakeup: None,
+        }
+    }
+
+    pub fn reachable_time(&self) -> Duration {
+        self.reachable_time
+    }
+
+    pub fn retrans_timer(&self) -> Duration {
+        self.retrans_timer
+    }
+
+    // Timers advertised by routers replace the defaults
+    pub fn set_timers(&mut self, reachable_time: Duration, retrans_timer: Duration) {
+        self.reachable_time = reachable_time;
+        self.retrans_timer = retrans_timer;
+    }
+
+    /// Returns the MAC address to send a packet for `ip` to. Sending to a stale neighbor
+    /// starts the delay before its reachability is probed.
+    pub fn lookup(&mut self, ip: &Ipv6Addr, now: Duration) -> Option<MacAddress> {
+        let entry = self.entries.get_mut(ip)?;
+        if entry.state == NeighborState::Stale {
+            entry.enter(NeighborState::Delay, now);
+        }
+        entry.mac.clone()
+    }
+
+    /// Holds `packet` until `ip` is resolved. Returns true if the address was not being
+    /// resolved yet, in which case the caller must send the first solicitation.
+    /// Packets beyond `MAX_PENDING` are dropped.
+    pub fn queue_packet(&mut self, ip: Ipv6Addr, packet: Ipv6Packet, now: Duration) -> bool {
+        match self.entries.get_mut(&ip) {
+            Some(entry) => {
+                if entry.pending.len() < Self::MAX_PENDING {
+                    entry.pending.push_back(packet);
+                }
+                false
+            }
+            None => {
+                let mut entry = NeighborEntry::new(None, NeighborState::Incomplete, now);
+                entry.probes = 1;
+                entry.pending.push_back(packet);
+                self.entries.insert(ip, entry);
+                true
+            }
+        }
+    }
+
+    /// Records the link-layer address announced in a solicitation or a router advertisement.
+    /// It is not a confirmation of reachability, so new or changed entries are stale.
+    /// Returns the packets that were waiting for the address, ready to be sent.
+    pub fn learn(&mut self, ip: Ipv6Addr, mac: MacAddress, now: Duration) -> Vec<Ipv6Packet> {
+        let entry = self
+            .entries
+            .entry(ip)
+            .or_insert_with(|| NeighborEntry::new(None, NeighborState::Stale, now));
+        if entry.mac.as_ref() != Some(&mac) {
+            entry.mac = Some(mac);
+            entry.enter(NeighborState::Stale, now);
+        }
+        entry.pending.drain(..).collect()
+    }
+
+    /// Processes a neighbor advertisement for `ip` (RFC 4861 section 7.2.5). Advertisements
+    /// for addresses not in the cache are ignored.
+    /// Returns the packets that were waiting for the address, ready to be sent.
+    pub fn advertised(
+        &mut self,
+        ip: Ipv6Addr,
+        mac: Option<MacAddress>,
+        solicited: bool,
+        override_flag: bool,
+        is_router: bool,
+        now: Duration,
+    ) -> Vec<Ipv6Packet> {
+        let Some(entry) = self.entries.get_mut(&ip) else {
+            return Vec::new();
+        };
+        let state = if solicited {
+            NeighborState::Reachable
+        } else {
+            NeighborState::Stale
+        };
+        if entry.state == NeighborState::Incomplete {
+            let Some(mac) = mac else {
+                return Vec::new();
+            };
+            entry.mac = Some(mac);
+            entry.is_router = is_router;
+            entry.enter(state, now);
+            return entry.pending.drain(..).collect();
+        }
+        let changed = mac.is_some() && mac != entry.mac;
+        if changed && !override_flag {
+            // Keep the cached address but stop trusting it
+            if entry.state == NeighborState::Reachable {
+                entry.enter(NeighborState::Stale, now);
+            }
+            return Vec::new();
+        }
+        if changed {
+            entry.mac = mac;
+        }
+        if solicited || changed {
+            entry.enter(state, now);
+        }
+        entry.is_router = is_router;
+        Vec::new()
+    }
+
+    /// Moves the entries along the NUD state machine: reachable entries go stale after the
+    /// reachable time, delayed ones start probing, and incomplete or probing entries send
+    /// another solicitation or are removed once the solicitations run out
+    pub fn update(&mut self, now: Duration) -> NeighborUpdate {
+        let mut update = NeighborUpdate::default();
+        let (reachable_time, retrans_timer) = (self.reachable_time, self.retrans_timer);
+        self.entries.retain(|ip, entry| {
+            match entry.state {
+                NeighborState::Stale => {}
+                NeighborState::Reachable => {
+                    if now >= entry.updated + reachable_time {
+                        entry.enter(NeighborState::Stale, now);
+                    }
+                }
+                NeighborState::Delay => {
+                    if now >= entry.updated + Self::DELAY_FIRST_PROBE_TIME {
+                        entry.enter(NeighborState::Probe, now);
+                        entry.probes = 1;
+                        if let Some(mac) = &entry.mac {
+                            update.probes.push((*ip, mac.clone()));
+                        }
+                    }
+                }
+                NeighborState::Incomplete | NeighborState::Probe => {
+                    if now < entry.updated + retrans_timer {
+                        return true;
+                    }
+                    let max_probes = match entry.state {
+                        NeighborState::Incomplete => Self::MAX_MULTICAST_SOLICIT,
+                        _ => Self::MAX_UNICAST_SOLICIT,
+                    };
+                    if entry.probes >= max_probes {
+                        update.dropped.extend(entry.pending.drain(..));
+                        return false;
+                    }
+                    entry.probes += 1;
+                    entry.updated = now;
+                    match &entry.mac {
+                        Some(mac) => update.probes.push((*ip, mac.clone())),
+                        None => update.solicitations.push(*ip),
+                    }
+                }
+            }
+            true
+        });
+        update
+    }
+
+    // Next time an entry changes state on its own, if not already scheduled
+    pub fn next_wakeup(&mut self) -> Option<Duration> {
+        let next = self
+            .entries
+            .values()
+            .filter_map(|entry| match entry.state {
+                NeighborState::Stale => None,
+                NeighborState::Reachable => Some(entry.updated + self.reachable_time),
+                NeighborState::Delay => Some(entry.updated + Self::DELAY_FIRST_PROBE_TIME),
+                NeighborState::Incomplete | NeighborState::Probe => {
+                    Some(entry.updated + self.retrans_timer)
+                }
+            })
+            .min()?;
+        if self.scheduled_wakeup == Some(next) {
+            return None;
+        }
+        self.scheduled_wakeup = Some(next);
+        Some(next)
+    }
+
+    // Flags a neighbor as a router, as announced by its router advertisements
+    pub fn set_router(&mut self, ip: &Ipv6Addr, is_router: bool) {
+        if let Some(entry) = self.entries.get_mut(ip) {
+            entry.is_router = is_router;
+        }
+    }
+
+    // "clear ipv6 neighbors"
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn entry(&self, ip: &Ipv6Addr) -> Option<&NeighborEntry> {
+        self.entries.get(ip)
+    }
+
+    pub fn remove_entry(&mut self, ip: &Ipv6Addr) {
+        self.entries.remove(ip);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Formats the cache like "show ipv6 neighbors", with the age of the entries in minutes
+    pub fn show(&self, now: Duration, interface_name: &str) -> String {
+        let mut entries: Vec<(&Ipv6Addr, &NeighborEntry)> = self.entries.iter().collect();
+        entries.sort_by_key(|(ip, _)| **ip);
+
+        let mut output = String::new();
+        output.push_str(
+            "IPv6 Address                              Age Link-layer Addr State Interface\n",
+        );
+        for (ip, entry) in entries {
+            let mac = match &entry.mac {
+                Some(mac) => mac.to_dotted_string(),
+                None => "-".to_string(),
+            };
+            output.push_str(&format!(
+                "{:<40} {:>4} {:<15} {:<5} {}\n",
+                ip.to_string().to_uppercase(),
+                now.saturating_sub(entry.updated).as_secs() / 60,
+                mac,
+                entry.state,
+                interface_name
+            ));
+        }
+        output
+    }
+}
+
+impl Default for NeighborCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer3::pdu::{IpPayload, Protocols};
+
+    const NEIGHBOR: &str = "fe80::211:22ff:fe33:4455";
+
+    fn ip(value: &str) -> Ipv6Addr {
+        value.parse().unwrap()
+    }
+
+    fn mac(value: &str) -> MacAddress {
+        value.parse().unwrap()
+    }
+
+    fn packet() -> Ipv6Packet {
+        let payload = IpPayload { data: vec![0; 8] };
+        Ipv6Packet::new(ip("fe80::1"), ip(NEIGHBOR), payload, 0, 64, Protocols::UDP)
+    }
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn state(cache: &NeighborCache) -> NeighborState {
+        cache.entry(&ip(NEIGHBOR)).unwrap().state
+    }
+
+    // A cache holding NEIGHBOR as reachable at 00:11:22:33:44:55 since `now`
+    fn reachable_cache(now: Duration) -> NeighborCache {
+        let mut cache = NeighborCache::new();
+        cache.queue_packet(ip(NEIGHBOR), packet(), now);
+        let mac = Some(mac("00:11:22:33:44:55"));
+        cache.advertised(ip(NEIGHBOR), mac, true, true, false, now);
+        cache
+    }
+
+    #[test]
+    fn derives_modified_eui64_identifiers() {
+        assert_eq!(
+            eui64_interface_id(&mac("00:11:22:33:44:55")),
+            0x0211_22ff_fe33_4455
+        );
+        // The universal/local bit is flipped, not set
+        assert_eq!(
+            eui64_interface_id(&mac("02:11:22:33:44:55")),
+            0x0011_22ff_fe33_4455
+        );
+        let prefix = "2001:db8:1::/64".parse().unwrap();
+        assert_eq!(
+            eui64_address(&prefix, &mac("00:11:22:33:44:55")),
+            ip("2001:db8:1::211:22ff:fe33:4455")
+        );
+        assert_eq!(
+            link_local_address(&mac("00:11:22:33:44:55")).to_string(),
+            "fe80::211:22ff:fe33:4455/64"
+        );
+    }
+
+    #[test]
+    fn resolves_incomplete_entries() {
+        let mut cache = NeighborCache::new();
+        assert!(cache.queue_packet(ip(NEIGHBOR), packet(), secs(0)));
+        assert!(!cache.queue_packet(ip(NEIGHBOR), packet(), secs(0)));
+        assert_eq!(state(&cache), NeighborState::Incomplete);
+        assert_eq!(cache.lookup(&ip(NEIGHBOR), secs(0)), None);
+
+        // An advertisement without a link-layer address cannot complete the entry
+        let released = cache.advertised(ip(NEIGHBOR), None, true, true, false, secs(0));
+        assert!(released.is_empty());
+        assert_eq!(state(&cache), NeighborState::Incomplete);
+
+        let mac = Some(mac("00:11:22:33:44:55"));
+        let released = cache.advertised(ip(NEIGHBOR), mac.clone(), true, false, true, secs(0));
+        assert_eq!(released.len(), 2);
+        assert_eq!(state(&cache), NeighborState::Reachable);
+        assert!(cache.entry(&ip(NEIGHBOR)).unwrap().is_router);
+        assert_eq!(cache.lookup(&ip(NEIGHBOR), secs(0)), mac);
+    }
+
+    #[test]
+    fn unsolicited_advertisement_completes_entry_as_stale() {
+        let mut cache = NeighborCache::new();
+        cache.queue_packet(ip(NEIGHBOR), packet(), secs(0));
+        let mac = Some(mac("00:11:22:33:44:55"));
+        let released = cache.advertised(ip(NEIGHBOR), mac, false, true, false, secs(0));
+        assert_eq!(released.len(), 1);
+        assert_eq!(state(&cache), NeighborState::Stale);
+    }
+
+    #[test]
+    fn gives_up_on_unanswered_solicitations() {
+        let mut cache = NeighborCache::new();
+        cache.queue_packet(ip(NEIGHBOR), packet(), secs(0));
+        for second in 1..NeighborCache::MAX_MULTICAST_SOLICIT as u64 {
+            let update = cache.update(secs(second));
+            assert_eq!(update.solicitations, [ip(NEIGHBOR)]);
+        }
+        let update = cache.update(secs(NeighborCache::MAX_MULTICAST_SOLICIT as u64));
+        assert!(update.solicitations.is_empty());
+        assert_eq!(update.dropped.len(), 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn walks_through_unreachability_detection() {
+        let mut cache = reachable_cache(secs(0));
+
+        // Reachable goes stale after the reachable time
+        cache.update(secs(29));
+        assert_eq!(state(&cache), NeighborState::Reachable);
+        cache.update(secs(30));
+        assert_eq!(state(&cache), NeighborState::Stale);
+
+        // Stale entries wait for traffic, which starts the delay
+        cache.update(secs(1000));
+        assert_eq!(state(&cache), NeighborState::Stale);
+        assert_eq!(
+            cache.lookup(&ip(NEIGHBOR), secs(1000)),
+            Some(mac("00:11:22:33:44:55"))
+        );
+        assert_eq!(state(&cache), NeighborState::Delay);
+        assert_eq!(cache.next_wakeup(), Some(secs(1005)));
+
+        // Delay starts probing after DELAY_FIRST_PROBE_TIME
+        cache.update(secs(1004));
+        assert_eq!(state(&cache), NeighborState::Delay);
+        let update = cache.update(secs(1005));
+        assert_eq!(state(&cache), NeighborState::Probe);
+        assert_eq!(update.probes, [(ip(NEIGHBOR), mac("00:11:22:33:44:55"))]);
+
+        // Unicast probes are retried, then the entry is removed
+        for second in 1006..1008 {
+            assert_eq!(cache.update(secs(second)).probes.len(), 1);
+        }
+        let update = cache.update(secs(1008));
+        assert!(update.probes.is_empty());
+        assert!(cache.entry(&ip(NEIGHBOR)).is_none());
+    }
+
+    #[test]
+    fn solicited_advertisement_confirms_probed_entry() {
+        let mut cache = reachable_cache(secs(0));
+        cache.update(secs(30));
+        cache.lookup(&ip(NEIGHBOR), secs(30));
+        cache.update(secs(35));
+        assert_eq!(state(&cache), NeighborState::Probe);
+
+        let mac = Some(mac("00:11:22:33:44:55"));
+        cache.advertised(ip(NEIGHBOR), mac.clone(), true, false, false, secs(36));
+        assert_eq!(state(&cache), NeighborState::Reachable);
+        assert_eq!(cache.entry(&ip(NEIGHBOR)).unwrap().probes, 0);
+
+        // An unsolicited advertisement of the same address changes nothing
+        cache.advertised(ip(NEIGHBOR), mac, false, false, false, secs(37));
+        assert_eq!(state(&cache), NeighborState::Reachable);
+        assert_eq!(cache.entry(&ip(NEIGHBOR)).unwrap().updated, secs(36));
+    }
+
+    #[test]
+    fn non_override_advertisement_keeps_cached_address() {
+        let mut cache = reachable_cache(secs(0));
+        let other = Some(mac("00:11:22:33:44:66"));
+        cache.advertised(ip(NEIGHBOR), other, true, false, false, secs(1));
+        let entry = cache.entry(&ip(NEIGHBOR)).unwrap();
+        assert_eq!(entry.mac, Some(mac("00:11:22:33:44:55")));
+        assert_eq!(entry.state, NeighborState::Stale);
+    }
+
+    #[test]
+    fn override_advertisement_replaces_cached_address() {
+        let mut cache = reachable_cache(secs(0));
+        let other = Some(mac("00:11:22:33:44:66"));
+        cache.advertised(ip(NEIGHBOR), other.clone(), false, true, false, secs(1));
+        let entry = cache.entry(&ip(NEIGHBOR)).unwrap();
+        assert_eq!(entry.mac, other);
+        assert_eq!(entry.state, NeighborState::Stale);
+
+        let mac = Some(mac("00:11:22:33:44:55"));
+        cache.advertised(ip(NEIGHBOR), mac.clone(), true, true, false, secs(2));
+        let entry = cache.entry(&ip(NEIGHBOR)).unwrap();
+        assert_eq!(entry.mac, mac);
+        assert_eq!(entry.state, NeighborState::Reachable);
+    }
+
+    #[test]
+    fn ignores_advertisements_for_unknown_neighbors() {
+        let mut cache = NeighborCache::new();
+        let mac = Some(mac("00:11:22:33:44:55"));
+        cache.advertised(ip(NEIGHBOR), mac, true, true, false, secs(0));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn learned_addresses_are_stale() {
+        let mut cache = reachable_cache(secs(0));
+        cache.learn(ip(NEIGHBOR), mac("00:11:22:33:44:55"), secs(1));
+        assert_eq!(state(&cache), NeighborState::Reachable);
+        cache.learn(ip(NEIGHBOR), mac("00:11:22:33:44:66"), secs(2));
+        assert_eq!(state(&cache), NeighborState::Stale);
+        cache.learn(ip("fe80::2"), mac("00:11:22:33:44:77"), secs(2));
+        assert_eq!(
+            cache.entry(&ip("fe80::2")).unwrap().state,
+            NeighborState::Stale
+        );
+    }
+}
@@ -1,9 +1,55 @@
 use super::address::{IpAddr, Ipv4Addr, Ipv6Addr};
-use crate::layer2::address::MacAddress;
-use bevy::prelude::*;
+use super::checksum::internet_checksum;
+use super::icmp::IcmpMessage;
+use super::icmpv6::Icmpv6Message;
 use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketError {
+    // Fewer bytes than the header or the length fields require
+    Truncated { expected: usize, actual: usize },
+    InvalidVersion(u8),
+    InvalidHeaderLength(u8),
+    InvalidTotalLength(u16),
+    // The IPv6 extension headers do not fit in the payload length
+    InvalidPayloadLength(u16),
+    ChecksumMismatch { expected: u16, actual: u16 },
+    UnsupportedIcmp { icmp_type: u8, code: u8 },
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Truncated { expected, actual } => write!(
+                f,
+                "Packet truncated: expected at least {} bytes, got {}",
+                expected, actual
+            ),
+            PacketError::InvalidVersion(version) => write!(f, "Invalid IP version {}", version),
+            PacketError::InvalidHeaderLength(ihl) => {
+                write!(f, "Invalid header length {} (in 32-bit words)", ihl)
+            }
+            PacketError::InvalidTotalLength(length) => {
+                write!(f, "Invalid total length {}", length)
+            }
+            PacketError::InvalidPayloadLength(length) => {
+                write!(f, "Invalid payload length {}", length)
+            }
+            PacketError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Checksum mismatch: expected {:04X}, got {:04X}",
+                expected, actual
+            ),
+            PacketError::UnsupportedIcmp { icmp_type, code } => {
+                write!(f, "Unsupported ICMP type {} code {}", icmp_type, code)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Protocols {
     ICMP,
     IGMP,
@@ -12,6 +58,9 @@ pub enum Protocols {
     GRE,
     ESP,
     AH,
+    ICMPv6,
+    // Nothing follows the IPv6 header (RFC 8200)
+    NoNextHeader,
     EIGRP,
     OSPF,
     PIM,
@@ -19,7 +68,8 @@ pub enum Protocols {
     L2TP,
     ISIS,
     MPLS,
-    Unknown,
+    // Any other protocol, kept by number so it is sent unchanged
+    Other(u8),
 }
 
 impl Protocols {
@@ -32,6 +82,8 @@ impl Protocols {
             Protocols::GRE => 47,
             Protocols::ESP => 50,
             Protocols::AH => 51,
+            Protocols::ICMPv6 => 58,
+            Protocols::NoNextHeader => 59,
             Protocols::EIGRP => 88,
             Protocols::OSPF => 89,
             Protocols::PIM => 103,
@@ -39,7 +91,29 @@ impl Protocols {
             Protocols::L2TP => 115,
             Protocols::ISIS => 124,
             Protocols::MPLS => 137,
-            Protocols::Unknown => 0,
+            Protocols::Other(value) => *value,
+        }
+    }
+
+    pub fn from_value(value: u8) -> Self {
+        match value {
+            1 => Protocols::ICMP,
+            2 => Protocols::IGMP,
+            6 => Protocols::TCP,
+            17 => Protocols::UDP,
+            47 => Protocols::GRE,
+            50 => Protocols::ESP,
+            51 => Protocols::AH,
+            58 => Protocols::ICMPv6,
+            59 => Protocols::NoNextHeader,
+            88 => Protocols::EIGRP,
+            89 => Protocols::OSPF,
+            103 => Protocols::PIM,
+            112 => Protocols::VRRP,
+            115 => Protocols::L2TP,
+            124 => Protocols::ISIS,
+            137 => Protocols::MPLS,
+            value => Protocols::Other(value),
         }
     }
 }
@@ -54,6 +128,8 @@ impl fmt::Display for Protocols {
             Protocols::GRE => write!(f, "GRE"),
             Protocols::ESP => write!(f, "ESP"),
             Protocols::AH => write!(f, "AH"),
+            Protocols::ICMPv6 => write!(f, "ICMPv6"),
+            Protocols::NoNextHeader => write!(f, "No Next Header"),
             Protocols::EIGRP => write!(f, "EIGRP"),
             Protocols::OSPF => write!(f, "OSPF"),
             Protocols::PIM => write!(f, "PIM"),
@@ -61,17 +137,17 @@ impl fmt::Display for Protocols {
             Protocols::L2TP => write!(f, "L2TP"),
             Protocols::ISIS => write!(f, "ISIS"),
             Protocols::MPLS => write!(f, "MPLS"),
-            Protocols::Unknown => write!(f, "Unknown"),
+            Protocols::Other(value) => write!(f, "Unknown ({})", value),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IpPayload {
     pub data: Vec<u8>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ipv4Header {
     pub version: u8,
     pub ihl: u8,
@@ -86,6 +162,7 @@ pub struct Ipv4Header {
     pub header_checksum: u16,
     pub src: Ipv4Addr,
     pub dest: Ipv4Addr,
+    pub options: Vec<u8>,
 }
 
 impl fmt::Display for Ipv4Header {
@@ -110,15 +187,19 @@ impl fmt::Display for Ipv4Header {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ipv4Packet {
     pub header: Ipv4Header,
     pub payload: IpPayload,
 }
 
 impl Ipv4Packet {
+    const MIN_HEADER_LENGTH: usize = 20;
+    // Flag bit forbidding routers to fragment the packet
+    pub const DONT_FRAGMENT: u8 = 0b010;
+
     pub fn new(src: Ipv4Addr, dest: Ipv4Addr, payload: IpPayload) -> Self {
-        Self {
+        let mut packet = Self {
             header: Ipv4Header {
                 version: 4,
                 ihl: 5,
@@ -133,30 +214,161 @@ impl Ipv4Packet {
                 header_checksum: 0,
                 src,
                 dest,
+                options: Vec::new(),
             },
             payload,
+        };
+        packet.update_header();
+        packet
+    }
+
+    // Packet carrying an ICMP message
+    pub fn icmp(src: Ipv4Addr, dest: Ipv4Addr, message: &IcmpMessage) -> Self {
+        let mut packet = Self::new(
+            src,
+            dest,
+            IpPayload {
+                data: message.to_bytes(),
+            },
+        );
+        packet.header.protocol = Protocols::ICMP;
+        packet.update_header();
+        packet
+    }
+
+    // The ICMP message carried by the packet, if it is a valid one
+    pub fn icmp_message(&self) -> Option<IcmpMessage> {
+        if self.header.protocol != Protocols::ICMP {
+            return None;
         }
+        IcmpMessage::from_bytes(&self.payload.data).ok()
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.push((self.header.version << 4) | self.header.ihl);
-        bytes.push((self.header.dscp << 2) | self.header.ecn);
-        bytes.push((self.header.total_length >> 8) as u8);
-        bytes.push((self.header.identification >> 8) as u8);
-        bytes.push(self.header.identification as u8);
-        bytes.push((self.header.flags << 5) | (self.header.fragment_offset >> 8) as u8);
-        bytes.push((self.header.fragment_offset >> 8) as u8);
-        bytes.push(self.header.fragment_offset as u8);
-        bytes.push((self.header.ttl) as u8);
+    pub fn dont_fragment(&self) -> bool {
+        self.header.flags & Self::DONT_FRAGMENT != 0
+    }
+
+    // Header length in bytes, options included
+    pub fn header_length(&self) -> usize {
+        Self::MIN_HEADER_LENGTH + self.header.options.len().div_ceil(4) * 4
+    }
+
+    /// Recomputes the IHL, total length and header checksum from the packet contents.
+    /// Call it after changing header fields such as the TTL.
+    pub fn update_header(&mut self) {
+        let header = self.header_bytes();
+        self.header.ihl = header[0] & 0x0F;
+        self.header.total_length = u16::from_be_bytes([header[2], header[3]]);
+        self.header.header_checksum = u16::from_be_bytes([header[10], header[11]]);
+    }
+
+    // Encodes the header as specified in RFC 791. The IHL, total length and checksum
+    // are always derived from the contents rather than taken from the header fields.
+    fn header_bytes(&self) -> Vec<u8> {
+        // Options are padded with zeros to a multiple of 32 bits
+        let options_length = self.header.options.len().div_ceil(4) * 4;
+        let header_length = Self::MIN_HEADER_LENGTH + options_length;
+        let total_length = (header_length + self.payload.data.len()) as u16;
+        let fragment_offset = self.header.fragment_offset & 0x1FFF;
+
+        let mut bytes = vec![
+            (self.header.version << 4) | (header_length / 4) as u8,
+            (self.header.dscp << 2) | (self.header.ecn & 0x03),
+        ];
+        bytes.extend_from_slice(&total_length.to_be_bytes());
+        bytes.extend_from_slice(&self.header.identification.to_be_bytes());
+        bytes.push((self.header.flags << 5) | (fragment_offset >> 8) as u8);
+        bytes.push(fragment_offset as u8);
+        bytes.push(self.header.ttl);
         bytes.push(self.header.protocol.get_value());
-        bytes.push((self.header.header_checksum >> 8) as u8);
-        bytes.push(self.header.header_checksum as u8);
+        bytes.extend_from_slice(&[0, 0]); // Checksum placeholder
         bytes.extend_from_slice(&self.header.src.to_bytes());
         bytes.extend_from_slice(&self.header.dest.to_bytes());
+        bytes.extend_from_slice(&self.header.options);
+        bytes.resize(header_length, 0);
+
+        let checksum = internet_checksum(&bytes);
+        bytes[10..12].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header_bytes();
         bytes.extend_from_slice(&self.payload.data);
         bytes
     }
+
+    /// Parses an IPv4 packet, verifying the header checksum.
+    /// Bytes beyond the total length (such as Ethernet padding) are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        if bytes.len() < Self::MIN_HEADER_LENGTH {
+            return Err(PacketError::Truncated {
+                expected: Self::MIN_HEADER_LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        let version = bytes[0] >> 4;
+        if version != 4 {
+            return Err(PacketError::InvalidVersion(version));
+        }
+        let ihl = bytes[0] & 0x0F;
+        let header_length = ihl as usize * 4;
+        if header_length < Self::MIN_HEADER_LENGTH {
+            return Err(PacketError::InvalidHeaderLength(ihl));
+        }
+        if bytes.len() < header_length {
+            return Err(PacketError::Truncated {
+                expected: header_length,
+                actual: bytes.len(),
+            });
+        }
+        let total_length = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if (total_length as usize) < header_length {
+            return Err(PacketError::InvalidTotalLength(total_length));
+        }
+        if bytes.len() < total_length as usize {
+            return Err(PacketError::Truncated {
+                expected: total_length as usize,
+                actual: bytes.len(),
+            });
+        }
+        let header_checksum = u16::from_be_bytes([bytes[10], bytes[11]]);
+        // Summing a valid header including its checksum yields zero
+        if internet_checksum(&bytes[..header_length]) != 0 {
+            let mut header = bytes[..header_length].to_vec();
+            header[10..12].copy_from_slice(&[0, 0]);
+            return Err(PacketError::ChecksumMismatch {
+                expected: internet_checksum(&header),
+                actual: header_checksum,
+            });
+        }
+
+        Ok(Self {
+            header: Ipv4Header {
+                version,
+                ihl,
+                dscp: bytes[1] >> 2,
+                ecn: bytes[1] & 0x03,
+                total_length,
+                identification: u16::from_be_bytes([bytes[4], bytes[5]]),
+                flags: bytes[6] >> 5,
+                fragment_offset: u16::from_be_bytes([bytes[6] & 0x1F, bytes[7]]),
+                ttl: bytes[8],
+                protocol: Protocols::from_value(bytes[9]),
+                header_checksum,
+                src: Ipv4Addr {
+                    octets: [bytes[12], bytes[13], bytes[14], bytes[15]],
+                },
+                dest: Ipv4Addr {
+                    octets: [bytes[16], bytes[17], bytes[18], bytes[19]],
+                },
+                options: bytes[Self::MIN_HEADER_LENGTH..header_length].to_vec(),
+            },
+            payload: IpPayload {
+                data: bytes[header_length..total_length as usize].to_vec(),
+            },
+        })
+    }
 }
 
 impl fmt::Display for Ipv4Packet {
@@ -165,20 +377,121 @@ impl fmt::Display for Ipv4Packet {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionHeaderType {
+    HopByHop,
+    Routing,
+    Fragment,
+    DestinationOptions,
+    Authentication,
+}
+
+impl ExtensionHeaderType {
+    pub fn get_value(&self) -> u8 {
+        match self {
+            ExtensionHeaderType::HopByHop => 0,
+            ExtensionHeaderType::Routing => 43,
+            ExtensionHeaderType::Fragment => 44,
+            ExtensionHeaderType::DestinationOptions => 60,
+            ExtensionHeaderType::Authentication => 51,
+        }
+    }
+
+    // None for upper-layer protocols
+    pub fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ExtensionHeaderType::HopByHop),
+            43 => Some(ExtensionHeaderType::Routing),
+            44 => Some(ExtensionHeaderType::Fragment),
+            60 => Some(ExtensionHeaderType::DestinationOptions),
+            51 => Some(ExtensionHeaderType::Authentication),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExtensionHeaderType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtensionHeaderType::HopByHop => write!(f, "Hop-by-Hop Options"),
+            ExtensionHeaderType::Routing => write!(f, "Routing"),
+            ExtensionHeaderType::Fragment => write!(f, "Fragment"),
+            ExtensionHeaderType::DestinationOptions => write!(f, "Destination Options"),
+            ExtensionHeaderType::Authentication => write!(f, "Authentication"),
+        }
+    }
+}
+
+/// An IPv6 extension header. `data` holds everything after the next header and length
+/// fields; it is padded with zeros when encoded so the header length is a multiple of
+/// 8 octets, or of 4 octets for the authentication header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionHeader {
+    pub header_type: ExtensionHeaderType,
+    pub data: Vec<u8>,
+}
+
+impl ExtensionHeader {
+    // Length in bytes once encoded, next header and length fields included
+    pub fn length(&self) -> usize {
+        match self.header_type {
+            // The fragment header has a fixed size and its length field is reserved
+            ExtensionHeaderType::Fragment => 8,
+            // Counted in 4-octet units (RFC 4302)
+            ExtensionHeaderType::Authentication => (2 + self.data.len()).div_ceil(4) * 4,
+            _ => (2 + self.data.len()).div_ceil(8) * 8,
+        }
+    }
+
+    fn to_bytes(&self, next_header: u8) -> Vec<u8> {
+        let length = self.length();
+        let length_field = match self.header_type {
+            ExtensionHeaderType::Fragment => 0,
+            // In 4-octet units, not counting the first two (RFC 4302)
+            ExtensionHeaderType::Authentication => length / 4 - 2,
+            // In 8-octet units, not counting the first eight
+            _ => length / 8 - 1,
+        };
+        let mut bytes = vec![next_header, length_field as u8];
+        bytes.extend_from_slice(&self.data);
+        bytes.resize(length, 0);
+        bytes
+    }
+
+    // Parses the header at the start of `bytes`, returning it with its next header field,
+    // or None if it does not fit
+    fn from_bytes(header_type: ExtensionHeaderType, bytes: &[u8]) -> Option<(Self, u8)> {
+        let length = match header_type {
+            ExtensionHeaderType::Fragment => 8,
+            ExtensionHeaderType::Authentication => (*bytes.get(1)? as usize + 2) * 4,
+            _ => (*bytes.get(1)? as usize + 1) * 8,
+        };
+        let header = Self {
+            header_type,
+            data: bytes.get(2..length)?.to_vec(),
+        };
+        Some((header, bytes[0]))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Ipv6Packet {
     pub src: Ipv6Addr,
     pub dest: Ipv6Addr,
     pub traffic_class: u8,
+    // 20-bit label of the flow the packet belongs to
+    pub flow_label: u32,
     pub hop_limit: u8,
+    // Upper-layer protocol carried after the extension headers
     pub protocol: Protocols,
-    pub payload_length: Option<u16>,
-    pub flow_label: Option<u32>,
-    pub next_header: Option<u8>, // Assuming a similar optional field as Python's None.
+    // Extension headers in the order they appear, each naming the next one (RFC 8200)
+    pub extension_headers: Vec<ExtensionHeader>,
     pub payload: IpPayload,
 }
 
 impl Ipv6Packet {
+    const HEADER_LENGTH: usize = 40;
+
     pub fn new(
         src: Ipv6Addr,
         dest: Ipv6Addr,
@@ -191,27 +504,140 @@ impl Ipv6Packet {
             src,
             dest,
             traffic_class,
+            flow_label: 0,
             hop_limit,
             protocol,
-            payload_length: None,
-            flow_label: None,
-            next_header: None,
+            extension_headers: Vec::new(),
             payload,
         }
     }
+
+    // Packet carrying an ICMPv6 message. Neighbor Discovery requires the maximum hop limit,
+    // which proves the packet was not forwarded by a router.
+    pub fn icmpv6(src: Ipv6Addr, dest: Ipv6Addr, message: &Icmpv6Message) -> Self {
+        let payload = IpPayload {
+            data: message.to_bytes(&src, &dest),
+        };
+        Self::new(src, dest, payload, 0, 255, Protocols::ICMPv6)
+    }
+
+    // The ICMPv6 message carried by the packet, if it is a valid one
+    pub fn icmpv6_message(&self) -> Option<Icmpv6Message> {
+        if self.protocol != Protocols::ICMPv6 {
+            return None;
+        }
+        Icmpv6Message::from_bytes(&self.payload.data, &self.src, &self.dest).ok()
+    }
+
+    // Length of everything after the fixed header: extension headers and upper-layer data
+    pub fn payload_length(&self) -> u16 {
+        let extensions: usize = self
+            .extension_headers
+            .iter()
+            .map(|header| header.length())
+            .sum();
+        (extensions + self.payload.data.len()) as u16
+    }
+
+    // Next header field of the fixed header: the first extension header if any, the
+    // upper-layer protocol otherwise
+    pub fn next_header(&self) -> u8 {
+        match self.extension_headers.first() {
+            Some(header) => header.header_type.get_value(),
+            None => self.protocol.get_value(),
+        }
+    }
+
+    /// Encodes the packet as specified in RFC 8200. The payload length and the next header
+    /// fields are derived from the extension headers and the upper-layer protocol.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let flow_label = self.flow_label & 0x000F_FFFF;
+        let mut bytes = vec![
+            (6 << 4) | (self.traffic_class >> 4),
+            (self.traffic_class << 4) | (flow_label >> 16) as u8,
+        ];
+        bytes.extend_from_slice(&(flow_label as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.payload_length().to_be_bytes());
+        bytes.push(self.next_header());
+        bytes.push(self.hop_limit);
+        bytes.extend_from_slice(&self.src.to_bytes());
+        bytes.extend_from_slice(&self.dest.to_bytes());
+        for (i, header) in self.extension_headers.iter().enumerate() {
+            let next_header = match self.extension_headers.get(i + 1) {
+                Some(next) => next.header_type.get_value(),
+                None => self.protocol.get_value(),
+            };
+            bytes.extend_from_slice(&header.to_bytes(next_header));
+        }
+        bytes.extend_from_slice(&self.payload.data);
+        bytes
+    }
+
+    /// Parses the fixed IPv6 header, follows the chain of extension headers and keeps the
+    /// upper-layer data as the payload.
+    /// Bytes beyond the payload length (such as Ethernet padding) are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(PacketError::Truncated {
+                expected: Self::HEADER_LENGTH,
+                actual: bytes.len(),
+            });
+        }
+        let version = bytes[0] >> 4;
+        if version != 6 {
+            return Err(PacketError::InvalidVersion(version));
+        }
+        let payload_length = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let end = Self::HEADER_LENGTH + payload_length as usize;
+        if bytes.len() < end {
+            return Err(PacketError::Truncated {
+                expected: end,
+                actual: bytes.len(),
+            });
+        }
+        let address = |bytes: &[u8]| {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(bytes);
+            Ipv6Addr::from_bytes(octets)
+        };
+
+        let mut next_header = bytes[6];
+        let mut offset = Self::HEADER_LENGTH;
+        let mut extension_headers = Vec::new();
+        while let Some(header_type) = ExtensionHeaderType::from_value(next_header) {
+            let (header, next) = ExtensionHeader::from_bytes(header_type, &bytes[offset..end])
+                .ok_or(PacketError::InvalidPayloadLength(payload_length))?;
+            offset += header.length();
+            extension_headers.push(header);
+            next_header = next;
+        }
+
+        Ok(Self {
+            src: address(&bytes[8..24]),
+            dest: address(&bytes[24..40]),
+            traffic_class: (bytes[0] << 4) | (bytes[1] >> 4),
+            flow_label: u32::from_be_bytes([0, bytes[1] & 0x0F, bytes[2], bytes[3]]),
+            hop_limit: bytes[7],
+            protocol: Protocols::from_value(next_header),
+            extension_headers,
+            payload: IpPayload {
+                data: bytes[offset..end].to_vec(),
+            },
+        })
+    }
 }
 
 impl fmt::Display for Ipv6Packet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "IPv6Packet {{ src: {}, dest: {}, traffic_class: {:02X}, hop_limit: {}, protocol: {}, payload_length: {:?}, flow_label: {:?}, next_header: {:?} }}",
+        write!(f, "IPv6Packet {{ src: {}, dest: {}, traffic_class: {:02X}, flow_label: {:05X}, payload_length: {}, next_header: {}, hop_limit: {}, protocol: {} }}",
             self.src,
             self.dest,
             self.traffic_class,
-            self.hop_limit,
-            self.protocol,
-            self.payload_length,
             self.flow_label,
-            self.next_header
+            self.payload_length(),
+            self.next_header(),
+            self.hop_limit,
+            self.protocol
         )
     }
 }
@@ -242,3 +668,164 @@ impl IpPacket {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ICMP echo request as sent by a Windows ping, 192.168.1.10 to 192.168.1.1:
+    // identification 0x1C46, DF set, TTL 128, header checksum 0x5B1F, ICMP checksum 0x4D5A
+    const ECHO_REQUEST: [u8; 60] = [
+        0x45, 0x00, 0x00, 0x3c, 0x1c, 0x46, 0x40, 0x00, 0x80, 0x01, 0x5b, 0x1f, 0xc0, 0xa8, 0x01,
+        0x0a, 0xc0, 0xa8, 0x01, 0x01, 0x08, 0x00, 0x4d, 0x5a, 0x00, 0x01, 0x00, 0x01, 0x61, 0x62,
+        0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70, 0x71,
+        0x72, 0x73, 0x74, 0x75, 0x76, 0x77, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    ];
+
+    // Header of a captured UDP datagram, 192.168.0.1 to 192.168.0.199, with checksum 0xB861.
+    // The checksum does not cover the payload, which is filled in by the test.
+    const UDP_HEADER: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+
+    fn echo_request() -> Ipv4Packet {
+        let message = IcmpMessage::echo_request(1, 1, b"abcdefghijklmnopqrstuvwabcdefghi".to_vec());
+        let mut packet = Ipv4Packet::icmp(
+            Ipv4Addr::new("192.168.1.10").unwrap(),
+            Ipv4Addr::new("192.168.1.1").unwrap(),
+            &message,
+        );
+        packet.header.identification = 0x1C46;
+        packet.header.flags = Ipv4Packet::DONT_FRAGMENT;
+        packet.header.ttl = 128;
+        packet.update_header();
+        packet
+    }
+
+    #[test]
+    fn encodes_echo_request_capture() {
+        let packet = echo_request();
+        assert_eq!(packet.header.header_checksum, 0x5B1F);
+        assert_eq!(packet.to_bytes(), ECHO_REQUEST);
+    }
+
+    #[test]
+    fn parses_echo_request_capture() {
+        let packet = Ipv4Packet::from_bytes(&ECHO_REQUEST).unwrap();
+        assert_eq!(packet, echo_request());
+        assert_eq!(packet.to_bytes(), ECHO_REQUEST);
+        assert!(matches!(
+            packet.icmp_message(),
+            Some(IcmpMessage::EchoRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn round_trips_udp_capture() {
+        let mut bytes = UDP_HEADER.to_vec();
+        bytes.extend((0..95).map(|i| i as u8));
+        let packet = Ipv4Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.header.protocol, Protocols::UDP);
+        assert_eq!(packet.header.header_checksum, 0xB861);
+        assert_eq!(packet.payload.data.len(), 95);
+        assert_eq!(packet.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn ignores_trailing_padding() {
+        let mut bytes = ECHO_REQUEST.to_vec();
+        bytes.extend_from_slice(&[0; 6]);
+        let packet = Ipv4Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.to_bytes(), ECHO_REQUEST);
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        assert_eq!(
+            Ipv4Packet::from_bytes(&ECHO_REQUEST[..12]).unwrap_err(),
+            PacketError::Truncated {
+                expected: 20,
+                actual: 12
+            }
+        );
+        assert_eq!(
+            Ipv4Packet::from_bytes(&ECHO_REQUEST[..40]).unwrap_err(),
+            PacketError::Truncated {
+                expected: 60,
+                actual: 40
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_header_length() {
+        let mut bytes = ECHO_REQUEST;
+        bytes[0] = 0x44;
+        assert_eq!(
+            Ipv4Packet::from_bytes(&bytes).unwrap_err(),
+            PacketError::InvalidHeaderLength(4)
+        );
+    }
+
+    #[test]
+    fn rejects_checksum_mismatch() {
+        let mut bytes = ECHO_REQUEST;
+        // TTL decremented without updating the checksum
+        bytes[8] = 127;
+        assert_eq!(
+            Ipv4Packet::from_bytes(&bytes).unwrap_err(),
+            PacketError::ChecksumMismatch {
+                expected: 0x5C1F,
+                actual: 0x5B1F
+            }
+        );
+    }
+
+    fn ipv6_packet(protocol: Protocols) -> Ipv6Packet {
+        Ipv6Packet::new(
+            Ipv6Addr::new("2001:db8::1").unwrap(),
+            Ipv6Addr::new("2001:db8::2").unwrap(),
+            IpPayload {
+                data: vec![0xAB; 8],
+            },
+            0,
+            64,
+            protocol,
+        )
+    }
+
+    #[test]
+    fn keeps_unknown_upper_layer_protocol() {
+        // 253 is reserved for experimentation (RFC 3692)
+        let packet = ipv6_packet(Protocols::from_value(253));
+        assert_eq!(packet.protocol, Protocols::Other(253));
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes[6], 253);
+        let parsed = Ipv6Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.protocol, Protocols::Other(253));
+        assert_eq!(parsed.payload, packet.payload);
+        assert_eq!(parsed.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn sizes_authentication_header_in_4_octet_units() {
+        let mut packet = ipv6_packet(Protocols::UDP);
+        // SPI, sequence number and a 32-bit ICV: 12 octets on the wire, length field 1
+        let header = ExtensionHeader {
+            header_type: ExtensionHeaderType::Authentication,
+            data: vec![0; 10],
+        };
+        assert_eq!(header.length(), 12);
+        packet.extension_headers.push(header);
+        let bytes = packet.to_bytes();
+        assert_eq!(packet.payload_length(), 20);
+        assert_eq!(bytes.len(), 60);
+        assert_eq!(&bytes[40..42], &[Protocols::UDP.get_value(), 1]);
+        let parsed = Ipv6Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.extension_headers, packet.extension_headers);
+        assert_eq!(parsed.protocol, Protocols::UDP);
+        assert_eq!(parsed.payload, packet.payload);
+        assert_eq!(parsed.to_bytes(), bytes);
+    }
+}
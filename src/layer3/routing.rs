@@ -0,0 +1,321 @@
+use super::address::{Ipv4Addr, Ipv4Interface, Ipv4Network};
+use bevy::prelude::*;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteType {
+    Connected,
+    Static,
+}
+
+impl RouteType {
+    // Administrative distance used by Cisco IOS
+    pub fn administrative_distance(&self) -> u8 {
+        match self {
+            RouteType::Connected => 0,
+            RouteType::Static => 1,
+        }
+    }
+}
+
+impl fmt::Display for RouteType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteType::Connected => f.pad("C"),
+            RouteType::Static => f.pad("S"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub network: Ipv4Network,
+    // Routes through a gateway have a next hop, which may itself need a route to be reached
+    pub next_hop: Option<Ipv4Addr>,
+    // Routes out of an interface; destinations are then reached directly on that network
+    pub interface: Option<Entity>,
+    pub route_type: RouteType,
+}
+
+impl Route {
+    pub fn contains(&self, address: &Ipv4Addr) -> bool {
+        self.network.contains(address)
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.network.prefix_length()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+    // Routes out of these interfaces stay configured but are not used while they are down
+    down_interfaces: Vec<Entity>,
+}
+
+impl RoutingTable {
+    // Static routes are resolved through at most this many next hops
+    const MAX_RECURSION: usize = 8;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the connected routes with one per configured interface address,
+    /// primary and secondary, given as (interface, address)
+    pub fn set_connected_routes(&mut self, interfaces: &[(Entity, Ipv4Interface)]) {
+        self.routes
+            .retain(|route| route.route_type != RouteType::Connected);
+        for (interface, address) in interfaces {
+            self.routes.push(Route {
+                network: address.network(),
+                next_hop: None,
+                interface: Some(*interface),
+                route_type: RouteType::Connected,
+            });
+        }
+    }
+
+    // Records which interfaces are down; like connected routes, this is refreshed as the
+    // interfaces change state
+    pub fn set_down_interfaces(&mut self, interfaces: &[Entity]) {
+        self.down_interfaces = interfaces.to_vec();
+    }
+
+    // Whether the route leads out of an interface that is up, or through a gateway
+    fn is_usable(&self, route: &Route) -> bool {
+        route
+            .interface
+            .is_none_or(|interface| !self.down_interfaces.contains(&interface))
+    }
+
+    // Adds a static route through a gateway, e.g. "ip route 10.0.0.0 255.0.0.0 192.168.1.2"
+    pub fn add_static_route(&mut self, network: Ipv4Network, next_hop: Ipv4Addr) {
+        self.add_route(Route {
+            network,
+            next_hop: Some(next_hop),
+            interface: None,
+            route_type: RouteType::Static,
+        });
+    }
+
+    // Adds a static route out of an interface, e.g. "ip route 10.0.0.0 255.0.0.0 Fa0/1"
+    pub fn add_interface_route(&mut self, network: Ipv4Network, interface: Entity) {
+        self.add_route(Route {
+            network,
+            next_hop: None,
+            interface: Some(interface),
+            route_type: RouteType::Static,
+        });
+    }
+
+    fn add_route(&mut self, route: Route) {
+        if !self.routes.contains(&route) {
+            self.routes.push(route);
+        }
+    }
+
+    // Removes every static route to the given network
+    pub fn remove_static_route(&mut self, network: Ipv4Network) {
+        self.routes
+            .retain(|route| route.route_type != RouteType::Static || route.network != network);
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Longest prefix match: the most specific route containing `destination`,
+    /// preferring the lowest administrative distance among equally specific ones
+    pub fn lookup(&self, destination: &Ipv4Addr) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.contains(destination) && self.is_usable(route))
+            .min_by_key(|route| {
+                (
+                    std::cmp::Reverse(route.prefix_length()),
+                    route.route_type.administrative_distance(),
+                )
+            })
+    }
+
+    /// Finds the egress interface and the neighbor to hand a packet for `destination` to,
+    /// following next hops recursively. Returns None if there is no usable route.
+    pub fn resolve(&self, destination: &Ipv4Addr) -> Option<(Entity, Ipv4Addr)> {
+        let mut target = *destination;
+        for _ in 0..Self::MAX_RECURSION {
+            let route = self.lookup(&target)?;
+            match (route.interface, route.next_hop) {
+                (Some(interface), Some(next_hop)) => return Some((interface, next_hop)),
+                // The target is on the attached network
+                (Some(interface), None) => return Some((interface, target)),
+                (None, Some(next_hop)) => target = next_hop,
+                (None, None) => return None,
+            }
+        }
+        None
+    }
+
+    /// Formats the table like "show ip route", using `interface_name` to label the interfaces
+    pub fn show<F: Fn(Entity) -> String>(&self, interface_name: F) -> String {
+        let mut routes: Vec<&Route> = self
+            .routes
+            .iter()
+            .filter(|route| self.is_usable(route))
+            .collect();
+        routes.sort_by_key(|route| (route.network.address().to_u32(), route.prefix_length()));
+
+        let mut output = String::new();
+        output.push_str("Codes: C - connected, S - static, * - candidate default\n\n");
+        let default_route = routes
+            .iter()
+            .find(|route| route.prefix_length() == 0 && route.next_hop.is_some());
+        match default_route.and_then(|route| route.next_hop) {
+            Some(gateway) => output.push_str(&format!(
+                "Gateway of last resort is {} to network 0.0.0.0\n\n",
+                gateway
+            )),
+            None => output.push_str("Gateway of last resort is not set\n\n"),
+        }
+        for route in routes {
+            let code = if route.prefix_length() == 0 {
+                format!("{}*", route.route_type)
+            } else {
+                route.route_type.to_string()
+            };
+            let destination = route.network.to_string();
+            let via = match (route.next_hop, route.interface) {
+                (Some(next_hop), Some(interface)) => {
+                    format!("via {}, {}", next_hop, interface_name(interface))
+                }
+                (Some(next_hop), None) => format!("via {}", next_hop),
+                (None, Some(interface)) => {
+                    format!("is directly connected, {}", interface_name(interface))
+                }
+                (None, None) => String::new(),
+            };
+            match route.route_type {
+                RouteType::Connected => {
+                    output.push_str(&format!("{:<4} {} {}\n", code, destination, via))
+                }
+                RouteType::Static => output.push_str(&format!(
+                    "{:<4} {} [{}/0] {}\n",
+                    code,
+                    destination,
+                    route.route_type.administrative_distance(),
+                    via
+                )),
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(value: &str) -> Ipv4Network {
+        value.parse().unwrap()
+    }
+
+    fn address(value: &str) -> Ipv4Addr {
+        value.parse().unwrap()
+    }
+
+    // A router with 192.168.1.1/24 on the first interface and 172.16.0.1/16 on the second
+    fn table() -> (RoutingTable, Entity, Entity) {
+        let (lan, wan) = (Entity::from_raw(1), Entity::from_raw(2));
+        let mut table = RoutingTable::new();
+        table.set_connected_routes(&[
+            (lan, "192.168.1.1/24".parse().unwrap()),
+            (wan, "172.16.0.1/16".parse().unwrap()),
+        ]);
+        (table, lan, wan)
+    }
+
+    #[test]
+    fn matches_longest_prefix() {
+        let (mut table, _, _) = table();
+        table.add_static_route(network("0.0.0.0/0"), address("172.16.0.254"));
+        table.add_static_route(network("10.0.0.0/8"), address("172.16.0.8"));
+        table.add_static_route(network("10.1.0.0/16"), address("172.16.0.16"));
+        table.add_static_route(network("10.1.2.0/24"), address("172.16.0.24"));
+
+        for (destination, next_hop) in [
+            ("10.1.2.3", "172.16.0.24"),
+            ("10.1.3.3", "172.16.0.16"),
+            ("10.2.3.4", "172.16.0.8"),
+            ("8.8.8.8", "172.16.0.254"),
+        ] {
+            let route = table.lookup(&address(destination)).unwrap();
+            assert_eq!(route.next_hop, Some(address(next_hop)), "{}", destination);
+        }
+        assert_eq!(
+            table.lookup(&address("192.168.1.7")).unwrap().route_type,
+            RouteType::Connected
+        );
+    }
+
+    #[test]
+    fn prefers_lower_administrative_distance() {
+        let (mut table, lan, wan) = table();
+        table.add_interface_route(network("192.168.1.0/24"), wan);
+        let route = table.lookup(&address("192.168.1.7")).unwrap();
+        assert_eq!(route.route_type, RouteType::Connected);
+        assert_eq!(route.interface, Some(lan));
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn resolves_next_hops_recursively() {
+        let (mut table, _, wan) = table();
+        table.add_static_route(network("10.0.0.0/8"), address("20.0.0.1"));
+        table.add_static_route(network("20.0.0.0/8"), address("172.16.0.2"));
+        assert_eq!(
+            table.resolve(&address("10.1.1.1")),
+            Some((wan, address("172.16.0.2")))
+        );
+        // Destinations on an attached network are their own next hop
+        assert_eq!(
+            table.resolve(&address("172.16.5.5")),
+            Some((wan, address("172.16.5.5")))
+        );
+        assert_eq!(table.resolve(&address("30.0.0.1")), None);
+    }
+
+    #[test]
+    fn stops_resolving_routing_loops() {
+        let (mut table, _, _) = table();
+        table.add_static_route(network("10.0.0.0/8"), address("20.0.0.1"));
+        table.add_static_route(network("20.0.0.0/8"), address("10.0.0.1"));
+        assert_eq!(table.resolve(&address("10.1.1.1")), None);
+    }
+
+    #[test]
+    fn skips_routes_out_of_down_interfaces() {
+        let (mut table, lan, _) = table();
+        table.add_static_route(network("10.0.0.0/8"), address("192.168.1.2"));
+        assert_eq!(
+            table.resolve(&address("10.1.1.1")),
+            Some((lan, address("192.168.1.2")))
+        );
+
+        table.set_down_interfaces(&[lan]);
+        assert!(table.lookup(&address("192.168.1.7")).is_none());
+        assert_eq!(table.resolve(&address("10.1.1.1")), None);
+        assert!(!table.show(|_| String::new()).contains("192.168.1.0"));
+
+        table.set_down_interfaces(&[]);
+        assert!(table.resolve(&address("10.1.1.1")).is_some());
+    }
+}
@@ -1,21 +1,15 @@
 use bevy::prelude::*;
-use netsim::layer1::crc::crc32;
-use netsim::layer1::{link::Link, hub::Hub, Layer1Plugin};
-use netsim::layer2::address::MacAddress;
-use netsim::layer2::{
-    interface::{
-        DestinationInterface, Direction, EthernetInterface, Interface, InterfaceType,
-        SourceInterface,
-    },
-    pdu::{EthernetFrame, EthernetPayload},
-    Layer2Plugin,
+use netsim::layer1::hub::Hub;
+use netsim::layer2::interface::{
+    DestinationInterface, EthernetInterface, Interface, InterfaceType, SourceInterface,
 };
 use netsim::layer3::address::Ipv4Addr;
+use netsim::simulation::NetSimPlugin;
 
 fn main() {
     App::new()
         .insert_resource(Time::<Fixed>::from_seconds(1.0))
-        .add_plugins((DefaultPlugins, Layer1Plugin, Layer2Plugin))
+        .add_plugins(NetSimPlugin)
         .add_systems(
             Startup,
             (setup, add_frame_to_source_interface, connect_interfaces).chain(),
@@ -25,15 +19,11 @@ fn main() {
 
 fn setup(mut commands: Commands) {
     let mut fe_int_1 = EthernetInterface::new(InterfaceType::FastEthernet);
-    fe_int_1.set_ipv4_address(Ipv4Addr::new("192.168.1.1"));
+    fe_int_1.set_ipv4_address("192.168.1.1/24".parse().unwrap());
     let mut fe_int_2 = EthernetInterface::new(InterfaceType::FastEthernet);
-    fe_int_2.set_ipv4_address(Ipv4Addr::new("192.168.1.2"));
+    fe_int_2.set_ipv4_address("192.168.1.2/24".parse().unwrap());
     let mut fe_int_3 = EthernetInterface::new(InterfaceType::FastEthernet);
-    fe_int_3.set_ipv4_address(Ipv4Addr::new("192.168.1.3"));
-
-    let mac_1 = fe_int_1.mac_address.clone();
-    let mac_2 = fe_int_2.mac_address.clone();
-    let mac_3 = fe_int_3.mac_address.clone();
+    fe_int_3.set_ipv4_address("192.168.1.3/24".parse().unwrap());
 
     // Spawn the interface entities
     commands.spawn((
@@ -60,7 +50,7 @@ fn add_frame_to_source_interface(
 ) {
     let mut interface = query_interface.single_mut();
     if let Interface::Ethernet(int) = &mut *interface {
-        int.send_arp_request(Ipv4Addr::new("192.168.1.2"));
+        int.send_arp_request(Ipv4Addr::new("192.168.1.2").unwrap());
     }
 }
 
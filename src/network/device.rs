@@ -1,8 +1,28 @@
 use super::super::layer3::address::IpAddr;
+use super::ping::{PingSession, PingStyle};
+use super::traceroute::TracerouteSession;
+use crate::layer2::switch::MacAddressTable;
+use crate::layer3::routing::RoutingTable;
 use bevy::prelude::*;
 
 pub trait NetworkDevice {
-    fn ping(&self, ip: IpAddr) -> bool;
+    // Conventions the device follows when printing the output of diagnostic commands
+    fn ping_style(&self) -> PingStyle;
+
+    /// Prepares a ping to `ip` with the defaults of the device. The session runs once it is
+    /// inserted on the device entity: requests go out and results come in as the simulation
+    /// advances (see `Simulation::ping`).
+    /// Only IPv4 destinations are supported: requests to an IPv6 address are never sent and
+    /// every one of them is reported as a failure.
+    fn ping(&self, ip: IpAddr) -> PingSession {
+        PingSession::new(ip, self.ping_style())
+    }
+
+    /// Prepares a traceroute to `ip` with the defaults of the device, run the same way as
+    /// `ping` (see `Simulation::traceroute`). Like `ping`, it only supports IPv4.
+    fn traceroute(&self, ip: IpAddr) -> TracerouteSession {
+        TracerouteSession::new(ip, self.ping_style())
+    }
 }
 #[derive(Debug)]
 pub enum RouterModel {
@@ -29,6 +49,7 @@ pub enum SwitchModel {
 pub struct Router {
     pub model: RouterModel,
     pub interfaces: Vec<Entity>,
+    pub routing_table: RoutingTable,
 }
 
 impl Router {
@@ -36,6 +57,7 @@ impl Router {
         Self {
             model,
             interfaces: Vec::new(),
+            routing_table: RoutingTable::new(),
         }
     }
 
@@ -45,33 +67,63 @@ impl Router {
 }
 
 impl NetworkDevice for Router {
-    // TODO: implement ping
-    fn ping(&self, _ip: IpAddr) -> bool {
-        true
+    fn ping_style(&self) -> PingStyle {
+        PingStyle::Cisco
     }
 }
 
 #[derive(Component)]
 pub struct Switch {
     pub model: SwitchModel,
+    pub interfaces: Vec<Entity>,
+    pub mac_table: MacAddressTable,
 }
 
 impl Switch {
     pub fn new(model: SwitchModel) -> Self {
-        Self { model }
+        Self {
+            model,
+            interfaces: Vec::new(),
+            mac_table: MacAddressTable::new(),
+        }
+    }
+
+    // The interface entity also needs a `SwitchPort` component
+    pub fn add_interface(&mut self, interface: Entity) {
+        self.interfaces.push(interface);
     }
 }
+
 #[derive(Component)]
 pub struct Endpoint {
     pub os_type: OsType,
+    pub interfaces: Vec<Entity>,
 }
 
 impl Endpoint {
     pub fn new(os_type: OsType) -> Self {
-        Self { os_type }
+        Self {
+            os_type,
+            interfaces: Vec::new(),
+        }
+    }
+
+    pub fn add_interface(&mut self, interface: Entity) {
+        self.interfaces.push(interface);
+    }
+}
+
+impl NetworkDevice for Endpoint {
+    fn ping_style(&self) -> PingStyle {
+        match self.os_type {
+            OsType::Windows => PingStyle::Windows,
+            // macOS prints the BSD output, which is close enough to the Linux one
+            OsType::MacOS | OsType::Linux => PingStyle::Linux,
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OsType {
     Windows,
     MacOS,
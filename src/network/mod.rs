@@ -1 +1,37 @@
+use crate::layer2::systems::{peek_queues, process_frames};
+use bevy::prelude::*;
+use systems::{
+    host_packets, route_packets, run_ping_sessions, run_spanning_tree, run_traceroute_sessions,
+    switch_frames,
+};
+
 pub mod device;
+pub mod ping;
+pub mod systems;
+pub mod traceroute;
+
+pub struct NetworkPlugin;
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (switch_frames, run_spanning_tree)
+                .chain()
+                .after(peek_queues)
+                .before(process_frames),
+        )
+        .add_systems(
+            FixedUpdate,
+            (
+                route_packets,
+                host_packets,
+                run_ping_sessions,
+                run_traceroute_sessions,
+            )
+                .chain()
+                .after(peek_queues)
+                .before(process_frames),
+        );
+    }
+}
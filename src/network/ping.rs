@@ -0,0 +1,601 @@
+use crate::layer3::address::{IpAddr, Ipv4Addr};
+use crate::layer3::icmp::{IcmpMessage, UnreachableCode};
+use crate::layer3::pdu::Ipv4Packet;
+use bevy::prelude::*;
+use std::time::Duration;
+
+/// Output conventions of the ping command, which differ between network gear and host systems
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingStyle {
+    Cisco,
+    Windows,
+    Linux,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingOptions {
+    pub count: u16,
+    // Bytes of ICMP data carried by every echo request
+    pub data_size: usize,
+    pub timeout: Duration,
+    // Minimum time between two consecutive requests
+    pub interval: Duration,
+    // Whether the next request waits for the reply to (or the timeout of) the previous one
+    pub wait_for_reply: bool,
+    pub ttl: u8,
+}
+
+impl PingOptions {
+    /// Defaults of "ping" on IOS, Windows and Linux. Linux pings until interrupted,
+    /// which is cut down to four requests here.
+    pub fn defaults(style: PingStyle) -> Self {
+        match style {
+            PingStyle::Cisco => Self {
+                count: 5,
+                data_size: 72,
+                timeout: Duration::from_secs(2),
+                interval: Duration::ZERO,
+                wait_for_reply: true,
+                ttl: 255,
+            },
+            PingStyle::Windows => Self {
+                count: 4,
+                data_size: 32,
+                timeout: Duration::from_secs(4),
+                interval: Duration::from_secs(1),
+                wait_for_reply: true,
+                ttl: 128,
+            },
+            PingStyle::Linux => Self {
+                count: 4,
+                data_size: 56,
+                timeout: Duration::from_secs(10),
+                interval: Duration::from_secs(1),
+                wait_for_reply: false,
+                ttl: 64,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeResult {
+    Reply {
+        from: Ipv4Addr,
+        // Bytes of ICMP data echoed back
+        bytes: usize,
+        ttl: u8,
+        rtt: Duration,
+    },
+    Unreachable {
+        from: Ipv4Addr,
+        code: UnreachableCode,
+        rtt: Duration,
+    },
+    TtlExceeded {
+        from: Ipv4Addr,
+        rtt: Duration,
+    },
+    Timeout,
+    // The device had no route or no address to send the request from
+    SendFailed,
+}
+
+impl ProbeResult {
+    // Address of the device that answered, if any did
+    pub fn from(&self) -> Option<Ipv4Addr> {
+        match self {
+            ProbeResult::Reply { from, .. }
+            | ProbeResult::Unreachable { from, .. }
+            | ProbeResult::TtlExceeded { from, .. } => Some(*from),
+            ProbeResult::Timeout | ProbeResult::SendFailed => None,
+        }
+    }
+
+    // Time until the answer came back, if one did
+    pub fn rtt(&self) -> Option<Duration> {
+        match self {
+            ProbeResult::Reply { rtt, .. }
+            | ProbeResult::Unreachable { rtt, .. }
+            | ProbeResult::TtlExceeded { rtt, .. } => Some(*rtt),
+            ProbeResult::Timeout | ProbeResult::SendFailed => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub sequence: u16,
+    pub sent_at: Duration,
+    pub result: Option<ProbeResult>,
+    // Simulation time at which the result became known
+    pub resolved_at: Option<Duration>,
+}
+
+impl Probe {
+    pub fn new(sequence: u16, sent_at: Duration) -> Self {
+        Self {
+            sequence,
+            sent_at,
+            result: None,
+            resolved_at: None,
+        }
+    }
+
+    /// Records the answer `message` carried by `packet`. Answers arriving after the probe
+    /// timed out are ignored, like the real commands do. Returns whether it was recorded.
+    pub fn resolve(&mut self, packet: &Ipv4Packet, message: IcmpMessage, now: Duration) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+        let from = packet.header.src;
+        let rtt = now.saturating_sub(self.sent_at);
+        self.result = Some(match message {
+            IcmpMessage::EchoReply { data, .. } => ProbeResult::Reply {
+                from,
+                bytes: data.len(),
+                ttl: packet.header.ttl,
+                rtt,
+            },
+            IcmpMessage::DestinationUnreachable { code, .. } => {
+                ProbeResult::Unreachable { from, code, rtt }
+            }
+            _ => ProbeResult::TtlExceeded { from, rtt },
+        });
+        self.resolved_at = Some(now);
+        true
+    }
+
+    // Gives up on the probe if no answer came within `timeout`
+    pub fn expire(&mut self, timeout: Duration, now: Duration) {
+        if self.result.is_none() && now >= self.sent_at + timeout {
+            self.result = Some(ProbeResult::Timeout);
+            self.resolved_at = Some(self.sent_at + timeout);
+        }
+    }
+
+    // Records that the probe could not be sent at all
+    pub fn fail(&mut self, now: Duration) {
+        self.result = Some(ProbeResult::SendFailed);
+        self.resolved_at = Some(now);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundTripStats {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    // Mean deviation, as reported by Linux
+    pub mdev: Duration,
+}
+
+/// A ping running on the device entity it is inserted on. Echo requests are sent as the
+/// simulation advances and every one of them ends up with a reply, an ICMP error or a timeout,
+/// all timed in simulation time.
+#[derive(Component, Debug, Clone)]
+pub struct PingSession {
+    pub destination: IpAddr,
+    pub style: PingStyle,
+    pub options: PingOptions,
+    // Echo identifier telling the replies to this session apart from others
+    pub identifier: u16,
+    pub probes: Vec<Probe>,
+    scheduled_wakeup: Option<Duration>,
+}
+
+impl PingSession {
+    pub fn new(destination: IpAddr, style: PingStyle) -> Self {
+        Self::with_options(destination, style, PingOptions::defaults(style))
+    }
+
+    pub fn with_options(destination: IpAddr, style: PingStyle, options: PingOptions) -> Self {
+        Self {
+            destination,
+            style,
+            options,
+            identifier: rand::random(),
+            probes: Vec::new(),
+            scheduled_wakeup: None,
+        }
+    }
+
+    // Whether every request has been sent and has a result
+    pub fn is_complete(&self) -> bool {
+        self.probes.len() >= usize::from(self.options.count)
+            && self.probes.iter().all(|probe| probe.result.is_some())
+    }
+
+    /// Times out the requests left unanswered for too long and returns the sequence number of
+    /// the next request if it is due. The caller sends it, or reports it with `fail`.
+    pub fn poll(&mut self, now: Duration) -> Option<u16> {
+        let timeout = self.options.timeout;
+        for probe in self.probes.iter_mut() {
+            probe.expire(timeout, now);
+        }
+        if now < self.next_send_time()? {
+            return None;
+        }
+        let sequence = self.probes.len() as u16 + 1;
+        self.probes.push(Probe::new(sequence, now));
+        Some(sequence)
+    }
+
+    // Records that the request `sequence` could not be sent at all
+    pub fn fail(&mut self, sequence: u16, now: Duration) {
+        if let Some(probe) = self.probe_mut(sequence) {
+            probe.fail(now);
+        }
+    }
+
+    // Echo request `sequence` from `source`, or None if the destination is not IPv4
+    pub fn echo_request(&self, source: Ipv4Addr, sequence: u16) -> Option<Ipv4Packet> {
+        let IpAddr::V4(destination) = self.destination else {
+            return None;
+        };
+        Some(echo_request(
+            source,
+            destination,
+            self.identifier,
+            sequence,
+            self.options.data_size,
+            self.options.ttl,
+        ))
+    }
+
+    /// Matches a packet delivered to the device against the outstanding requests: an echo
+    /// reply, or an ICMP error quoting one of the requests. Returns whether it was ours.
+    pub fn receive(&mut self, packet: &Ipv4Packet, now: Duration) -> bool {
+        let Some((sequence, message)) = echo_response(packet, self.identifier) else {
+            return false;
+        };
+        self.probe_mut(sequence)
+            .is_some_and(|probe| probe.resolve(packet, message, now))
+    }
+
+    // Next time the session needs to run, if not already scheduled
+    pub fn next_wakeup(&mut self) -> Option<Duration> {
+        let timeout = self.options.timeout;
+        let next = self
+            .probes
+            .iter()
+            .filter(|probe| probe.result.is_none())
+            .map(|probe| probe.sent_at + timeout)
+            .chain(self.next_send_time())
+            .min()?;
+        if self.scheduled_wakeup == Some(next) {
+            return None;
+        }
+        self.scheduled_wakeup = Some(next);
+        Some(next)
+    }
+
+    fn next_send_time(&self) -> Option<Duration> {
+        if self.probes.len() >= usize::from(self.options.count) {
+            return None;
+        }
+        let Some(last) = self.probes.last() else {
+            return Some(Duration::ZERO);
+        };
+        let after_interval = last.sent_at + self.options.interval;
+        if self.options.wait_for_reply {
+            last.resolved_at
+                .map(|resolved| resolved.max(after_interval))
+        } else {
+            Some(after_interval)
+        }
+    }
+
+    fn probe_mut(&mut self, sequence: u16) -> Option<&mut Probe> {
+        self.probes
+            .iter_mut()
+            .find(|probe| probe.sequence == sequence)
+    }
+
+    pub fn sent(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn received(&self) -> usize {
+        self.round_trip_times().len()
+    }
+
+    // Requests answered by an ICMP error instead of a reply
+    pub fn errors(&self) -> usize {
+        self.probes
+            .iter()
+            .filter(|probe| {
+                matches!(
+                    probe.result,
+                    Some(ProbeResult::Unreachable { .. } | ProbeResult::TtlExceeded { .. })
+                )
+            })
+            .count()
+    }
+
+    pub fn loss_percent(&self) -> f64 {
+        if self.probes.is_empty() {
+            return 0.0;
+        }
+        (self.sent() - self.received()) as f64 * 100.0 / self.sent() as f64
+    }
+
+    pub fn round_trip_times(&self) -> Vec<Duration> {
+        self.probes
+            .iter()
+            .filter_map(|probe| match probe.result {
+                Some(ProbeResult::Reply { rtt, .. }) => Some(rtt),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn round_trip_stats(&self) -> Option<RoundTripStats> {
+        let rtts = self.round_trip_times();
+        let min = *rtts.iter().min()?;
+        let max = *rtts.iter().max()?;
+        let avg = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+        let variance = rtts
+            .iter()
+            .map(|rtt| (rtt.as_secs_f64() - avg.as_secs_f64()).powi(2))
+            .sum::<f64>()
+            / rtts.len() as f64;
+        Some(RoundTripStats {
+            min,
+            avg,
+            max,
+            mdev: Duration::from_secs_f64(variance.sqrt()),
+        })
+    }
+
+    /// Formats the session like the output of the ping command of its style
+    pub fn report(&self) -> String {
+        match self.style {
+            PingStyle::Cisco => self.cisco_report(),
+            PingStyle::Windows => self.windows_report(),
+            PingStyle::Linux => self.linux_report(),
+        }
+    }
+
+    fn cisco_report(&self) -> String {
+        let mut output = String::new();
+        output.push_str("Type escape sequence to abort.\n");
+        output.push_str(&format!(
+            "Sending {}, {}-byte ICMP Echos to {}, timeout is {} seconds:\n",
+            self.options.count,
+            self.options.data_size + 28,
+            self.destination,
+            self.options.timeout.as_secs()
+        ));
+        let marks: Vec<char> = self
+            .probes
+            .iter()
+            .map(|probe| match &probe.result {
+                Some(ProbeResult::Reply { .. }) => '!',
+                Some(ProbeResult::Unreachable {
+                    code: UnreachableCode::FragmentationNeeded,
+                    ..
+                }) => 'M',
+                Some(ProbeResult::Unreachable {
+                    code: UnreachableCode::AdministrativelyProhibited,
+                    ..
+                }) => 'A',
+                Some(ProbeResult::Unreachable { .. }) => 'U',
+                Some(ProbeResult::TtlExceeded { .. }) => '&',
+                _ => '.',
+            })
+            .collect();
+        // IOS wraps the marks every 70 characters
+        for line in marks.chunks(70) {
+            output.push_str(&line.iter().collect::<String>());
+            output.push('\n');
+        }
+        let success = (self.received() * 100)
+            .checked_div(self.sent())
+            .unwrap_or(0);
+        output.push_str(&format!(
+            "Success rate is {} percent ({}/{})",
+            success,
+            self.received(),
+            self.sent()
+        ));
+        if let Some(stats) = self.round_trip_stats() {
+            output.push_str(&format!(
+                ", round-trip min/avg/max = {}/{}/{} ms",
+                millis(stats.min),
+                millis(stats.avg),
+                millis(stats.max)
+            ));
+        }
+        output.push('\n');
+        output
+    }
+
+    fn windows_report(&self) -> String {
+        let mut output = String::new();
+        output.push_str(&format!(
+            "\nPinging {} with {} bytes of data:\n",
+            self.destination, self.options.data_size
+        ));
+        for probe in &self.probes {
+            let line = match &probe.result {
+                Some(ProbeResult::Reply {
+                    from,
+                    bytes,
+                    ttl,
+                    rtt,
+                }) => {
+                    let time = if *rtt < Duration::from_millis(1) {
+                        "time<1ms".to_string()
+                    } else {
+                        format!("time={}ms", millis(*rtt))
+                    };
+                    format!("Reply from {}: bytes={} {} TTL={}", from, bytes, time, ttl)
+                }
+                Some(ProbeResult::Unreachable { from, code, .. }) => {
+                    let reason = match code {
+                        UnreachableCode::Network => "Destination net unreachable.",
+                        UnreachableCode::Host => "Destination host unreachable.",
+                        UnreachableCode::Protocol => "Destination protocol unreachable.",
+                        UnreachableCode::Port => "Destination port unreachable.",
+                        UnreachableCode::FragmentationNeeded => {
+                            "Packet needs to be fragmented but DF set."
+                        }
+                        UnreachableCode::AdministrativelyProhibited => {
+                            "Communication administratively prohibited."
+                        }
+                    };
+                    format!("Reply from {}: {}", from, reason)
+                }
+                Some(ProbeResult::TtlExceeded { from, .. }) => {
+                    format!("Reply from {}: TTL expired in transit.", from)
+                }
+                Some(ProbeResult::SendFailed) => {
+                    "PING: transmit failed. General failure.".to_string()
+                }
+                Some(ProbeResult::Timeout) | None => "Request timed out.".to_string(),
+            };
+            output.push_str(&line);
+            output.push('\n');
+        }
+        output.push_str(&format!("\nPing statistics for {}:\n", self.destination));
+        output.push_str(&format!(
+            "    Packets: Sent = {}, Received = {}, Lost = {} ({}% loss),\n",
+            self.sent(),
+            self.received(),
+            self.sent() - self.received(),
+            self.loss_percent() as u32
+        ));
+        if let Some(stats) = self.round_trip_stats() {
+            output.push_str("Approximate round trip times in milli-seconds:\n");
+            output.push_str(&format!(
+                "    Minimum = {}ms, Maximum = {}ms, Average = {}ms\n",
+                millis(stats.min),
+                millis(stats.max),
+                millis(stats.avg)
+            ));
+        }
+        output
+    }
+
+    fn linux_report(&self) -> String {
+        let mut output = String::new();
+        output.push_str(&format!(
+            "PING {} ({}) {}({}) bytes of data.\n",
+            self.destination,
+            self.destination,
+            self.options.data_size,
+            self.options.data_size + 28
+        ));
+        for probe in &self.probes {
+            let line = match &probe.result {
+                Some(ProbeResult::Reply {
+                    from,
+                    bytes,
+                    ttl,
+                    rtt,
+                }) => format!(
+                    "{} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+                    bytes + 8,
+                    from,
+                    probe.sequence,
+                    ttl,
+                    rtt.as_secs_f64() * 1000.0
+                ),
+                Some(ProbeResult::Unreachable { from, code, .. }) => {
+                    let reason = match code {
+                        UnreachableCode::Network => "Destination Net Unreachable",
+                        UnreachableCode::Host => "Destination Host Unreachable",
+                        UnreachableCode::Protocol => "Destination Protocol Unreachable",
+                        UnreachableCode::Port => "Destination Port Unreachable",
+                        UnreachableCode::FragmentationNeeded => "Frag needed and DF set",
+                        UnreachableCode::AdministrativelyProhibited => "Packet filtered",
+                    };
+                    format!("From {} icmp_seq={} {}", from, probe.sequence, reason)
+                }
+                Some(ProbeResult::TtlExceeded { from, .. }) => {
+                    format!(
+                        "From {} icmp_seq={} Time to live exceeded",
+                        from, probe.sequence
+                    )
+                }
+                Some(ProbeResult::SendFailed) => {
+                    "ping: sendmsg: Network is unreachable".to_string()
+                }
+                // Linux stays silent about requests that go unanswered
+                Some(ProbeResult::Timeout) | None => continue,
+            };
+            output.push_str(&line);
+            output.push('\n');
+        }
+        let elapsed = match (self.probes.first(), self.probes.last()) {
+            (Some(first), Some(last)) => last.sent_at - first.sent_at,
+            _ => Duration::ZERO,
+        };
+        output.push_str(&format!("\n--- {} ping statistics ---\n", self.destination));
+        output.push_str(&format!(
+            "{} packets transmitted, {} received, ",
+            self.sent(),
+            self.received()
+        ));
+        if self.errors() > 0 {
+            output.push_str(&format!("+{} errors, ", self.errors()));
+        }
+        output.push_str(&format!(
+            "{}% packet loss, time {}ms\n",
+            self.loss_percent() as u32,
+            elapsed.as_millis()
+        ));
+        if let Some(stats) = self.round_trip_stats() {
+            let ms = |rtt: Duration| rtt.as_secs_f64() * 1000.0;
+            output.push_str(&format!(
+                "rtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms\n",
+                ms(stats.min),
+                ms(stats.avg),
+                ms(stats.max),
+                ms(stats.mdev)
+            ));
+        }
+        output
+    }
+}
+
+// Echo request carrying `data_size` bytes of data, sent with the given TTL
+pub fn echo_request(
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    identifier: u16,
+    sequence: u16,
+    data_size: usize,
+    ttl: u8,
+) -> Ipv4Packet {
+    let data = (0..data_size).map(|i| i as u8).collect();
+    let message = IcmpMessage::echo_request(identifier, sequence, data);
+    let mut packet = Ipv4Packet::icmp(source, destination, &message);
+    packet.header.ttl = ttl;
+    packet.update_header();
+    packet
+}
+
+/// Sequence number and message of a packet answering one of the echo requests sent with
+/// `identifier`: an echo reply, or an ICMP error quoting the request
+pub fn echo_response(packet: &Ipv4Packet, identifier: u16) -> Option<(u16, IcmpMessage)> {
+    let message = packet.icmp_message()?;
+    let (id, sequence) = match &message {
+        IcmpMessage::EchoReply {
+            identifier,
+            sequence,
+            ..
+        } => (*identifier, *sequence),
+        IcmpMessage::DestinationUnreachable { .. } | IcmpMessage::TimeExceeded { .. } => {
+            message.quoted_echo()?
+        }
+        IcmpMessage::EchoRequest { .. } => return None,
+    };
+    (id == identifier).then_some((sequence, message))
+}
+
+// Whole milliseconds, rounded to the nearest
+pub(crate) fn millis(duration: Duration) -> u128 {
+    (duration.as_micros() + 500) / 1000
+}
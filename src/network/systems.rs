@@ -0,0 +1,474 @@
+use super::device::{Endpoint, Router, Switch};
+use super::ping::PingSession;
+use super::traceroute::TracerouteSession;
+use crate::layer2::address::MacAddress;
+use crate::layer2::arp::{ArpOperation, ArpPacket};
+use crate::layer2::interface::{Direction, EthernetInterface, Interface};
+use crate::layer2::pdu::{EthernetFrame, EthernetPayload};
+use crate::layer2::stp::{port_cost, PortState, SpanningTree};
+use crate::layer2::switch::SwitchPort;
+use crate::layer3::address::{IpAddr, Ipv4Addr, Ipv4Interface};
+use crate::layer3::icmp::{self, IcmpMessage, UnreachableCode};
+use crate::layer3::pdu::Ipv4Packet;
+use crate::layer3::routing::RoutingTable;
+use crate::simulation::entity::{EventScheduler, SimClock};
+use bevy::prelude::*;
+use std::time::Duration;
+
+/// Learning bridge: every frame received on a port is classified into a VLAN and teaches the
+/// switch where its source lives. It then goes out the port its destination was learned on,
+/// or is flooded to every other port in the same VLAN when unknown.
+/// Switches running spanning tree hand BPDUs to it and only use ports in the states that allow it.
+/// Ports that are down are skipped, and the addresses learned on them are forgotten.
+pub fn switch_frames(
+    clock: Res<SimClock>,
+    mut switches: Query<(&mut Switch, Option<&mut SpanningTree>)>,
+    ports: Query<&SwitchPort>,
+    mut interfaces: Query<&mut Interface>,
+) {
+    let now = clock.now();
+    for (mut switch, mut spanning_tree) in switches.iter_mut() {
+        switch.mac_table.age_out(now);
+        for port in switch.interfaces.clone() {
+            if let Ok(Interface::Ethernet(int)) = interfaces.get(port) {
+                if !int.is_up() {
+                    switch.mac_table.flush_port(port);
+                }
+            }
+        }
+
+        for ingress in switch.interfaces.clone() {
+            let Ok(ingress_port) = ports.get(ingress) else {
+                continue;
+            };
+            let state = spanning_tree
+                .as_ref()
+                .map_or(PortState::Forwarding, |stp| stp.port_state(ingress));
+            let mut frames: Vec<(u16, EthernetFrame)> = Vec::new();
+            if let Ok(mut interface) = interfaces.get_mut(ingress) {
+                if let Interface::Ethernet(int) = &mut *interface {
+                    while let Some(frame) = int.dequeue_frame(Direction::In) {
+                        // Store-and-forward: corrupted frames are never forwarded
+                        if !frame.has_valid_fcs() {
+                            int.stats.rx_crc_errors += 1;
+                            continue;
+                        }
+                        int.stats.rx_frames += 1;
+                        // BPDUs are consumed by the bridge and never forwarded
+                        if frame.dest == MacAddress::bridge_group() {
+                            if let (Some(stp), EthernetPayload::STP(bpdu)) =
+                                (spanning_tree.as_mut(), &frame.payload)
+                            {
+                                stp.receive_bpdu(ingress, bpdu, now);
+                            }
+                            continue;
+                        }
+                        if !state.learns() {
+                            continue;
+                        }
+                        if let Some(vlan) = ingress_port.ingress(&frame) {
+                            frames.push((vlan, frame));
+                        }
+                    }
+                }
+            }
+
+            for (vlan, frame) in frames {
+                if !frame.src.is_multicast() {
+                    switch.mac_table.learn(vlan, frame.src.clone(), ingress, now);
+                }
+                if !state.forwards() {
+                    continue;
+                }
+
+                let known_port = if frame.dest.is_multicast() {
+                    None
+                } else {
+                    switch.mac_table.lookup(vlan, &frame.dest, now)
+                };
+                let egress_ports: Vec<Entity> = match known_port {
+                    // The destination is on the segment the frame came from
+                    Some(port) if port == ingress => Vec::new(),
+                    Some(port) => vec![port],
+                    None => switch
+                        .interfaces
+                        .iter()
+                        .copied()
+                        .filter(|port| *port != ingress)
+                        .collect(),
+                };
+                let egress_ports = egress_ports.into_iter().filter(|port| {
+                    spanning_tree
+                        .as_ref()
+                        .is_none_or(|stp| stp.port_state(*port).forwards())
+                });
+
+                for port in egress_ports {
+                    let Some(egress_frame) = ports
+                        .get(port)
+                        .ok()
+                        .and_then(|egress_port| egress_port.egress(&frame, vlan))
+                    else {
+                        continue;
+                    };
+                    if let Ok(mut interface) = interfaces.get_mut(port) {
+                        if let Interface::Ethernet(int) = &mut *interface {
+                            if int.is_up() {
+                                int.enqueue_frame(egress_frame, Direction::Out);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs spanning tree on every switch that has a `SpanningTree`: adds new ports, disables the
+/// ones that are down, advances the protocol timers, flushes learned addresses after topology
+/// changes and sends BPDUs.
+pub fn run_spanning_tree(
+    clock: Res<SimClock>,
+    mut scheduler: ResMut<EventScheduler>,
+    mut bridges: Query<(&mut Switch, &mut SpanningTree)>,
+    mut interfaces: Query<&mut Interface>,
+) {
+    let now = clock.now();
+    for (mut switch, mut spanning_tree) in bridges.iter_mut() {
+        for port in &switch.interfaces {
+            if let Ok(Interface::Ethernet(int)) = interfaces.get(*port) {
+                spanning_tree.add_port(*port, port_cost(int.interface_type.bandwidth()));
+                spanning_tree.set_port_enabled(*port, int.is_up());
+            }
+        }
+
+        let bpdus = spanning_tree.update(now);
+        if spanning_tree.take_topology_change() {
+            switch.mac_table.clear_dynamic();
+        }
+        for (port, bpdu) in bpdus {
+            if let Ok(mut interface) = interfaces.get_mut(port) {
+                if let Interface::Ethernet(int) = &mut *interface {
+                    let frame = EthernetFrame::bpdu(int.mac_address.clone(), bpdu);
+                    int.enqueue_frame(frame, Direction::Out);
+                }
+            }
+        }
+
+        // Come back when the next protocol timer expires
+        if let Some(wakeup) = spanning_tree.next_wakeup(now) {
+            scheduler.schedule_wakeup(wakeup);
+        }
+    }
+}
+
+/// Routers take over the frames received on their interfaces. ARP is handled by the interface
+/// itself, IPv4 packets addressed to the router are answered locally and any other packet is
+/// forwarded along the longest matching route with its TTL decremented. Packets that cannot be
+/// forwarded are reported back to their source with ICMP.
+pub fn route_packets(
+    clock: Res<SimClock>,
+    mut routers: Query<(
+        &mut Router,
+        Option<&mut PingSession>,
+        Option<&mut TracerouteSession>,
+    )>,
+    mut interfaces: Query<&mut Interface>,
+) {
+    let now = clock.now();
+    for (mut router, mut ping, mut traceroute) in routers.iter_mut() {
+        // Connected routes follow the interface configuration and state. Routes out of an
+        // interface that is down are withdrawn.
+        let connected: Vec<(Entity, Ipv4Interface)> = router
+            .interfaces
+            .iter()
+            .flat_map(|entity| match interfaces.get(*entity) {
+                Ok(Interface::Ethernet(int)) if int.is_up() => int
+                    .ipv4_addresses()
+                    .map(|address| (*entity, *address))
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        router.routing_table.set_connected_routes(&connected);
+        let down: Vec<Entity> = router
+            .interfaces
+            .iter()
+            .copied()
+            .filter(|entity| {
+                !matches!(interfaces.get(*entity), Ok(Interface::Ethernet(int)) if int.is_up())
+            })
+            .collect();
+        router.routing_table.set_down_interfaces(&down);
+
+        let mut packets: Vec<(Entity, Ipv4Packet)> = Vec::new();
+        for ingress in router.interfaces.clone() {
+            let Ok(mut interface) = interfaces.get_mut(ingress) else {
+                continue;
+            };
+            let Interface::Ethernet(int) = &mut *interface else {
+                continue;
+            };
+            while let Some(frame) = int.dequeue_frame(Direction::In) {
+                if !frame.has_valid_fcs() {
+                    int.stats.rx_crc_errors += 1;
+                    continue;
+                }
+                int.stats.rx_frames += 1;
+                if !int.accepts_frame(&frame.dest) {
+                    continue;
+                }
+                match frame.payload {
+                    EthernetPayload::IPv4(packet) => packets.push((ingress, packet)),
+                    EthernetPayload::ARP(ref arp)
+                        if int.proxy_arp
+                            && is_proxied(&router.routing_table, int, ingress, arp) =>
+                    {
+                        println!(
+                            "Proxy ARP for {} on behalf of {}",
+                            arp.target_ip, arp.sender_ip
+                        );
+                        let reply = frame.arp_reply(arp, int.mac_address.clone());
+                        int.enqueue_frame(reply, Direction::Out);
+                        int.process_frame(&frame, now);
+                    }
+                    _ => int.process_frame(&frame, now),
+                }
+            }
+        }
+
+        for (ingress, mut packet) in packets {
+            let dest = packet.header.dest;
+            // ICMP errors come from the primary address of the interface the packet arrived on
+            let Some(ingress_address) = connected
+                .iter()
+                .find(|(entity, _)| *entity == ingress)
+                .map(|(_, address)| address.address)
+            else {
+                continue;
+            };
+
+            if dest.is_broadcast() || connected.iter().any(|(_, address)| address.address == dest) {
+                println!("Router received IPv4 packet: {}", packet);
+                if let Some(ping) = ping.as_mut() {
+                    ping.receive(&packet, now);
+                }
+                if let Some(traceroute) = traceroute.as_mut() {
+                    traceroute.receive(&packet, now);
+                }
+                let local = if dest.is_broadcast() { ingress_address } else { dest };
+                if let Some(reply) = icmp::reply_to(&packet, local) {
+                    send_from_router(&router.routing_table, &mut interfaces, reply, now);
+                }
+                continue;
+            }
+
+            let error = if packet.header.ttl <= 1 {
+                println!("Dropping packet to {}: TTL expired in transit", dest);
+                IcmpMessage::time_exceeded(&packet)
+            } else if let Some((egress, next_hop)) = router.routing_table.resolve(&dest) {
+                let Ok(mut interface) = interfaces.get_mut(egress) else {
+                    continue;
+                };
+                let Interface::Ethernet(int) = &mut *interface else {
+                    continue;
+                };
+                // Fragmentation is not modeled: oversized packets that may be fragmented
+                // are sent as they are
+                if packet.header.total_length > int.mtu && packet.dont_fragment() {
+                    println!("Dropping packet to {}: fragmentation needed", dest);
+                    IcmpMessage::fragmentation_needed(&packet, int.mtu)
+                } else {
+                    packet.header.ttl -= 1;
+                    packet.update_header();
+                    int.send_ipv4_packet(packet, next_hop, now);
+                    continue;
+                }
+            } else {
+                println!("Dropping packet to {}: no route to host", dest);
+                IcmpMessage::destination_unreachable(UnreachableCode::Network, &packet)
+            };
+            if let Some(error) = icmp::error_for(&packet, ingress_address, error) {
+                send_from_router(&router.routing_table, &mut interfaces, error, now);
+            }
+        }
+    }
+}
+
+// Whether a router answers an ARP request received on `ingress` with proxy ARP: the target
+// lies outside the attached network and the router has a route to it through another interface
+fn is_proxied(
+    routing_table: &RoutingTable,
+    int: &EthernetInterface,
+    ingress: Entity,
+    arp: &ArpPacket,
+) -> bool {
+    if !matches!(arp.operation, ArpOperation::Request) || arp.sender_ip == arp.target_ip {
+        return false;
+    }
+    if int.ipv4_address.is_none() || int.is_on_link(&arp.target_ip) {
+        return false;
+    }
+    routing_table
+        .resolve(&arp.target_ip)
+        .is_some_and(|(egress, _)| egress != ingress)
+}
+
+// Sends a packet originated by the router itself along its routing table
+fn send_from_router(
+    routing_table: &RoutingTable,
+    interfaces: &mut Query<&mut Interface>,
+    packet: Ipv4Packet,
+    now: Duration,
+) {
+    let Some((egress, next_hop)) = routing_table.resolve(&packet.header.dest) else {
+        return;
+    };
+    if let Ok(mut interface) = interfaces.get_mut(egress) {
+        if let Interface::Ethernet(int) = &mut *interface {
+            int.send_ipv4_packet(packet, next_hop, now);
+        }
+    }
+}
+
+/// Delivers the frames received by endpoints to their interfaces, letting a running ping or
+/// traceroute see the ICMP messages addressed to the endpoint first
+pub fn host_packets(
+    clock: Res<SimClock>,
+    mut endpoints: Query<(
+        &Endpoint,
+        Option<&mut PingSession>,
+        Option<&mut TracerouteSession>,
+    )>,
+    mut interfaces: Query<&mut Interface>,
+) {
+    let now = clock.now();
+    for (endpoint, mut ping, mut traceroute) in endpoints.iter_mut() {
+        for entity in &endpoint.interfaces {
+            let Ok(mut interface) = interfaces.get_mut(*entity) else {
+                continue;
+            };
+            let Interface::Ethernet(int) = &mut *interface else {
+                continue;
+            };
+            while let Some(frame) = int.dequeue_frame(Direction::In) {
+                if !frame.has_valid_fcs() {
+                    int.stats.rx_crc_errors += 1;
+                    continue;
+                }
+                int.stats.rx_frames += 1;
+                if !int.accepts_frame(&frame.dest) {
+                    continue;
+                }
+                if let EthernetPayload::IPv4(packet) = &frame.payload {
+                    if int.has_ipv4_address(&packet.header.dest) {
+                        if let Some(ping) = ping.as_mut() {
+                            ping.receive(packet, now);
+                        }
+                        if let Some(traceroute) = traceroute.as_mut() {
+                            traceroute.receive(packet, now);
+                        }
+                    }
+                }
+                int.process_frame(&frame, now);
+            }
+        }
+    }
+}
+
+/// Sends the echo requests of running ping sessions when they are due and times out the ones
+/// left unanswered
+pub fn run_ping_sessions(
+    clock: Res<SimClock>,
+    mut scheduler: ResMut<EventScheduler>,
+    mut sessions: Query<(&mut PingSession, Option<&Router>, Option<&Endpoint>)>,
+    mut interfaces: Query<&mut Interface>,
+) {
+    let now = clock.now();
+    for (mut session, router, endpoint) in sessions.iter_mut() {
+        while let Some(sequence) = session.poll(now) {
+            let destination = session.destination.clone();
+            let build = |source| session.echo_request(source, sequence);
+            let sent = send_probe(router, endpoint, &destination, &mut interfaces, build, now);
+            if !sent {
+                session.fail(sequence, now);
+            }
+        }
+        if let Some(wakeup) = session.next_wakeup() {
+            scheduler.schedule_wakeup(wakeup);
+        }
+    }
+}
+
+/// Sends the TTL-limited probes of running traceroutes one after the other, each once the
+/// previous one is answered or has timed out
+pub fn run_traceroute_sessions(
+    clock: Res<SimClock>,
+    mut scheduler: ResMut<EventScheduler>,
+    mut sessions: Query<(&mut TracerouteSession, Option<&Router>, Option<&Endpoint>)>,
+    mut interfaces: Query<&mut Interface>,
+) {
+    let now = clock.now();
+    for (mut session, router, endpoint) in sessions.iter_mut() {
+        while let Some((sequence, ttl)) = session.poll(now) {
+            let destination = session.destination.clone();
+            let build = |source| session.echo_request(source, sequence, ttl);
+            let sent = send_probe(router, endpoint, &destination, &mut interfaces, build, now);
+            if !sent {
+                session.fail(sequence, now);
+            }
+        }
+        if let Some(wakeup) = session.next_wakeup() {
+            scheduler.schedule_wakeup(wakeup);
+        }
+    }
+}
+
+// Sends the probe `build` makes from the address it leaves the device from: routers send along
+// their routing table, endpoints out of the first interface that can reach the destination.
+// Returns false if the device has no way to send it. A probe to a next hop missing from the
+// ARP cache waits in the ARP queue, and only times out if resolution fails.
+fn send_probe<F: FnOnce(Ipv4Addr) -> Option<Ipv4Packet>>(
+    router: Option<&Router>,
+    endpoint: Option<&Endpoint>,
+    destination: &IpAddr,
+    interfaces: &mut Query<&mut Interface>,
+    build: F,
+    now: Duration,
+) -> bool {
+    let IpAddr::V4(destination) = destination else {
+        return false;
+    };
+    let egress = match (router, endpoint) {
+        (Some(router), _) => router.routing_table.resolve(destination),
+        (None, Some(endpoint)) => endpoint.interfaces.iter().find_map(|entity| {
+            let Ok(Interface::Ethernet(int)) = interfaces.get(*entity) else {
+                return None;
+            };
+            int.ipv4_address?;
+            let reachable = destination.is_broadcast()
+                || int.is_on_link(destination)
+                || int.default_gateway.is_some();
+            reachable.then_some((*entity, *destination))
+        }),
+        _ => None,
+    };
+    let Some((egress, next_hop)) = egress else {
+        return false;
+    };
+    let Ok(mut interface) = interfaces.get_mut(egress) else {
+        return false;
+    };
+    let Interface::Ethernet(int) = &mut *interface else {
+        return false;
+    };
+    let Some(packet) = int.source_address(destination).and_then(build) else {
+        return false;
+    };
+    if router.is_some() {
+        int.send_ipv4_packet(packet, next_hop, now);
+    } else {
+        int.originate_ipv4_packet(packet, now);
+    }
+    true
+}
@@ -0,0 +1,360 @@
+use super::ping::{echo_request, echo_response, millis, PingStyle, Probe, ProbeResult};
+use crate::layer3::address::{IpAddr, Ipv4Addr};
+use crate::layer3::icmp::UnreachableCode;
+use crate::layer3::pdu::Ipv4Packet;
+use bevy::prelude::*;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracerouteOptions {
+    pub probes_per_hop: u8,
+    pub max_hops: u8,
+    pub timeout: Duration,
+    // Bytes of ICMP data carried by every probe
+    pub data_size: usize,
+}
+
+impl TracerouteOptions {
+    // Defaults of "traceroute" on IOS and Linux and of "tracert" on Windows
+    pub fn defaults(style: PingStyle) -> Self {
+        let timeout = match style {
+            PingStyle::Cisco => Duration::from_secs(3),
+            PingStyle::Windows => Duration::from_secs(4),
+            PingStyle::Linux => Duration::from_secs(5),
+        };
+        Self {
+            probes_per_hop: 3,
+            max_hops: 30,
+            timeout,
+            data_size: 32,
+        }
+    }
+}
+
+/// Probes sent with the same TTL, answered by the device that many hops away
+#[derive(Debug, Clone)]
+pub struct Hop {
+    pub ttl: u8,
+    pub probes: Vec<Probe>,
+}
+
+impl Hop {
+    // Addresses that answered, in order and without repetitions
+    pub fn responders(&self) -> Vec<Ipv4Addr> {
+        let mut responders: Vec<Ipv4Addr> = Vec::new();
+        for from in self
+            .probes
+            .iter()
+            .filter_map(|probe| probe.result.as_ref()?.from())
+        {
+            if !responders.contains(&from) {
+                responders.push(from);
+            }
+        }
+        responders
+    }
+
+    pub fn round_trip_times(&self) -> Vec<Option<Duration>> {
+        self.probes
+            .iter()
+            .map(|probe| probe.result.as_ref().and_then(|result| result.rtt()))
+            .collect()
+    }
+
+    // Whether the trace stops here: the destination answered, it is unreachable, or the
+    // probes could not be sent
+    pub fn is_last(&self) -> bool {
+        self.probes.iter().any(|probe| {
+            matches!(
+                probe.result,
+                Some(
+                    ProbeResult::Reply { .. }
+                        | ProbeResult::Unreachable { .. }
+                        | ProbeResult::SendFailed
+                )
+            )
+        })
+    }
+}
+
+/// A traceroute running on the device entity it is inserted on. Echo requests go out with a
+/// TTL of 1, 2, 3... and the routers where they expire answer with ICMP time exceeded, which
+/// reveals the path hop by hop until the destination replies.
+/// Probes are sent one at a time, each after the previous one is answered or times out.
+#[derive(Component, Debug, Clone)]
+pub struct TracerouteSession {
+    pub destination: IpAddr,
+    pub style: PingStyle,
+    pub options: TracerouteOptions,
+    // Echo identifier telling the answers to this session apart from others
+    pub identifier: u16,
+    pub hops: Vec<Hop>,
+    next_sequence: u16,
+    scheduled_wakeup: Option<Duration>,
+}
+
+impl TracerouteSession {
+    pub fn new(destination: IpAddr, style: PingStyle) -> Self {
+        Self::with_options(destination, style, TracerouteOptions::defaults(style))
+    }
+
+    pub fn with_options(destination: IpAddr, style: PingStyle, options: TracerouteOptions) -> Self {
+        Self {
+            destination,
+            style,
+            options,
+            identifier: rand::random(),
+            hops: Vec::new(),
+            next_sequence: 1,
+            scheduled_wakeup: None,
+        }
+    }
+
+    // Whether the trace is over and every probe has a result
+    pub fn is_complete(&self) -> bool {
+        self.probes().all(|probe| probe.result.is_some()) && self.next_ttl().is_none()
+    }
+
+    // Whether the destination itself answered
+    pub fn reached_destination(&self) -> bool {
+        self.hops.last().is_some_and(|hop| {
+            hop.probes
+                .iter()
+                .any(|probe| matches!(probe.result, Some(ProbeResult::Reply { .. })))
+        })
+    }
+
+    /// Times out the probe left unanswered for too long and returns the sequence number and
+    /// TTL of the next probe if it is due. The caller sends it, or reports it with `fail`.
+    pub fn poll(&mut self, now: Duration) -> Option<(u16, u8)> {
+        let timeout = self.options.timeout;
+        for hop in self.hops.iter_mut() {
+            for probe in hop.probes.iter_mut() {
+                probe.expire(timeout, now);
+            }
+        }
+        if self.probes().any(|probe| probe.result.is_none()) {
+            return None;
+        }
+        let ttl = self.next_ttl()?;
+        if self.hops.last().is_none_or(|hop| hop.ttl != ttl) {
+            self.hops.push(Hop {
+                ttl,
+                probes: Vec::new(),
+            });
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.hops.last_mut()?.probes.push(Probe::new(sequence, now));
+        Some((sequence, ttl))
+    }
+
+    // TTL of the next probe, or None once the trace is over
+    fn next_ttl(&self) -> Option<u8> {
+        let Some(hop) = self.hops.last() else {
+            return Some(1);
+        };
+        if hop.probes.len() < usize::from(self.options.probes_per_hop) {
+            return Some(hop.ttl);
+        }
+        if hop.is_last() || hop.ttl >= self.options.max_hops {
+            return None;
+        }
+        Some(hop.ttl + 1)
+    }
+
+    // Records that the probe `sequence` could not be sent at all
+    pub fn fail(&mut self, sequence: u16, now: Duration) {
+        if let Some(probe) = self.probe_mut(sequence) {
+            probe.fail(now);
+        }
+    }
+
+    // Probe `sequence` from `source`, or None if the destination is not IPv4
+    pub fn echo_request(&self, source: Ipv4Addr, sequence: u16, ttl: u8) -> Option<Ipv4Packet> {
+        let IpAddr::V4(destination) = self.destination else {
+            return None;
+        };
+        Some(echo_request(
+            source,
+            destination,
+            self.identifier,
+            sequence,
+            self.options.data_size,
+            ttl,
+        ))
+    }
+
+    /// Matches a packet delivered to the device against the outstanding probe: a time
+    /// exceeded or unreachable message quoting it, or the reply of the destination.
+    /// Returns whether it was ours.
+    pub fn receive(&mut self, packet: &Ipv4Packet, now: Duration) -> bool {
+        let Some((sequence, message)) = echo_response(packet, self.identifier) else {
+            return false;
+        };
+        self.probe_mut(sequence)
+            .is_some_and(|probe| probe.resolve(packet, message, now))
+    }
+
+    // Next time the session needs to run, if not already scheduled
+    pub fn next_wakeup(&mut self) -> Option<Duration> {
+        let timeout = self.options.timeout;
+        let next = self
+            .probes()
+            .filter(|probe| probe.result.is_none())
+            .map(|probe| probe.sent_at + timeout)
+            .min()?;
+        if self.scheduled_wakeup == Some(next) {
+            return None;
+        }
+        self.scheduled_wakeup = Some(next);
+        Some(next)
+    }
+
+    fn probes(&self) -> impl Iterator<Item = &Probe> {
+        self.hops.iter().flat_map(|hop| hop.probes.iter())
+    }
+
+    fn probe_mut(&mut self, sequence: u16) -> Option<&mut Probe> {
+        self.hops
+            .iter_mut()
+            .flat_map(|hop| hop.probes.iter_mut())
+            .find(|probe| probe.sequence == sequence)
+    }
+
+    /// Formats the session like the output of the traceroute command of its style
+    pub fn report(&self) -> String {
+        match self.style {
+            PingStyle::Cisco => self.cisco_report(),
+            PingStyle::Windows => self.windows_report(),
+            PingStyle::Linux => self.linux_report(),
+        }
+    }
+
+    fn cisco_report(&self) -> String {
+        let mut output = String::new();
+        output.push_str("Type escape sequence to abort.\n");
+        output.push_str(&format!("Tracing the route to {}\n\n", self.destination));
+        for hop in &self.hops {
+            let mut line = format!("{:>3}", hop.ttl);
+            let mut last_from = None;
+            for probe in &hop.probes {
+                let Some((result, from, rtt)) = answer(probe) else {
+                    line.push_str(" *");
+                    continue;
+                };
+                if last_from != Some(from) {
+                    line.push_str(&format!(" {}", from));
+                    last_from = Some(from);
+                }
+                line.push_str(&format!(" {} msec", millis(rtt)));
+                if let Some(flag) = unreachable_flag(result) {
+                    line.push_str(&format!(" {}", flag));
+                }
+            }
+            output.push_str(&line);
+            output.push('\n');
+        }
+        output
+    }
+
+    fn windows_report(&self) -> String {
+        let mut output = String::new();
+        output.push_str(&format!(
+            "\nTracing route to {} over a maximum of {} hops\n\n",
+            self.destination, self.options.max_hops
+        ));
+        for hop in &self.hops {
+            let mut line = format!("{:>3}", hop.ttl);
+            for rtt in hop.round_trip_times() {
+                match rtt {
+                    Some(rtt) if rtt < Duration::from_millis(1) => {
+                        line.push_str(&format!("  {:>5} ms", "<1"))
+                    }
+                    Some(rtt) => line.push_str(&format!("  {:>5} ms", millis(rtt))),
+                    None => line.push_str(&format!("  {:>5}   ", "*")),
+                }
+            }
+            let last = hop.probes.last().and_then(|probe| probe.result.as_ref());
+            let host = match (hop.responders().first(), last) {
+                (Some(from), Some(ProbeResult::Unreachable { code, .. })) => {
+                    let reason = match code {
+                        UnreachableCode::Network => "Destination net unreachable.",
+                        UnreachableCode::Host => "Destination host unreachable.",
+                        UnreachableCode::Protocol => "Destination protocol unreachable.",
+                        UnreachableCode::Port => "Destination port unreachable.",
+                        UnreachableCode::FragmentationNeeded => {
+                            "Packet needs to be fragmented but DF set."
+                        }
+                        UnreachableCode::AdministrativelyProhibited => {
+                            "Communication administratively prohibited."
+                        }
+                    };
+                    format!("{} reports: {}", from, reason)
+                }
+                (Some(from), _) => from.to_string(),
+                (None, Some(ProbeResult::SendFailed)) => "General failure.".to_string(),
+                (None, _) => "Request timed out.".to_string(),
+            };
+            output.push_str(&format!("{}  {}\n", line, host));
+        }
+        output.push_str("\nTrace complete.\n");
+        output
+    }
+
+    fn linux_report(&self) -> String {
+        let mut output = String::new();
+        output.push_str(&format!(
+            "traceroute to {} ({}), {} hops max, {} byte packets\n",
+            self.destination,
+            self.destination,
+            self.options.max_hops,
+            self.options.data_size + 28
+        ));
+        for hop in &self.hops {
+            let mut line = format!("{:>2} ", hop.ttl);
+            let mut last_from = None;
+            for probe in &hop.probes {
+                let Some((result, from, rtt)) = answer(probe) else {
+                    line.push_str(" *");
+                    continue;
+                };
+                if last_from != Some(from) {
+                    line.push_str(&format!(" {} ({})", from, from));
+                    last_from = Some(from);
+                }
+                line.push_str(&format!("  {:.3} ms", rtt.as_secs_f64() * 1000.0));
+                if let Some(flag) = unreachable_flag(result) {
+                    line.push_str(&format!(" {}", flag));
+                }
+            }
+            output.push_str(&line);
+            output.push('\n');
+        }
+        output
+    }
+}
+
+// The result of a probe that was answered, with who answered and how fast
+fn answer(probe: &Probe) -> Option<(&ProbeResult, Ipv4Addr, Duration)> {
+    let result = probe.result.as_ref()?;
+    Some((result, result.from()?, result.rtt()?))
+}
+
+// Annotation of unreachable answers shared by IOS and Linux. The probes are echo requests,
+// so the destination ends the trace with an echo reply rather than the port unreachable
+// that answers the UDP probes of the real tools. A port unreachable is left unannotated,
+// as those tools do.
+fn unreachable_flag(result: &ProbeResult) -> Option<&'static str> {
+    match result {
+        ProbeResult::Unreachable { code, .. } => match code {
+            UnreachableCode::Network => Some("!N"),
+            UnreachableCode::Host => Some("!H"),
+            UnreachableCode::Protocol => Some("!P"),
+            UnreachableCode::Port => None,
+            UnreachableCode::FragmentationNeeded => Some("!F"),
+            UnreachableCode::AdministrativelyProhibited => Some("!X"),
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,128 @@
+use crate::layer2::pdu::EthernetFrame;
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+/// Simulation time, measured from the start of the run.
+/// The clock only moves when the scheduler jumps to the next event.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct SimClock {
+    now: Duration,
+    horizon: Option<Duration>,
+}
+
+impl SimClock {
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Moves the clock forward. The clock never moves backwards.
+    pub fn advance_to(&mut self, time: Duration) {
+        self.now = self.now.max(time);
+    }
+
+    /// Events scheduled after the horizon are held back until it is cleared or moved
+    pub fn horizon(&self) -> Option<Duration> {
+        self.horizon
+    }
+
+    pub fn set_horizon(&mut self, horizon: Option<Duration>) {
+        self.horizon = horizon;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SimEvent {
+    // A frame has been fully received by an interface
+    FrameArrival {
+        interface: Entity,
+        frame: EthernetFrame,
+    },
+    // Nothing to deliver, but systems with timers need to run at this instant
+    Wakeup,
+}
+
+#[derive(Debug)]
+struct ScheduledEvent {
+    time: Duration,
+    seq: u64,
+    event: SimEvent,
+}
+
+impl PartialEq for ScheduledEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.time == other.time && self.seq == other.seq
+    }
+}
+
+impl Eq for ScheduledEvent {}
+
+impl PartialOrd for ScheduledEvent {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScheduledEvent {
+    // Reversed so that the BinaryHeap pops the earliest event first.
+    // Events scheduled for the same instant keep their insertion order.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .time
+            .cmp(&self.time)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Priority queue of pending simulation events ordered by timestamp
+#[derive(Resource, Debug, Default)]
+pub struct EventScheduler {
+    queue: BinaryHeap<ScheduledEvent>,
+    next_seq: u64,
+}
+
+impl EventScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn schedule(&mut self, time: Duration, event: SimEvent) {
+        self.queue.push(ScheduledEvent {
+            time,
+            seq: self.next_seq,
+            event,
+        });
+        self.next_seq += 1;
+    }
+
+    pub fn schedule_frame_arrival(&mut self, time: Duration, interface: Entity, frame: EthernetFrame) {
+        self.schedule(time, SimEvent::FrameArrival { interface, frame });
+    }
+
+    pub fn schedule_wakeup(&mut self, time: Duration) {
+        self.schedule(time, SimEvent::Wakeup);
+    }
+
+    // Returns the timestamp of the earliest pending event
+    pub fn next_event_time(&self) -> Option<Duration> {
+        self.queue.peek().map(|scheduled| scheduled.time)
+    }
+
+    // Removes the earliest event if it is due at or before `time`
+    pub fn pop_due(&mut self, time: Duration) -> Option<SimEvent> {
+        if self.next_event_time()? <= time {
+            self.queue.pop().map(|scheduled| scheduled.event)
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
@@ -1,2 +1,42 @@
+use crate::layer1::Layer1Plugin;
+use crate::layer2::{systems::peek_queues, Layer2Plugin};
+use crate::network::NetworkPlugin;
+use bevy::app::{PluginGroupBuilder, ScheduleRunnerPlugin};
+use bevy::prelude::*;
+use entity::{EventScheduler, SimClock};
+use std::time::Duration;
+use system::dispatch_events;
+
 pub mod entity;
+pub mod runner;
 pub mod system;
+
+/// Owns the simulation clock and the event scheduler. Every `FixedUpdate` tick
+/// first jumps to the next scheduled instant, then lets the layers react to it.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimClock>()
+            .init_resource::<EventScheduler>()
+            .add_systems(FixedUpdate, dispatch_events.before(peek_queues));
+    }
+}
+
+/// Headless plugin group that runs the network simulation without a window or renderer.
+/// It is built on top of `MinimalPlugins`, so it can be used on CI boxes and in tests.
+pub struct NetSimPlugin;
+
+impl PluginGroup for NetSimPlugin {
+    fn build(self) -> PluginGroupBuilder {
+        MinimalPlugins
+            .build()
+            .set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / 60.0,
+            )))
+            .add(SimulationPlugin)
+            .add(Layer1Plugin)
+            .add(Layer2Plugin)
+            .add(NetworkPlugin)
+    }
+}
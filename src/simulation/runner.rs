@@ -0,0 +1,180 @@
+use super::entity::{EventScheduler, SimClock};
+use super::NetSimPlugin;
+use crate::layer3::address::IpAddr;
+use crate::network::device::{Endpoint, NetworkDevice, Router};
+use crate::network::ping::PingSession;
+use crate::network::traceroute::TracerouteSession;
+use bevy::app::FixedMain;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use std::time::Duration;
+
+/// Drives a headless NetSim app from code, one `FixedUpdate` tick at a time.
+///
+/// Wall-clock time never advances the simulation: fixed ticks only happen when
+/// `step` is called, so the timing of a run does not depend on how fast it executes.
+/// Runs are not reproducible, though: MAC addresses, link loss, jitter and bit errors,
+/// CSMA/CD backoff, WRED drops and ping identifiers all come from an unseeded RNG.
+pub struct Simulation {
+    app: App,
+    started: bool,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        let mut app = App::new();
+        app.add_plugins(NetSimPlugin)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+        Self {
+            app,
+            started: false,
+        }
+    }
+
+    pub fn app(&self) -> &App {
+        &self.app
+    }
+
+    pub fn app_mut(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    pub fn world(&self) -> &World {
+        &self.app.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.app.world
+    }
+
+    /// Finishes building the app and runs the `Startup` schedules.
+    /// Called automatically by `step`, so it only needs to be invoked directly
+    /// to inspect the topology before the first tick.
+    pub fn start(&mut self) {
+        if self.started {
+            return;
+        }
+        self.app.finish();
+        self.app.cleanup();
+        // With a zero manual duration this runs the startup schedules without
+        // accumulating any fixed time.
+        self.app.update();
+        self.started = true;
+    }
+
+    /// Current simulation time
+    pub fn now(&self) -> Duration {
+        self.app.world.resource::<SimClock>().now()
+    }
+
+    /// Advances the simulation by `ticks` runs of the `FixedUpdate` schedule.
+    /// Each tick jumps the clock to the next scheduled event.
+    pub fn step(&mut self, ticks: u32) {
+        self.start();
+        let world = &mut self.app.world;
+        for _ in 0..ticks {
+            let timestep = world.resource::<Time<Fixed>>().timestep();
+            world.resource_mut::<Time<Fixed>>().advance_by(timestep);
+            *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+            world.run_schedule(FixedMain);
+        }
+        *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+    }
+
+    /// Processes every event scheduled up to and including `time`, then sets the clock to `time`
+    pub fn run_until(&mut self, time: Duration) {
+        self.start();
+        self.app
+            .world
+            .resource_mut::<SimClock>()
+            .set_horizon(Some(time));
+        loop {
+            // Always run at least one tick so frames queued from code get transmitted
+            self.step(1);
+            match self.app.world.resource::<EventScheduler>().next_event_time() {
+                Some(next) if next <= time => continue,
+                _ => break,
+            }
+        }
+        let mut clock = self.app.world.resource_mut::<SimClock>();
+        clock.set_horizon(None);
+        clock.advance_to(time);
+    }
+
+    /// Runs the simulation for `duration` of simulation time
+    pub fn run_for(&mut self, duration: Duration) {
+        let until = self.now() + duration;
+        self.run_until(until);
+    }
+
+    /// Pings `ip` from `device` with the defaults of the device and runs the simulation until
+    /// every request has a result. Returns None if the entity is not a router or an endpoint;
+    /// switches have no IP address to send from.
+    pub fn ping(&mut self, device: Entity, ip: IpAddr) -> Option<PingSession> {
+        let session = self.with_device(device, |network_device| network_device.ping(ip))?;
+        self.run_ping(device, session)
+    }
+
+    /// Runs `session` on `device` until every request has a result and returns it
+    pub fn run_ping(&mut self, device: Entity, session: PingSession) -> Option<PingSession> {
+        self.run_session(device, session, PingSession::is_complete)
+    }
+
+    /// Traces the route from `device` to `ip` with the defaults of the device and runs the
+    /// simulation until the trace is over. Returns None if the entity is not a router or an
+    /// endpoint.
+    pub fn traceroute(&mut self, device: Entity, ip: IpAddr) -> Option<TracerouteSession> {
+        let session = self.with_device(device, |network_device| network_device.traceroute(ip))?;
+        self.run_traceroute(device, session)
+    }
+
+    /// Runs `session` on `device` until the trace is over and returns it
+    pub fn run_traceroute(
+        &mut self,
+        device: Entity,
+        session: TracerouteSession,
+    ) -> Option<TracerouteSession> {
+        self.run_session(device, session, TracerouteSession::is_complete)
+    }
+
+    fn with_device<T, F: FnOnce(&dyn NetworkDevice) -> T>(&self, device: Entity, f: F) -> Option<T> {
+        let entity = self.app.world.get_entity(device)?;
+        if let Some(router) = entity.get::<Router>() {
+            Some(f(router))
+        } else {
+            entity.get::<Endpoint>().map(|endpoint| f(endpoint))
+        }
+    }
+
+    // Inserts a diagnostic session on `device` and advances event by event until it is done
+    fn run_session<S: Component>(
+        &mut self,
+        device: Entity,
+        session: S,
+        is_complete: fn(&S) -> bool,
+    ) -> Option<S> {
+        self.app.world.get_entity_mut(device)?.insert(session);
+        loop {
+            self.step(1);
+            let done = self
+                .app
+                .world
+                .get::<S>(device)
+                .is_none_or(is_complete);
+            if done {
+                break;
+            }
+            match self.app.world.resource::<EventScheduler>().next_event_time() {
+                Some(next) => self.run_until(next),
+                None => break,
+            }
+        }
+        self.app.world.get_entity_mut(device)?.take::<S>()
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,42 @@
+use super::entity::{EventScheduler, SimClock, SimEvent};
+use crate::layer1::capture::Capture;
+use crate::layer2::interface::{Direction, Interface};
+use bevy::prelude::*;
+
+/// Jumps the clock straight to the next scheduled instant and delivers every event due then.
+/// When nothing is scheduled (or the next event lies beyond the horizon) the clock stays put,
+/// so the rest of the tick just processes whatever is already queued.
+pub fn dispatch_events(
+    mut clock: ResMut<SimClock>,
+    mut scheduler: ResMut<EventScheduler>,
+    mut interfaces: Query<&mut Interface>,
+    mut captures: Query<&mut Capture>,
+) {
+    let Some(next) = scheduler.next_event_time() else {
+        return;
+    };
+    if clock.horizon().is_some_and(|horizon| next > horizon) {
+        return;
+    }
+    clock.advance_to(next);
+
+    while let Some(event) = scheduler.pop_due(next) {
+        match event {
+            SimEvent::FrameArrival { interface, frame } => match interfaces.get_mut(interface) {
+                Ok(mut int) => {
+                    // Frames reaching an interface whose link went down are lost
+                    if let Interface::Ethernet(eth) = &mut *int {
+                        if eth.is_up() {
+                            if let Ok(mut capture) = captures.get_mut(interface) {
+                                capture.record(next, Some(Direction::In), &frame);
+                            }
+                            eth.enqueue_frame(frame, Direction::In);
+                        }
+                    }
+                }
+                Err(_) => println!("Destination interface not found."),
+            },
+            SimEvent::Wakeup => {}
+        }
+    }
+}